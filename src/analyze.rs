@@ -1,6 +1,10 @@
 use std::collections::HashMap;
 
 use crate::ast::*;
+use crate::explain::*;
+use crate::inline::*;
+use crate::optimize::range_form;
+use crate::path::*;
 use crate::source::*;
 
 /// A Scope holds all the contextual information for a single block of code.
@@ -9,8 +13,11 @@ pub struct Scope {
     /// The symbol table containing all variables declared in *this specific scope*.
     pub symbol_table: HashMap<String, Symbol>,
 
-    /// What kind of scope is this? (Global, a conditional branch, a function, etc.)
+    /// Index of the spending path, and of the input stack bound to it.
     pub branch: usize,
+
+    /// How the path is called in diagnostics, by its name if declared with one.
+    pub label: String,
 }
 
 // Symbol to build symbol table from stack
@@ -26,6 +33,18 @@ pub struct Symbol {
     /// The initial depth on the stack (0 = top).
     /// Used to verify consumption order.
     pub stack_position: usize,
+
+    /// Whether witness pubkey is bound by hash commitment, e.g. hash160(pk) == "...".
+    pub committed: bool,
+}
+
+// Witness pubkey is used twice: by hash commitment and by checksig.
+// Every other variable is used exactly once.
+pub fn use_limit(ty: &Type) -> usize {
+    match ty {
+        Type::PublicKey => 2,
+        _ => 1,
+    }
 }
 
 // Check the duplication here.
@@ -44,6 +63,7 @@ pub fn build_symbol_table(
             has_sig = true;
         }
 
+        check_byte_size(&item)?;
         if symbol_table.get(&stack_item.identifier.0).is_some() {
             return Err(CompileError {
                 loc: item.loc,
@@ -59,6 +79,7 @@ pub fn build_symbol_table(
                 ty: item.ty,
                 consume_count: 0,
                 stack_position: stack_vec.len() - 1 - i, // 0 is the top stack position.
+                committed: false,
             },
         );
     }
@@ -75,109 +96,372 @@ pub fn build_symbol_table(
     }
 }
 
-pub fn analyze(
+// Analyze with the names of paths, as declared by `path name(...) { ... }`.
+pub fn analyze_paths(
     ast: &Vec<Statement>,
     input: Vec<Vec<StackParam>>,
+    names: &[String],
     target: &Target,
-) -> Result<(), CompileError> {
-    let mut scope_vec: Vec<Scope> = vec![];
-    for (branch, stack) in input.iter().enumerate() {
-        scope_vec.push(Scope {
-            symbol_table: build_symbol_table(&stack)?,
-            branch: branch,
-        });
-    }
+) -> Result<Vec<PathReport>, CompileError> {
+    check_witness_declaration(&input)?;
 
-    analyze_statement(ast, &mut scope_vec, target, 0)?;
+    // Missing else is reported at the if statement, before it shows up as a path without stack.
     check_flow(ast)?;
+    let paths = enumerate_paths(ast);
+    check_path_count(&paths, &input)?;
+
+    // Each input stack is bound to the path of the same index.
+    let mut scope_vec: Vec<Scope> = vec![];
+    for (branch, (path, stack)) in paths.iter().zip(&input).enumerate() {
+        let mut scope = Scope {
+            symbol_table: build_symbol_table(stack)?,
+            branch,
+            label: path_label(names, branch),
+        };
+        analyze_path(path, &mut scope, target)?;
+        scope_vec.push(scope);
+    }
+    for path in &paths {
+        check_locktime_path(path)?;
+    }
 
     // Check unused variable at last.
-    for (i, stack) in input.iter().enumerate() {
-        check_unused_variable(&stack, &scope_vec[i].symbol_table)?;
+    for (scope, stack) in scope_vec.iter().zip(&input) {
+        check_consumed_stack(stack, &scope.symbol_table)
+            .and_then(|_| check_unused_variable(stack, &scope.symbol_table))
+            .map_err(|e| bind_error(e, &scope.label))?;
+    }
+
+    Ok(paths
+        .iter()
+        .zip(&input)
+        .enumerate()
+        .map(|(stack, (path, params))| PathReport {
+            stack,
+            name: names.get(stack).cloned(),
+            branches: path.branches(),
+            witness: path.witness(params),
+            booleans: path.booleans(params, target),
+            malleability: vec![],
+            policy: path_policy(path),
+        })
+        .collect())
+}
+
+// Path is called by its name, or by its index otherwise.
+pub fn path_label(names: &[String], branch: usize) -> String {
+    match names.get(branch) {
+        Some(name) => format!("path {:?}", name),
+        None => format!("path {} bound to stack {}", branch, branch),
     }
+}
 
+// Input stacks are bound to paths one to one, in the order of if block first.
+pub fn check_path_count(paths: &[Path], input: &[Vec<StackParam>]) -> Result<(), CompileError> {
+    if paths.len() == input.len() {
+        return Ok(());
+    }
+    let loc = match input.get(paths.len()) {
+        // Stack left without path.
+        Some(stack) => stack[0].loc.to_owned(),
+        // Path left without stack.
+        None => paths[input.len()].end(),
+    };
+    Err(CompileError {
+        loc,
+        kind: ErrorKind::StackMismatch(format!(
+            "Each spending path needs its own input stack, but {} path(s) for {} stack(s).",
+            paths.len(),
+            input.len()
+        )),
+    })
+}
+
+// Error found on the path names the path, and the stack it is bound to.
+pub fn bind_error(mut error: CompileError, label: &str) -> CompileError {
+    if let Some(msg) = error.kind.message_mut() {
+        *msg = format!("{} ({})", msg, label);
+    }
+    error
+}
+
+// Witness pubkey and sized bytes are compiled by name, so the name must have the same type in every stack.
+pub fn check_witness_declaration(input: &[Vec<StackParam>]) -> Result<(), CompileError> {
+    let is_compiled_by_name = |ty: &Type| matches!(ty, Type::PublicKey | Type::Bytes(_));
+    for stack in input {
+        for item in stack {
+            let is_mixed = input.iter().flatten().any(|other| {
+                other.identifier == item.identifier
+                    && other.ty != item.ty
+                    && (is_compiled_by_name(&item.ty) || is_compiled_by_name(&other.ty))
+            });
+            if is_mixed {
+                return Err(CompileError {
+                    loc: item.loc.to_owned(),
+                    kind: ErrorKind::TypeMismatch(format!(
+                        "Witness pubkey or bytes must have the same type in every stack but: {:?} is {:?}.",
+                        item.identifier.0, item.ty,
+                    )),
+                });
+            }
+        }
+    }
     Ok(())
 }
 
-pub fn analyze_statement(
-    ast: &Vec<Statement>,
-    scope_vec: &mut Vec<Scope>,
+// Transaction has one nLockTime, and input has one nSequence.
+// So a path can't require both height and time of the same locktime.
+pub fn check_locktime_path(path: &Path) -> Result<(), CompileError> {
+    let mut first: HashMap<LocktimeOp, (&Location, bool)> = HashMap::new();
+    for stmt in path.statements() {
+        if let Statement::LocktimeStatement {
+            loc,
+            operand,
+            op,
+            unit,
+        } = stmt
+        {
+            let is_time = unit.is_time(*operand);
+            let (first_loc, first_is_time) = *first.entry(op.to_owned()).or_insert((loc, is_time));
+            if first_is_time != is_time {
+                return Err(CompileError {
+                    loc: loc.to_owned(),
+                    kind: ErrorKind::MixedLocktime(format!(
+                        "Spending path cannot mix height-based and time-based {} but: {} conflicts with the one at {}.",
+                        match op {
+                            LocktimeOp::Cltv => "absolute locktime (after)",
+                            LocktimeOp::Csv => "relative locktime (older)",
+                        },
+                        if is_time { "time" } else { "height" },
+                        first_loc
+                    )),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+// Sized bytes must fit in a single stack element.
+pub fn check_byte_size(param: &StackParam) -> Result<(), CompileError> {
+    match param.ty {
+        Type::Bytes(n) if n == 0 || n > bitcoin::constants::MAX_SCRIPT_ELEMENT_SIZE => {
+            Err(CompileError {
+                loc: param.loc.to_owned(),
+                kind: ErrorKind::TypeMismatch(format!(
+                    "Size of bytes must be in range of 1 to {} but: {:?} is {}.",
+                    bitcoin::constants::MAX_SCRIPT_ELEMENT_SIZE,
+                    param.identifier.0,
+                    n
+                )),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+// Length of byte string known at compile time.
+// Hash result is typed by its digest length.
+pub fn byte_length(
+    expression: &Expression,
+    symbol_table: &HashMap<String, Symbol>,
+) -> Option<usize> {
+    match expression {
+        Expression::StringLiteral(_loc, data) => Some(literal_length(data)),
+        Expression::Variable(_loc, id) => match symbol_table.get(&id.0)?.ty {
+            Type::Bytes(n) => Some(n),
+            _ => None,
+        },
+        Expression::UnaryCryptoExpression { op, .. } => match op.result_type() {
+            Type::Bytes(n) => Some(n),
+            _ => None,
+        },
+        Expression::InlineExpression {
+            ty: Type::Bytes(n), ..
+        } => Some(*n),
+        Expression::InlineExpression { body, .. } => byte_length(body, symbol_table),
+        _ => None,
+    }
+}
+
+// String literal is pushed as hex if possible, otherwise as utf-8.
+pub fn literal_length(data: &str) -> usize {
+    hex::decode(data).map_or(data.len(), |bytes| bytes.len())
+}
+
+// 65535 intervals of 512 seconds, about 388 days.
+pub const MAX_RELATIVE_LOCKTIME_SECONDS: i64 = u16::MAX as i64 * 512;
+
+// Check the steps of path against the input stack bound to it.
+pub fn analyze_path(path: &Path, scope: &mut Scope, target: &Target) -> Result<(), CompileError> {
+    for step in &path.steps {
+        match step {
+            Step::Branch { condition, .. } => {
+                check_expression(condition, &mut scope.symbol_table)
+                    .and_then(|_| check_condition(condition, &scope.symbol_table, target))
+                    .map_err(|e| bind_error(e, &scope.label))?;
+            }
+            Step::Arm { selector, .. } => {
+                check_selector(selector, &scope.symbol_table)
+                    .and_then(|_| check_expression(selector, &mut scope.symbol_table))
+                    .map_err(|e| bind_error(e, &scope.label))?;
+            }
+            Step::Statement(stmt) => analyze_statement(stmt, &mut scope.symbol_table, target)
+                .map_err(|e| bind_error(e, &scope.label))?,
+        }
+    }
+    Ok(())
+}
+
+// Selector of match is compared by OP_NUMEQUAL, so it must be a number witness item.
+pub fn check_selector(
+    selector: &Expression,
+    symbol_table: &HashMap<String, Symbol>,
+) -> Result<(), CompileError> {
+    match selector {
+        Expression::Variable(loc, id) => match symbol_table.get(&id.0) {
+            Some(symbol) if symbol.ty != Type::Number => Err(CompileError {
+                loc: loc.to_owned(),
+                kind: ErrorKind::TypeMismatch(format!(
+                    "Selector of match must be number but: {:?} is {:?}.",
+                    id.0, symbol.ty
+                )),
+            }),
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+// Under MINIMALIF, OP_IF fails on anything but empty or 0x01, so byte string can't be if condition.
+// Number is normalised to 0 or 1 by codegen instead.
+pub fn check_condition(
+    condition: &Expression,
+    symbol_table: &HashMap<String, Symbol>,
     target: &Target,
-    mut branch: usize,
-) -> Result<usize, CompileError> {
-    // Check statements in global scope of current branch.
-    for stmt in ast {
-        match stmt {
-            Statement::LocktimeStatement { loc, operand, op } => {
-                // BIP 68: Relative locktime (CSV/older) is physically limited to 16 bits
-                // because it relies on the nSequence field's low 16 bits.
-                if matches!(op, LocktimeOp::Csv) {
-                    if *operand < 0 || *operand > u16::MAX as i64 {
-                        return Err(CompileError {
-                            loc: loc.to_owned(),
-                            kind: ErrorKind::IntegerOverflow(format!(
-                                "Relative locktime (older) cannot exceed 65,535 blocks due to BIP 68 limits but got: {}.",
-                            operand
-                            )),
-                        });
-                    }
-                }
-                // BIP112: Absoulte locktim is limited to u32::MAX
-                if *operand < 0 || *operand > u32::MAX as i64 {
+) -> Result<(), CompileError> {
+    let ty = |id: &Identifier| symbol_table.get(&id.0).map(|symbol| symbol.ty.to_owned());
+    if target.is_minimal_if() && condition.condition(&ty) == Condition::Bytes {
+        let value = match condition {
+            Expression::Variable(_loc, id) => format!("{:?} is {:?}", id.0, ty(id)),
+            _ => "bytes".to_string(),
+        };
+        return Err(CompileError {
+            loc: condition.to_owned().loc(),
+            kind: ErrorKind::TypeMismatch(format!(
+                "If condition must be boolean or number under MINIMALIF of {:?} but: {}. Compare it instead.",
+                target, value
+            )),
+        });
+    }
+    Ok(())
+}
+
+pub fn analyze_statement(
+    stmt: &Statement,
+    symbol_table: &mut HashMap<String, Symbol>,
+    _target: &Target,
+) -> Result<(), CompileError> {
+    match stmt {
+        Statement::LocktimeStatement {
+            loc,
+            operand,
+            op,
+            unit,
+        } => {
+            // BIP 68: Time-based relative locktime is 16 bits of 512 seconds.
+            if matches!(op, LocktimeOp::Csv)
+                && matches!(unit, LocktimeUnit::Second)
+                && (*operand < 0 || *operand > MAX_RELATIVE_LOCKTIME_SECONDS)
+            {
+                return Err(CompileError {
+                    loc: loc.to_owned(),
+                    kind: ErrorKind::IntegerOverflow(format!(
+                        "Relative locktime (older) cannot exceed {} seconds due to BIP 68 limits but got: {}.",
+                        MAX_RELATIVE_LOCKTIME_SECONDS, operand
+                    )),
+                });
+            }
+            // BIP 68: Relative locktime (CSV/older) is physically limited to 16 bits
+            // because it relies on the nSequence field's low 16 bits.
+            if matches!(op, LocktimeOp::Csv) && matches!(unit, LocktimeUnit::Block) {
+                if *operand < 0 || *operand > u16::MAX as i64 {
                     return Err(CompileError {
                         loc: loc.to_owned(),
                         kind: ErrorKind::IntegerOverflow(format!(
-                            "Locktime must be a 32-bit unsigned integer (0-4294967295), but got: {}.",
+                            "Relative locktime (older) cannot exceed 65,535 blocks due to BIP 68 limits but got: {}.",
                         operand
                         )),
                     });
                 }
             }
-            Statement::VerifyStatement(loc, expr) => {
-                check_variable(expr, &mut scope_vec[branch].symbol_table)?;
-                check_type(expr, &mut scope_vec[branch].symbol_table)?;
-                check_security(expr)?
-            }
-            Statement::ExpressionStatement(loc, expr) => {
-                check_variable(expr, &mut scope_vec[branch].symbol_table)?;
-                check_type(expr, &mut scope_vec[branch].symbol_table)?;
-                check_security(expr)?
-            }
-            Statement::IfStatement {
-                loc,
-                condition_expr,
-                if_block,
-                else_block,
-            } => {
-                check_variable(condition_expr, &mut scope_vec[branch].symbol_table)?;
-                check_type(condition_expr, &mut scope_vec[branch].symbol_table)?;
-                check_security(condition_expr)?;
-
-                branch = analyze_statement(&if_block, scope_vec, target, branch)?;
-                if else_block.is_some() {
-                    branch += 1;
-                    // Mark consumed variable before checkout to new stack branch.
-                    let [before, current]: &mut [Scope; 2] = (&mut scope_vec[branch - 1..=branch])
-                        .try_into()
-                        .expect("Non existing stack look up.");
-                    let before_stack = &mut before.symbol_table;
-                    let current_stack = &mut current.symbol_table;
-                    mark_consumed_stack(before_stack, current_stack)?;
-
-                    branch = analyze_statement(
-                        else_block.to_owned().unwrap().as_ref(),
-                        scope_vec,
-                        target,
-                        branch,
-                    )?;
+            // Explicit height or date must be on its side of the threshold.
+            let threshold = bitcoin::locktime::absolute::LOCK_TIME_THRESHOLD as i64;
+            if matches!(op, LocktimeOp::Cltv) && *unit != LocktimeUnit::Raw {
+                let is_valid = match unit {
+                    LocktimeUnit::Block => *operand < threshold,
+                    _ => *operand >= threshold,
+                };
+                if !is_valid {
+                    return Err(CompileError {
+                        loc: loc.to_owned(),
+                        kind: ErrorKind::IntegerOverflow(format!(
+                            "Absolute locktime (after) height must be below {} and date must be from {} as timestamp but got: {}.",
+                            threshold, threshold, operand
+                        )),
+                    });
                 }
             }
+            // BIP112: Absoulte locktim is limited to u32::MAX
+            if *operand < 0 || *operand > u32::MAX as i64 {
+                return Err(CompileError {
+                    loc: loc.to_owned(),
+                    kind: ErrorKind::IntegerOverflow(format!(
+                        "Locktime must be a 32-bit unsigned integer (0-4294967295), but got: {}.",
+                        operand
+                    )),
+                });
+            }
+        }
+        Statement::VerifyStatement(_loc, expr) | Statement::ExpressionStatement(_loc, expr) => {
+            check_expression(expr, symbol_table)?;
+            check_commitment(expr, symbol_table);
         }
+        // Path has no if or match statement but its branches.
+        Statement::IfStatement { .. } | Statement::MatchStatement { .. } => (),
     }
-    Ok(branch)
+    Ok(())
+}
+
+pub fn check_expression(
+    expr: &Expression,
+    symbol_table: &mut HashMap<String, Symbol>,
+) -> Result<(), CompileError> {
+    check_variable(expr, symbol_table)?;
+    check_type(expr, symbol_table)?;
+    check_security(expr)
 }
 
 // Check the existence of unused variable after analysis.
+// Input stack is spendable only if some path consumes it.
+pub fn check_consumed_stack(
+    stack_vec: &[StackParam],
+    stack_table: &HashMap<String, Symbol>,
+) -> Result<(), CompileError> {
+    let is_unconsumed = stack_vec.iter().all(|e| {
+        stack_table
+            .get(&e.identifier.0)
+            .is_some_and(|v| v.consume_count == 0)
+    });
+    match stack_vec.first() {
+        Some(first) if is_unconsumed => Err(CompileError {
+            loc: first.to_owned().loc(),
+            kind: ErrorKind::DeadPath("Input stack is never consumed by any path.".to_string()),
+        }),
+        _ => Ok(()),
+    }
+}
+
 pub fn check_unused_variable(
     stack_vec: &Vec<StackParam>,
     stack_table: &HashMap<String, Symbol>,
@@ -193,65 +477,84 @@ pub fn check_unused_variable(
                 kind: ErrorKind::UnusedVariable(format!("Variable unused: {:?}.", e.identifier)),
             });
         }
-    }
-    return Ok(());
-}
-
-// When checkout to next branch(stack), mark shared variables consumed.
-pub fn mark_consumed_stack(
-    before_stack: &mut HashMap<String, Symbol>,
-    current_stack: &mut HashMap<String, Symbol>,
-) -> Result<(), CompileError> {
-    for (k, v) in before_stack {
-        if current_stack.get(k).is_some() {
-            let consumed_item = current_stack.get(k).unwrap().clone();
-
-            // Counter consume_count consumed in before stack(shared item).
-            current_stack.insert(
-                k.to_string(),
-                Symbol {
-                    ty: consumed_item.ty,
-                    consume_count: 1,
-                    stack_position: consumed_item.stack_position,
-                },
-            );
+        // Otherwise, anyone can spend with their own key.
+        if let Some(v) = stack_table
+            .get(&e.identifier.0)
+            .filter(|v| v.ty == Type::PublicKey)
+        {
+            if !v.committed {
+                return Err(CompileError {
+                    loc: e.to_owned().loc(),
+                    kind: ErrorKind::UncommittedPubkey(format!(
+                        "Witness pubkey must be bound by hash commitment, e.g. hash160({}) == \"...\": {:?}.",
+                        e.identifier.0, e.identifier
+                    )),
+                });
+            }
+            if v.consume_count < use_limit(&v.ty) {
+                return Err(CompileError {
+                    loc: e.to_owned().loc(),
+                    kind: ErrorKind::UnusedVariable(format!(
+                        "Witness pubkey must be used by checksig after hash commitment: {:?}.",
+                        e.identifier
+                    )),
+                });
+            }
         }
     }
-
     return Ok(());
 }
 
-// No sequential if/else block && No statement after if/else block.
 // Unreachable Code Detection(No statement after return statement).
-// Final Statement must be expression statement.
+// Final Statement must be expression statement, or verify which succeeds by the value verified.
+// Statements after if/else block are the shared tail of every path through it.
 pub fn check_flow(ast: &Vec<Statement>) -> Result<(), CompileError> {
-    // No sequential if/else block && No statement after if/else block
-    // Check No statement after return statement.
-    for (i, statement) in ast.iter().enumerate() {
+    check_block_flow(ast, None)
+}
+
+// `next` is the statement run after the block, if any.
+// Block followed by other statement must not return, but fall through to it.
+fn check_block_flow(block: &[Statement], next: Option<&Statement>) -> Result<(), CompileError> {
+    for (i, statement) in block.iter().enumerate() {
+        let after = block.get(i + 1).or(next);
         match statement {
-            Statement::IfStatement { .. } => {
-                // Check No statement after if/else block,
-                // as it can be placed before if/else block.
-                if i != ast.len() - 1 {
-                    let next = ast[i + 1].to_owned();
-                    return Err(CompileError {
-                        loc: next.to_owned().loc(),
-                        kind: ErrorKind::UnreachableCode(format!(
-                            "No statement after if/else block but: {:?}.",
-                            next
-                        )),
-                    });
+            Statement::IfStatement {
+                if_block,
+                else_block,
+                ..
+            } => {
+                check_block_flow(if_block, after)?;
+                match else_block {
+                    Some(else_b) => check_block_flow(else_b, after)?,
+                    // Else path has nothing left to run.
+                    None if after.is_none() => {
+                        return Err(CompileError {
+                            loc: statement.to_owned().loc(),
+                            kind: ErrorKind::NoReturn(format!(
+                                "Return statement must exist for each possible execution path, but else block is missing: {:?}.",
+                                statement
+                            )),
+                        });
+                    }
+                    None => (),
+                }
+            }
+            Statement::MatchStatement { arms, default, .. } => {
+                for arm in arms {
+                    check_block_flow(&arm.block, after)?;
+                }
+                if let Some(default_b) = default {
+                    check_block_flow(default_b, after)?;
                 }
             }
             Statement::ExpressionStatement(..) => {
                 // Check No statement after return statement.
-                if i != ast.len() - 1 {
-                    let next = ast[i + 1].to_owned();
+                if let Some(after) = after {
                     return Err(CompileError {
-                        loc: next.to_owned().loc(),
+                        loc: after.to_owned().loc(),
                         kind: ErrorKind::UnreachableCode(format!(
                             "Unreachable code after return statement: {:?}. Move return statement at the last scope of execution path",
-                            next
+                            after
                         )),
                     });
                 }
@@ -259,32 +562,23 @@ pub fn check_flow(ast: &Vec<Statement>) -> Result<(), CompileError> {
             _ => (),
         }
     }
-    let last = ast.last().unwrap().to_owned();
-    // Final Statement must be expression statement.
-    match last {
-        Statement::IfStatement {
-            if_block,
-            else_block,
-            ..
-        } => {
-            check_flow(&if_block)?;
-            if else_block.is_some() {
-                check_flow(&else_block.unwrap())?;
-            }
-        }
-        Statement::ExpressionStatement(..) => (),
-        _ => {
-            return Err(CompileError {
-                loc: last.to_owned().loc(),
-                kind: ErrorKind::NoReturn(format!(
-                    "Return statement must exist for each possible execution path: {:?}.",
-                    last
-                )),
-            });
-        }
+    if next.is_some() {
+        return Ok(());
+    }
+    // Final Statement must be expression statement, or verify.
+    match block.last() {
+        Some(Statement::IfStatement { .. })
+        | Some(Statement::MatchStatement { .. })
+        | Some(Statement::ExpressionStatement(..))
+        | Some(Statement::VerifyStatement(..)) => Ok(()),
+        last => Err(CompileError {
+            loc: last.cloned().map(|stmt| stmt.loc()).unwrap_or_default(),
+            kind: ErrorKind::NoReturn(format!(
+                "Return statement must exist for each possible execution path: {:?}.",
+                last
+            )),
+        }),
     }
-
-    Ok(())
 }
 
 // Undefined Variable Check
@@ -296,6 +590,10 @@ pub fn check_variable(
     expression: &Expression,
     symbol_table: &mut HashMap<String, Symbol>,
 ) -> Result<(), CompileError> {
+    // Range form uses its variable once, whether or not it is rewritten to OP_WITHIN.
+    if let Some(range) = range_form(expression) {
+        return check_variable(range.operand, symbol_table);
+    }
     match expression {
         Expression::Variable(loc, id) => {
             let id_string = id.0.to_owned();
@@ -311,7 +609,7 @@ pub fn check_variable(
             }
             let item = symbol_table.get(&id_string).unwrap().to_owned();
             // 2. Check the consumption of variable
-            if item.consume_count != 0 {
+            if item.consume_count >= use_limit(&item.ty) {
                 return Err(CompileError {
                     loc: expression.to_owned().loc(),
                     kind: ErrorKind::VariableConsumed(format!(
@@ -322,9 +620,10 @@ pub fn check_variable(
             }
 
             // 3. Check whether there is unconsumed variable before this variable.
-            let is_invalid_consumption_order = symbol_table
-                .values()
-                .any(|v| v.stack_position < item.stack_position && v.consume_count == 0);
+            // Witness pubkey stays on the stack until its last use.
+            let is_invalid_consumption_order = symbol_table.values().any(|v| {
+                v.stack_position < item.stack_position && v.consume_count < use_limit(&v.ty)
+            });
             if is_invalid_consumption_order {
                 return Err(CompileError {
                     loc: expression.to_owned().loc(),
@@ -340,9 +639,8 @@ pub fn check_variable(
             symbol_table.insert(
                 id_string,
                 Symbol {
-                    ty: item.ty,
-                    consume_count: 1,
-                    stack_position: item.stack_position,
+                    consume_count: item.consume_count + 1,
+                    ..item
                 },
             );
 
@@ -357,10 +655,32 @@ pub fn check_variable(
                 loc: _,
                 sig,
                 pubkey,
-            } => {
-                check_variable(&sig, symbol_table)?;
-                check_variable(&pubkey, symbol_table)
-            }
+            } => match &**pubkey {
+                // Witness pubkey is on top of signature, as OP_CHECKSIG takes.
+                Expression::Variable(_loc, id)
+                    if symbol_table
+                        .get(&id.0)
+                        .is_some_and(|v| v.ty == Type::PublicKey) =>
+                {
+                    check_variable(pubkey, symbol_table)?;
+                    // Copy of pubkey for commitment is tucked under signature, not above.
+                    let pubkey_item = symbol_table.get(&id.0).unwrap().to_owned();
+                    symbol_table.insert(
+                        id.0.to_owned(),
+                        Symbol {
+                            consume_count: use_limit(&pubkey_item.ty),
+                            ..pubkey_item.to_owned()
+                        },
+                    );
+                    let result = check_variable(sig, symbol_table);
+                    symbol_table.insert(id.0.to_owned(), pubkey_item);
+                    result
+                }
+                _ => {
+                    check_variable(&sig, symbol_table)?;
+                    check_variable(&pubkey, symbol_table)
+                }
+            },
             Factor::MultiSigFactor { loc: _, m: _, n } => {
                 for factor in n {
                     match factor {
@@ -421,10 +741,73 @@ pub fn check_variable(
             operand,
             op: _,
         } => check_variable(&operand, symbol_table),
+        Expression::WithinExpression {
+            loc: _,
+            operand,
+            min,
+            max,
+        } => {
+            check_variable(operand, symbol_table)?;
+            check_variable(min, symbol_table)?;
+            check_variable(max, symbol_table)
+        }
+        // Body of inlined function consumes the arguments in its own order.
+        Expression::InlineExpression {
+            loc,
+            identifier,
+            definition,
+            body,
+            ..
+        } => check_variable(body, symbol_table)
+            .map_err(|e| inline_error(e, loc, identifier, definition)),
         _ => Ok(()),
     }
 }
 
+// Mark witness pubkey bound by hash commitment, e.g. hash160(pk) == "...".
+// Commitment counts only if it must hold for the expression to succeed.
+pub fn check_commitment(expression: &Expression, symbol_table: &mut HashMap<String, Symbol>) {
+    match expression {
+        Expression::LogicalExpression {
+            lhs,
+            op: BinaryLogicalOp::BoolAnd,
+            rhs,
+            ..
+        } => {
+            check_commitment(lhs, symbol_table);
+            check_commitment(rhs, symbol_table);
+        }
+        Expression::InlineExpression { body, .. } => check_commitment(body, symbol_table),
+        Expression::CompareExpression {
+            lhs,
+            op: BinaryCompareOp::Equal,
+            rhs,
+            ..
+        } => {
+            for (hash, digest) in [(lhs, rhs), (rhs, lhs)] {
+                if !matches!(**digest, Expression::StringLiteral(..)) {
+                    continue;
+                }
+                // Unwrap hash ops down to the variable.
+                let mut operand = &**hash;
+                let mut is_hashed = false;
+                while let Expression::UnaryCryptoExpression { operand: inner, .. } = operand {
+                    operand = inner;
+                    is_hashed = true;
+                }
+                if let Expression::Variable(_loc, id) = operand {
+                    if let Some(v) = symbol_table.get_mut(&id.0) {
+                        if is_hashed && v.ty == Type::PublicKey {
+                            v.committed = true;
+                        }
+                    }
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
 // Check type(e.g. operand of expression).
 // Every arithmetic op takes 32bit integer including boolean.
 // Every bytes op can takes string or signature.
@@ -451,7 +834,19 @@ pub fn check_type(
                             loc: _,
                             sig,
                             pubkey,
-                        } => check_type_sig_pubkey(sig, pubkey, symbol_table)?,
+                        } => {
+                            // Multisig takes pubkeys pushed by script.
+                            if !matches!(**pubkey, Expression::StringLiteral(..)) {
+                                return Err(CompileError {
+                                    loc: pubkey.to_owned().loc(),
+                                    kind: ErrorKind::TypeMismatch(format!(
+                                        "Public Key of multisig must be from string literal but: {:?}.",
+                                        pubkey
+                                    )),
+                                });
+                            }
+                            check_type_sig_pubkey(sig, pubkey, symbol_table)?
+                        }
                         _ => continue,
                     }
                 }
@@ -482,6 +877,21 @@ pub fn check_type(
                         )),
                     });
                 }
+                // Bytes of different length never equal.
+                if let (Some(lhs_len), Some(rhs_len)) = (
+                    byte_length(lhs, symbol_table),
+                    byte_length(rhs, symbol_table),
+                ) {
+                    if lhs_len != rhs_len {
+                        return Err(CompileError {
+                            loc: loc.to_owned(),
+                            kind: ErrorKind::TypeMismatch(format!(
+                                "Compare bytes length must be same but: {} bytes to {} bytes.",
+                                lhs_len, rhs_len
+                            )),
+                        });
+                    }
+                }
                 return Ok(());
             }
             // compare boolean/number to boolean/number
@@ -513,6 +923,19 @@ pub fn check_type(
             check_type_numeric(&lhs, symbol_table)?;
             check_type_numeric(&rhs, symbol_table)
         }
+        Expression::WithinExpression {
+            loc: _,
+            operand,
+            min,
+            max,
+        } => {
+            check_type_numeric(operand, symbol_table)?;
+            check_type_numeric(min, symbol_table)?;
+            check_type_numeric(max, symbol_table)
+        }
+        Expression::UnaryCryptoExpression { operand, .. } => {
+            check_type_checksig(operand, symbol_table)
+        }
         // Allow only ascii encoded string.
         // UTF-8 string's char has various byte size, which makes use of OP_SIZE hard.
         Expression::ByteExpression {
@@ -538,6 +961,109 @@ pub fn check_type(
                 _ => Ok(()),
             }
         }
+        // Arguments must match parameter types, and body must match return type.
+        Expression::InlineExpression {
+            loc,
+            identifier,
+            definition,
+            params,
+            ty,
+            args,
+            body,
+        } => {
+            for (param, arg) in params.iter().zip(args.iter()) {
+                check_type_argument(&param.ty, arg, symbol_table)?;
+            }
+            if ty.is_numeric() {
+                check_type_numeric(body, symbol_table)
+            } else {
+                check_type_string(body, symbol_table)
+                    .and_then(|_| check_byte_length(ty, body, symbol_table))
+            }
+            .map_err(|e| inline_error(e, loc, identifier, definition))
+        }
+        _ => Ok(()),
+    }
+}
+
+// Checksig nested in operand not type checked by itself, e.g. of hash.
+pub fn check_type_checksig(
+    expression: &Expression,
+    symbol_table: &HashMap<String, Symbol>,
+) -> Result<(), CompileError> {
+    match expression {
+        Expression::CheckSigExpression { .. } => check_type(expression, symbol_table),
+        _ => expression
+            .sub_expressions()
+            .into_iter()
+            .try_for_each(|sub| check_type_checksig(sub, symbol_table)),
+    }
+}
+
+pub fn check_type_argument(
+    ty: &Type,
+    arg: &Expression,
+    symbol_table: &HashMap<String, Symbol>,
+) -> Result<(), CompileError> {
+    match ty {
+        Type::Boolean | Type::Number => check_type_numeric(arg, symbol_table),
+        Type::String => check_type_string(arg, symbol_table),
+        Type::Bytes(_) => {
+            check_type_string(arg, symbol_table)?;
+            check_byte_length(ty, arg, symbol_table)
+        }
+        // Pubkey is either literal or from arguments.
+        Type::PublicKey => match arg {
+            Expression::StringLiteral(loc, data) => check_pubkey_literal(loc, data),
+            Expression::Variable(_loc, id)
+                if symbol_table
+                    .get(&id.0)
+                    .is_some_and(|symbol| symbol.ty == Type::PublicKey) =>
+            {
+                Ok(())
+            }
+            _ => Err(CompileError {
+                loc: arg.to_owned().loc(),
+                kind: ErrorKind::TypeMismatch(format!(
+                    "Argument must be type of pubkey but: {:?}.",
+                    arg
+                )),
+            }),
+        },
+        // Signature can only be passed from arguments, as checksig takes.
+        Type::Signature => match arg {
+            Expression::Variable(_loc, id)
+                if symbol_table
+                    .get(&id.0)
+                    .is_some_and(|symbol| symbol.ty == Type::Signature) =>
+            {
+                Ok(())
+            }
+            _ => Err(CompileError {
+                loc: arg.to_owned().loc(),
+                kind: ErrorKind::TypeMismatch(format!(
+                    "Argument must be type of signature but: {:?}.",
+                    arg
+                )),
+            }),
+        },
+    }
+}
+
+// Sized bytes takes expression of the same length, if known.
+pub fn check_byte_length(
+    ty: &Type,
+    expression: &Expression,
+    symbol_table: &HashMap<String, Symbol>,
+) -> Result<(), CompileError> {
+    match (ty, byte_length(expression, symbol_table)) {
+        (Type::Bytes(n), Some(len)) if *n != len => Err(CompileError {
+            loc: expression.to_owned().loc(),
+            kind: ErrorKind::TypeMismatch(format!(
+                "Expected {} bytes but {} bytes: {:?}.",
+                n, len, expression
+            )),
+        }),
         _ => Ok(()),
     }
 }
@@ -570,7 +1096,7 @@ pub fn check_type_numeric(
         Expression::Variable(loc, id) => {
             let id_string = id.0.to_owned();
             let var_type = symbol_table.get(&id_string).unwrap().ty.to_owned();
-            if var_type == Type::Signature || var_type == Type::String {
+            if !var_type.is_numeric() {
                 return Err(CompileError {
                     loc: loc,
                     kind: ErrorKind::InvalidOperation(format!(
@@ -605,6 +1131,20 @@ pub fn check_type_numeric(
         Expression::UnaryMathExpression { loc, operand, op } => {
             check_type_numeric(&operand, symbol_table)
         }
+        Expression::WithinExpression { .. } => check_type(expression, symbol_table),
+        // Function returning string is not numeric.
+        Expression::InlineExpression { loc, ref ty, .. } => {
+            if !ty.is_numeric() {
+                return Err(CompileError {
+                    loc,
+                    kind: ErrorKind::InvalidOperation(format!(
+                        "Operand must be number or boolean but: {:?}.",
+                        expression,
+                    )),
+                });
+            }
+            check_type(expression, symbol_table)
+        }
         _ => Ok(()),
     }
 }
@@ -617,8 +1157,10 @@ pub fn check_type_string(
         // StringLiteral is just string.
         Expression::StringLiteral(loc, val) => Ok(()),
         // UnaryCryptoExpression ouputs ascii string.
-        // UnaryCryptoExpression doesn't need to check operand.
-        Expression::UnaryCryptoExpression { .. } => Ok(()),
+        // Operand of any type is hashed, but checksig in it is checked as anywhere else.
+        Expression::UnaryCryptoExpression { operand, .. } => {
+            check_type_checksig(&operand, symbol_table)
+        }
         // For variable, look up symbol table.
         Expression::Variable(loc, id) => {
             let id_string = id.0.to_owned();
@@ -634,6 +1176,10 @@ pub fn check_type_string(
             }
             Ok(())
         }
+        // Only function returning string is string.
+        Expression::InlineExpression { ref ty, .. } if !ty.is_numeric() => {
+            check_type(expression, symbol_table)
+        }
         // Throw error for non-string evaluated expression.
         // Remaining expressions are all evaluated to number.
         _ => {
@@ -678,40 +1224,28 @@ pub fn check_type_sig_pubkey(
             });
         }
     };
-    // Only string literal can be pubkey.
-    // Public key safety check on ECC.
+    // Pubkey is either string literal or witness pubkey.
     match pubkey {
-        Expression::StringLiteral(loc, data) => {
-            // Try decoding hex.
-            let hex_pubkey = hex::decode(&data);
-            if hex_pubkey.is_ok() {
-                let pubkey_bytes = hex_pubkey.unwrap();
-
-                // Extract XOnlyPubkey as it could be ECDSA public key.
-                if bitcoin::XOnlyPublicKey::from_slice(
-                    // Check whether prefix is 02 or 03.
-                    if pubkey_bytes.len() == 33 && (pubkey_bytes[0] == 2 || pubkey_bytes[0] == 3) {
-                        &pubkey_bytes[1..]
-                    } else {
-                        &pubkey_bytes
-                    },
-                )
-                .is_ok()
-                {
-                    return Ok(());
-                }
-            }
-
-            Err(CompileError {
-                loc: loc.to_owned(),
-                kind: ErrorKind::MalformedPubkey(format!("Public key is malformed: {:?}.", data)),
-            })
+        Expression::StringLiteral(loc, data) => check_pubkey_literal(loc, data),
+        Expression::Variable(_loc, id)
+            if symbol_table
+                .get(&id.0)
+                .is_some_and(|v| v.ty == Type::PublicKey) =>
+        {
+            Ok(())
         }
+        Expression::InlineExpression { identifier, .. } => Err(CompileError {
+            loc: pubkey.to_owned().loc(),
+            kind: ErrorKind::TypeMismatch(format!(
+                "Public Key must be from string literal or pubkey argument but: call of {:?}.",
+                identifier.0
+            )),
+        }),
         _ => {
             return Err(CompileError {
                 loc: pubkey.to_owned().loc(),
                 kind: ErrorKind::TypeMismatch(format!(
-                    "Public Key must be from string literal but: {:?}.",
+                    "Public Key must be from string literal or pubkey argument but: {:?}.",
                     pubkey
                 )),
             });
@@ -719,6 +1253,34 @@ pub fn check_type_sig_pubkey(
     }
 }
 
+// Public key safety check on ECC.
+pub fn check_pubkey_literal(loc: &Location, data: &str) -> Result<(), CompileError> {
+    // Try decoding hex.
+    let hex_pubkey = hex::decode(data);
+    if hex_pubkey.is_ok() {
+        let pubkey_bytes = hex_pubkey.unwrap();
+
+        // Extract XOnlyPubkey as it could be ECDSA public key.
+        if bitcoin::XOnlyPublicKey::from_slice(
+            // Check whether prefix is 02 or 03.
+            if pubkey_bytes.len() == 33 && (pubkey_bytes[0] == 2 || pubkey_bytes[0] == 3) {
+                &pubkey_bytes[1..]
+            } else {
+                &pubkey_bytes
+            },
+        )
+        .is_ok()
+        {
+            return Ok(());
+        }
+    }
+
+    Err(CompileError {
+        loc: loc.to_owned(),
+        kind: ErrorKind::MalformedPubkey(format!("Public key is malformed: {:?}.", data)),
+    })
+}
+
 // Check any possible vulnerability.
 pub fn check_security(expression: &Expression) -> Result<(), CompileError> {
     check_overflow(expression)?;
@@ -744,9 +1306,25 @@ pub fn check_security(expression: &Expression) -> Result<(), CompileError> {
         Expression::UnaryMathExpression { loc, operand, op } => {
             check_security(&operand)?;
         }
+        Expression::WithinExpression {
+            operand, min, max, ..
+        } => {
+            check_security(operand)?;
+            check_security(min)?;
+            check_security(max)?;
+        }
         Expression::UnaryCryptoExpression { loc, operand, op } => {
             check_security(&operand)?;
         }
+        Expression::InlineExpression {
+            loc,
+            identifier,
+            definition,
+            body,
+            ..
+        } => {
+            check_security(body).map_err(|e| inline_error(e, loc, identifier, definition))?;
+        }
         _ => (),
     }
 
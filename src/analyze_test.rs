@@ -2,8 +2,8 @@
 mod tests {
     use super::*;
     use crate::analyze::{
-        analyze, build_symbol_table, check_flow, check_overflow, check_type, check_type_sig_pubkey,
-        check_useless_sig, check_variable, Symbol,
+        analyze_paths, build_symbol_table, check_flow, check_overflow, check_type,
+        check_type_sig_pubkey, check_useless_sig, check_variable, Symbol,
     };
     // Import analyzer functions
     use crate::ast::*; // Import AST definitions
@@ -21,6 +21,7 @@ mod tests {
             end,
             line: 1,
             column: start,
+            file: String::new(),
         }
     }
 
@@ -56,6 +57,15 @@ mod tests {
         Statement::ExpressionStatement(loc(0, 0), expr)
     }
 
+    fn older(operand: i64) -> Statement {
+        Statement::LocktimeStatement {
+            loc: loc(0, 0),
+            operand,
+            op: LocktimeOp::Csv,
+            unit: LocktimeUnit::Block,
+        }
+    }
+
     fn simple_verify(expr: Expression) -> Statement {
         Statement::VerifyStatement(loc(0, 0), expr)
     }
@@ -86,6 +96,7 @@ mod tests {
                 ty: Type::Number,
                 consume_count: 0,
                 stack_position: 0,
+                committed: false,
             },
         );
         table.insert(
@@ -94,6 +105,7 @@ mod tests {
                 ty: Type::Boolean,
                 consume_count: 0,
                 stack_position: 1,
+                committed: false,
             },
         );
         table.insert(
@@ -102,6 +114,7 @@ mod tests {
                 ty: Type::Signature,
                 consume_count: 0,
                 stack_position: 2,
+                committed: false,
             },
         );
         table.insert(
@@ -110,6 +123,7 @@ mod tests {
                 ty: Type::String,
                 consume_count: 0,
                 stack_position: 3,
+                committed: false,
             },
         );
         table
@@ -476,11 +490,42 @@ mod tests {
     }
 
     #[test]
-    fn test_check_flow_err_no_return() {
+    fn test_check_flow_ok_implicit_return() {
+        // Path ending with verify succeeds by the value verified.
         let ast = vec![simple_verify(bool_lit(true))];
+        assert!(check_flow(&ast).is_ok());
+    }
+
+    #[test]
+    fn test_check_flow_ok_statement_after_if() {
+        let ast = vec![
+            Statement::IfStatement {
+                loc: loc(0, 0),
+                condition_expr: bool_lit(true),
+                if_block: vec![simple_verify(num(1))],
+                else_block: None,
+            },
+            simple_return(num(3)),
+        ];
+        assert!(check_flow(&ast).is_ok());
+    }
+
+    #[test]
+    fn test_check_flow_err_no_return() {
+        let ast = vec![older(100)];
         let res = check_flow(&ast);
         assert!(res.is_err());
         assert!(matches!(res.unwrap_err().kind, ErrorKind::NoReturn(_)));
+
+        // Else path of the last if statement has nothing to run.
+        let ast = vec![Statement::IfStatement {
+            loc: loc(0, 0),
+            condition_expr: bool_lit(true),
+            if_block: vec![simple_return(num(1))],
+            else_block: None,
+        }];
+        let res = check_flow(&ast);
+        assert!(matches!(res.unwrap_err().kind, ErrorKind::NoReturn(_)));
     }
 
     #[test]
@@ -519,7 +564,7 @@ mod tests {
             loc: loc(0, 0),
             condition_expr: bool_lit(true),
             if_block: vec![simple_return(num(1))],
-            else_block: Some(vec![simple_verify(bool_lit(true))]), // This path has no return
+            else_block: Some(vec![older(100)]), // This path has no return
         }];
         let res = check_flow(&ast);
         assert!(res.is_err());
@@ -534,9 +579,10 @@ mod tests {
         ($input:expr) => {{
             let parser = BithovenParser::new();
             let parsed = parser.parse($input).expect("Parser failed on valid input");
-            let res = analyze(
+            let res = analyze_paths(
                 &parsed.output_script,
                 parsed.input_stack,
+                &[],
                 &parsed.pragma.target,
             );
             assert!(res.is_ok(), "Analyzer failed on valid input: {:?}", res);
@@ -548,9 +594,10 @@ mod tests {
         ($input:expr, $expected_kind:pat) => {{
             let parser = BithovenParser::new();
             let parsed = parser.parse($input).expect("Parser failed on input string");
-            let res = analyze(
+            let res = analyze_paths(
                 &parsed.output_script,
                 parsed.input_stack,
+                &[],
                 &parsed.pragma.target,
             );
             assert!(res.is_err(), "Analyzer unexpectedly succeeded");
@@ -645,12 +692,35 @@ mod tests {
             (my_sig: signature)
             {
                 verify checksig(my_sig, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+                older 100;
                 // No return statement
             }
         "#;
         assert_analyze_fails!(input, ErrorKind::NoReturn(_));
     }
 
+    #[test]
+    fn test_analyze_integration_err_else_if_no_else() {
+        // Else if chain without the final else is reported at its last if, not as a path left without stack.
+        let input = r#"
+            pragma bithoven version 1.0.0;
+            pragma bithoven target segwit;
+            (a: bool, b: bool, my_sig: signature)
+            (a: bool, b: bool, my_sig: signature)
+            {
+                if a {
+                    return checksig(my_sig, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+                } else if b {
+                    return checksig(my_sig, "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7");
+                }
+            }
+        "#;
+        assert_analyze_fails!(input, ErrorKind::NoReturn(_));
+        let err = crate::compile_program(input.to_string()).unwrap_err();
+        assert_eq!((err.loc.line, err.loc.column), (9, 24));
+        assert!(format!("{:?}", err.kind).contains("else block is missing"));
+    }
+
     #[test]
     fn test_analyze_integration_err_unreachable() {
         let input = r#"
@@ -679,6 +749,31 @@ mod tests {
         assert_analyze_fails!(input, ErrorKind::TypeMismatch(_));
     }
 
+    #[test]
+    fn test_analyze_integration_err_hashed_checksig() {
+        // Checksig is type checked under hash, as compile takes its sig and pubkey for granted.
+        let input = r#"
+            pragma bithoven version 1.0.0;
+            pragma bithoven target segwit;
+            (my_sig: signature)
+            {
+                verify sha256 checksig(my_sig, 5);
+                return true;
+            }
+        "#;
+        assert_analyze_fails!(input, ErrorKind::TypeMismatch(_));
+        let input = r#"
+            pragma bithoven version 1.0.0;
+            pragma bithoven target segwit;
+            (my_sig: signature)
+            {
+                verify sha256 checksig(1, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798") == "0000000000000000000000000000000000000000000000000000000000000000";
+                return checksig(my_sig, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+            }
+        "#;
+        assert_analyze_fails!(input, ErrorKind::InvalidOperation(_));
+    }
+
     #[test]
     fn test_analyze_integration_err_scope_violation() {
         let input = r#"
@@ -699,9 +794,8 @@ mod tests {
     }
 
     #[test]
-    #[should_panic] // This test should panic because it tries to access scope_vec[1]
-    fn test_analyze_panic_branch_mismatch() {
-        // This tests for a bug in the analyzer itself.
+    fn test_analyze_err_branch_mismatch() {
+        // Two paths for one stack.
         let input = r#"
             pragma bithoven version 1.0.0;
             pragma bithoven target segwit;
@@ -710,20 +804,298 @@ mod tests {
                 if true {
                     return 1;
                 } else {
-                    return 2; // This 'else' will cause branch=1
+                    return 2; // No stack is left for this path
                 }
             }
         "#;
-        // The parser succeeds
-        let parser = BithovenParser::new();
-        let parsed = parser.parse(input).expect("Parser failed");
+        assert_analyze_fails!(input, ErrorKind::StackMismatch(_));
+
+        // One path for two stacks.
+        let input = r#"
+            pragma bithoven version 1.0.0;
+            pragma bithoven target segwit;
+            (sig_a: signature)
+            (sig_b: signature) // No path is left for this stack
+            {
+                return checksig(sig_a, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+            }
+        "#;
+        assert_analyze_fails!(input, ErrorKind::StackMismatch(_));
+    }
+
+    #[test]
+    fn test_analyze_path_report() {
+        let source = fs::read_to_string("example/nestedif.bithoven").unwrap();
+        let paths = crate::compile_program(source).unwrap().paths();
+        let branches: Vec<(usize, Vec<bool>)> =
+            paths.into_iter().map(|p| (p.stack, p.branches)).collect();
+        assert_eq!(
+            branches,
+            vec![
+                (0, vec![true, true]),
+                (1, vec![true, false]),
+                (2, vec![false])
+            ]
+        );
+    }
 
-        // This will call analyze_statement with branch=1, which panics on scope_vec[1]
-        // This is a valid test, as it identifies a crash bug in the analyzer.
-        let _ = analyze(
+    #[test]
+    fn test_analyze_named_path() {
+        let source = fs::read_to_string("example/namedpath.bithoven").unwrap();
+        let paths = crate::compile_program(source).unwrap().paths();
+        let report: Vec<(Option<String>, Vec<bool>, Vec<String>)> = paths
+            .into_iter()
+            .map(|p| (p.name, p.branches, p.witness))
+            .collect();
+        assert_eq!(
+            report,
+            vec![
+                (
+                    Some("refund".to_string()),
+                    vec![true],
+                    vec!["true".to_string(), "<sig_alice>".to_string()]
+                ),
+                (
+                    Some("claim".to_string()),
+                    vec![false],
+                    vec![
+                        "false".to_string(),
+                        "<preimage>".to_string(),
+                        "<sig_bob>".to_string()
+                    ]
+                ),
+            ]
+        );
+
+        // Error names the path instead of the stack.
+        let input = r#"
+            pragma bithoven version 1.0.0;
+            pragma bithoven target segwit;
+            path refund(sig_a: signature, unused: number) {
+                return checksig(sig_a, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+            }
+            path claim(sig_b: signature) {
+                return checksig(sig_b, "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7");
+            }
+        "#;
+        let err = crate::compile_program(input.to_string()).unwrap_err();
+        assert!(
+            matches!(&err.kind, ErrorKind::UnusedVariable(msg) if msg.ends_with("(path \"refund\")")),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_analyze_match_statement() {
+        let source = fs::read_to_string("example/match.bithoven").unwrap();
+        let output = crate::compile_program(source).unwrap();
+        assert!(
+            output
+                .asm()
+                .starts_with("OP_DUP OP_NOT OP_IF OP_DROP OP_PUSHBYTES_33"),
+            "{}",
+            output.asm()
+        );
+        assert!(output
+            .asm()
+            .contains("OP_ELSE OP_DUP OP_PUSHNUM_1 OP_NUMEQUAL OP_NIP OP_IF"));
+        assert!(output.asm().ends_with("OP_ENDIF OP_ENDIF OP_CHECKSIG"));
+
+        // Arms are reported as else if chain, with the selector filled in.
+        let report: Vec<(Vec<bool>, String)> = output
+            .paths()
+            .into_iter()
+            .map(|p| (p.branches, p.witness[0].to_owned()))
+            .collect();
+        assert_eq!(
+            report,
+            vec![
+                (vec![true], "0".to_string()),
+                (vec![false, true], "1".to_string()),
+                (vec![false, false], "2".to_string())
+            ]
+        );
+
+        // Without default arm, the last arm verifies the selector.
+        let source = program(
+            "(choice: number, sig: signature)\n(choice: number, sig_bob: signature)",
+            &format!(
+                "match choice {{\n1 => {{\nreturn {}\n}}\n2 => {{\nreturn {}\n}}\n}}",
+                CHECKSIG, BOB_CHECKSIG
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert!(
+            output
+                .asm()
+                .contains("OP_ELSE OP_PUSHNUM_2 OP_NUMEQUALVERIFY"),
+            "{}",
+            output.asm()
+        );
+    }
+
+    #[test]
+    fn test_analyze_err_match_statement() {
+        // Arm of the same value is never taken.
+        let source = program(
+            "(choice: number, sig: signature)\n(choice: number, sig_bob: signature)",
+            &format!(
+                "match choice {{\n1 => {{\nreturn {}\n}}\n1 => {{\nreturn {}\n}}\n}}",
+                CHECKSIG, BOB_CHECKSIG
+            ),
+        );
+        let warnings = crate::compile_program(source).unwrap().warnings();
+        assert!(
+            matches!(warnings[0].kind, WarningKind::DeadPath(_)),
+            "{:?}",
+            warnings
+        );
+        assert_eq!(warnings[0].loc.line, 10);
+
+        // Selector must be number.
+        let source = program(
+            "(choice: bool, sig: signature)",
+            &format!("match choice {{\n1 => {{\nreturn {}\n}}\n}}", CHECKSIG),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::TypeMismatch(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_analyze_else_if() {
+        // else if is the same as if statement in else block.
+        let stack = "(a: bool, sig: signature)\n(a: bool, b: bool, sig_bob: signature)\n(a: bool, b: bool, sig_carol: signature)";
+        let carol_checksig = CHECKSIG.replace("(sig,", "(sig_carol,");
+        let chain = program(
+            stack,
+            &format!(
+                "if a {{\nreturn {}\n}} else if b {{\nreturn {}\n}} else {{\nreturn {}\n}}",
+                CHECKSIG, BOB_CHECKSIG, carol_checksig
+            ),
+        );
+        let nested = program(
+            stack,
+            &format!(
+                "if a {{\nreturn {}\n}} else {{\nif b {{\nreturn {}\n}} else {{\nreturn {}\n}}\n}}",
+                CHECKSIG, BOB_CHECKSIG, carol_checksig
+            ),
+        );
+        let chain = crate::compile_program(chain).unwrap();
+        assert_eq!(chain.asm(), crate::compile_program(nested).unwrap().asm());
+        let branches: Vec<Vec<bool>> = chain.paths().into_iter().map(|p| p.branches).collect();
+        assert_eq!(
+            branches,
+            vec![vec![true], vec![false, true], vec![false, false]]
+        );
+    }
+
+    #[test]
+    fn test_analyze_shared_tail() {
+        // Owner signs after either branch.
+        let source = program(
+            "(a: bool, preimage: bytes32, sig: signature)\n(a: bool, sig: signature)",
+            &format!(
+                "if a {{\nverify sha256 preimage == \"{}\";\n}} else {{\nolder 1000;\n}}\nreturn {}",
+                DIGEST, CHECKSIG
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert!(
+            output.asm().ends_with(
+                "OP_ENDIF OP_PUSHBYTES_33 0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212 OP_CHECKSIG"
+            ),
+            "{}",
+            output.asm()
+        );
+        assert_eq!(output.asm().matches("OP_CHECKSIG").count(), 1);
+
+        // Tail consumes the stack in order on every path.
+        let source = program(
+            "(a: bool, sig: signature, x: number)\n(a: bool, sig: signature)",
+            &format!(
+                "if a {{\nverify x == 1;\n}} else {{\nolder 1000;\n}}\nreturn {}",
+                CHECKSIG
+            ),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::InvalidConsumptionOrder(_)),
+            "{:?}",
+            err
+        );
+
+        // Return in the block followed by the tail.
+        let source = program(
+            "(a: bool, sig: signature)\n(a: bool, sig: signature)",
+            &format!(
+                "if a {{\nreturn true;\n}} else {{\nolder 1000;\n}}\nreturn {}",
+                CHECKSIG
+            ),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::UnreachableCode(_)),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_analyze_implicit_return() {
+        // Verify at the end is compiled without OP_VERIFY.
+        let source = program(
+            "(sig: signature)",
+            &CHECKSIG.replace("checksig", "verify checksig"),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert!(output.asm().ends_with(" OP_CHECKSIG"), "{}", output.asm());
+
+        let source = program(
+            "(a: bool, sig: signature)\n(a: bool, x: number, sig: signature)",
+            &format!(
+                "if a {{\nverify {}\n}} else {{\nverify x > 1;\nverify {}\n}}",
+                CHECKSIG, CHECKSIG
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert!(
+            !output.asm().contains("OP_CHECKSIGVERIFY"),
+            "{}",
+            output.asm()
+        );
+        assert!(output.asm().contains("OP_GREATERTHAN OP_VERIFY"));
+    }
+
+    #[test]
+    fn test_analyze_err_path_binding() {
+        // Second stack doesn't hold what its path consumes, in order.
+        let input = r#"
+            pragma bithoven version 1.0.0;
+            pragma bithoven target segwit;
+            (choice: bool, sig_a: signature)
+            (choice: bool, sig_b: signature, preimage: string)
+            {
+                if choice {
+                    return checksig(sig_a, "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798");
+                } else {
+                    verify sha256(preimage) == "0000000000000000000000000000000000000000000000000000000000000000";
+                    return checksig(sig_b, "03a0434d9e47f3c86235477c7b1ae6ae5d3442d49b1943c2b752a68e2a47e247c7");
+                }
+            }
+        "#;
+        let parsed = BithovenParser::new().parse(input).unwrap();
+        let err = analyze_paths(
             &parsed.output_script,
             parsed.input_stack,
+            &[],
             &parsed.pragma.target,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(&err.kind, ErrorKind::InvalidConsumptionOrder(msg) if msg.ends_with("(path 1 bound to stack 1)")),
+            "{:?}",
+            err
         );
     }
 
@@ -767,9 +1139,10 @@ mod tests {
                 });
 
                 // 6. Analyze all
-                analyze(
+                analyze_paths(
                     &parsed.output_script,
                     parsed.input_stack,
+                    &[],
                     &parsed.pragma.target,
                 )
                 .expect("Analyze Error: ");
@@ -813,9 +1186,10 @@ mod tests {
         let parser = BithovenParser::new();
         let parsed = parser.parse(input).expect("Parser failed");
 
-        analyze(
+        analyze_paths(
             &parsed.output_script,
             parsed.input_stack,
+            &[],
             &parsed.pragma.target,
         )
         .expect("Analyze Error: ");
@@ -851,9 +1225,10 @@ mod tests {
         let parser = BithovenParser::new();
         let parsed = parser.parse(input).expect("Parser failed");
 
-        analyze(
+        analyze_paths(
             &parsed.output_script,
             parsed.input_stack,
+            &[],
             &parsed.pragma.target,
         )
         .expect("Analyze Error: ");
@@ -879,9 +1254,10 @@ mod tests {
         let parser = BithovenParser::new();
         let parsed = parser.parse(input).expect("Parser failed");
 
-        analyze(
+        analyze_paths(
             &parsed.output_script,
             parsed.input_stack,
+            &[],
             &parsed.pragma.target,
         )
         .expect("Analyze Error: ");
@@ -907,9 +1283,10 @@ mod tests {
         let parser = BithovenParser::new();
         let parsed = parser.parse(input).expect("Parser failed");
 
-        analyze(
+        analyze_paths(
             &parsed.output_script,
             parsed.input_stack,
+            &[],
             &parsed.pragma.target,
         )
         .expect("Analyze Error: ");
@@ -935,11 +1312,1561 @@ mod tests {
         let parser = BithovenParser::new();
         let parsed = parser.parse(input).expect("Parser failed");
 
-        analyze(
+        analyze_paths(
             &parsed.output_script,
             parsed.input_stack,
+            &[],
             &parsed.pragma.target,
         )
         .expect("Analyze Error: ");
     }
+
+    // Segwit program of the declarations, functions and input stacks, before its block.
+    fn program(declarations: &str, body: &str) -> String {
+        format!(
+            "pragma bithoven version 0.0.1;\npragma bithoven target segwit;\n{}\n{{\n{}\n}}",
+            declarations, body
+        )
+    }
+
+    // --- INLINE FUNCTION TESTS ---
+
+    const HASHLOCK_SIG: &str = r#"
+            fn hashlock_sig(p: string, s: signature, key: string) -> bool {
+                return sha256 p == "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f" && checksig (s, key);
+            }
+    "#;
+
+    #[test]
+    fn test_inline_function_ok() {
+        let source = program(
+            &format!("{}\n(condition: bool, secret: string, sig_alice: signature)\n(condition: bool, preimage: string, sig_bob: signature)", HASHLOCK_SIG),
+            r#"
+                if condition {
+                    return hashlock_sig(secret, sig_alice, "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");
+                } else {
+                    return hashlock_sig(preimage, sig_bob, "0345a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");
+                }
+            "#,
+        );
+        let res = crate::compile_program(source);
+        assert!(res.is_ok(), "Compile failed on valid input: {:?}", res);
+    }
+
+    #[test]
+    fn test_inline_function_same_code_as_expanded() {
+        let expanded = r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            (preimage: string, sig_alice: signature)
+            {
+                return sha256 preimage == "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f" && checksig (sig_alice, "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");
+            }
+        "#;
+        let inlined = program(
+            &format!("{}\n(preimage: string, sig_alice: signature)", HASHLOCK_SIG),
+            r#"return hashlock_sig(preimage, sig_alice, "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");"#,
+        );
+        assert_eq!(
+            crate::compile_program(expanded.to_string()).unwrap().hex(),
+            crate::compile_program(inlined).unwrap().hex()
+        );
+    }
+
+    #[test]
+    fn test_inline_function_err_consumption_order() {
+        // sig_alice is on top of stack, but preimage is consumed first in body.
+        let source = program(
+            &format!("{}\n(sig_alice: signature, preimage: string)", HASHLOCK_SIG),
+            r#"return hashlock_sig(preimage, sig_alice, "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");"#,
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::InvalidConsumptionOrder(_)));
+        // Error points at call site, and message mentions the definition.
+        assert_eq!(err.loc.line, 10);
+        let msg = format!("{:?}", err.kind);
+        assert!(msg.contains("in function \\\"hashlock_sig\\\" defined at line 4:"));
+    }
+
+    #[test]
+    fn test_inline_function_err_consumed_twice() {
+        let source = r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            fn twice(a: number, b: number) -> bool {
+                return a + b == 4;
+            }
+            (x: number, sig_alice: signature)
+            {
+                verify twice(x, x);
+                return checksig (sig_alice, "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");
+            }
+        "#;
+        let err = crate::compile_program(source.to_string()).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::VariableConsumed(_)));
+    }
+
+    #[test]
+    fn test_inline_function_err_definition() {
+        let cases: [(&str, &str, fn(&ErrorKind) -> bool); 9] = [
+            (
+                "fn f(a: bool) -> bool { return a; }\nfn f(a: bool) -> bool { return a; }",
+                "f(true)",
+                |kind| matches!(kind, ErrorKind::DuplicateFunction(_)),
+            ),
+            (
+                "fn f(a: bool, a: bool) -> bool { return a; }",
+                "f(true, true)",
+                |kind| matches!(kind, ErrorKind::DuplicateVariable(_)),
+            ),
+            (
+                "fn f(a: bool, b: bool) -> bool { return a; }",
+                "f(true, true)",
+                |kind| matches!(kind, ErrorKind::UnusedVariable(_)),
+            ),
+            ("fn f(a: bool) -> bool { return c; }", "f(true)", |kind| {
+                matches!(kind, ErrorKind::UndefinedVariable(_))
+            }),
+            (
+                "fn f(a: bool) -> bool { return f(a); }",
+                "f(true)",
+                |kind| matches!(kind, ErrorKind::RecursiveFunction(_)),
+            ),
+            ("fn f(a: bool) -> bool { return a; }", "g(true)", |kind| {
+                matches!(kind, ErrorKind::UndefinedFunction(_))
+            }),
+            (
+                "fn f(a: bool) -> bool { return a; }",
+                "f(true, false)",
+                |kind| matches!(kind, ErrorKind::InvalidArgument(_)),
+            ),
+            (
+                "fn f(a: number) -> bool { return a; }",
+                "f(\"abcd\")",
+                |kind| matches!(kind, ErrorKind::InvalidOperation(_)),
+            ),
+            (
+                "fn k(a: pubkey) -> pubkey { return a; }",
+                "true",
+                |kind| matches!(kind, ErrorKind::TypeMismatch(message) if message.contains("\"k\"")),
+            ),
+        ];
+        for (functions, call, expected) in cases {
+            let source = format!(
+                "pragma bithoven version 0.0.1;\npragma bithoven target segwit;\n{}\n(sig_alice: signature)\n{{\nverify {};\nreturn checksig (sig_alice, \"0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212\");\n}}",
+                functions, call
+            );
+            let kind = crate::compile_program(source).unwrap_err().kind;
+            assert!(expected(&kind), "{} / {}: {:?}", functions, call, kind);
+        }
+
+        // Call in place of pubkey is named, not dumped with its body.
+        let source = "pragma bithoven version 0.0.1;\npragma bithoven target segwit;\nfn k(a: string) -> string { return a; }\n(sig_alice: signature)\n{\nreturn checksig (sig_alice, k(\"0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212\"));\n}";
+        let err = crate::compile_program(source.to_string()).unwrap_err();
+        assert!(
+            matches!(&err.kind, ErrorKind::TypeMismatch(message) if message.starts_with("Public Key must be from string literal or pubkey argument but: call of \"k\".")),
+            "{:?}",
+            err
+        );
+    }
+
+    // --- IMPORT TESTS ---
+
+    // Write files into a fresh temporary directory, returning path of the first file.
+    fn write_files(dir: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(dir);
+        let _ = fs::remove_dir_all(&dir);
+        for (name, source) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, source).unwrap();
+        }
+        dir.join(files[0].0)
+    }
+
+    const ROOT_IMPORT: &str = r#"pragma bithoven version 0.0.1;
+pragma bithoven target segwit;
+import "lib/a.bithoven";
+(sig_alice: signature)
+{
+    return checksig (sig_alice, alice);
+}"#;
+
+    #[test]
+    fn test_import_example() {
+        let path = std::path::Path::new("example/import/htlc.bithoven");
+        let source = fs::read_to_string(path).unwrap();
+        let res = crate::compile_file(source, path);
+        assert!(res.is_ok(), "Compile failed on valid input: {:?}", res);
+    }
+
+    #[test]
+    fn test_import_diamond_ok() {
+        // Both a and b import c, which is loaded only once.
+        // Otherwise, function of c would be duplicate.
+        let path = write_files(
+            "bithoven_import_diamond",
+            &[
+                ("root.bithoven", ROOT_IMPORT),
+                ("lib/a.bithoven", "import \"b.bithoven\";\nimport \"c.bithoven\";\nconst alice: string = \"0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212\";"),
+                ("lib/b.bithoven", "import \"c.bithoven\";"),
+                ("lib/c.bithoven", "fn key(k: string) -> string { return k; }"),
+            ],
+        );
+        let res = crate::compile_file(fs::read_to_string(&path).unwrap(), &path);
+        assert!(res.is_ok(), "Compile failed on valid input: {:?}", res);
+    }
+
+    #[test]
+    fn test_import_err_cycle() {
+        let path = write_files(
+            "bithoven_import_cycle",
+            &[
+                ("root.bithoven", ROOT_IMPORT),
+                ("lib/a.bithoven", "import \"b.bithoven\";"),
+                ("lib/b.bithoven", "import \"a.bithoven\";"),
+            ],
+        );
+        let err = crate::compile_file(fs::read_to_string(&path).unwrap(), &path).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::CyclicImport(_)), "{:?}", err);
+        // Error is reported in the file closing the cycle.
+        assert!(err.loc.file.ends_with("b.bithoven"), "{:?}", err.loc);
+    }
+
+    #[test]
+    fn test_import_err_names_file() {
+        let path = write_files(
+            "bithoven_import_error_file",
+            &[
+                ("root.bithoven", ROOT_IMPORT),
+                ("lib/a.bithoven", "\nconst alice: number = \"abcd\";"),
+            ],
+        );
+        let err = crate::compile_file(fs::read_to_string(&path).unwrap(), &path).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::TypeMismatch(_)), "{:?}", err);
+        assert!(err.loc.file.ends_with("a.bithoven"), "{:?}", err.loc);
+        assert_eq!(err.loc.line, 2);
+        assert!(err.to_string().contains("a.bithoven:2:1"));
+    }
+
+    #[test]
+    fn test_import_err_missing_file() {
+        let path = write_files("bithoven_import_missing", &[("root.bithoven", ROOT_IMPORT)]);
+        let err = crate::compile_file(fs::read_to_string(&path).unwrap(), &path).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::ImportError(_)), "{:?}", err);
+        assert_eq!(err.loc.line, 3);
+    }
+
+    #[test]
+    fn test_import_err_without_file() {
+        let err = crate::compile_program(ROOT_IMPORT.to_string()).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::ImportError(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_constant() {
+        let source = ROOT_IMPORT.replace(
+            "import \"lib/a.bithoven\";",
+            "const alice: string = \"0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212\";",
+        );
+        let res = crate::compile_program(source.to_owned());
+        assert!(res.is_ok(), "Compile failed on valid input: {:?}", res);
+
+        // Stack item cannot take the name of constant.
+        let err = crate::compile_program(source.replace("sig_alice", "alice")).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::DuplicateVariable(_)),
+            "{:?}",
+            err
+        );
+    }
+
+    // --- WITNESS PUBKEY TESTS ---
+
+    const PK_HASH: &str = "d4eac89d456a48d2405645c4602264961215968b";
+
+    #[test]
+    fn test_witness_pubkey_commitment_first() {
+        let source = program(
+            "(pk: pubkey, sig: signature)",
+            &format!(
+                "verify ripemd160 sha256 pk == \"{}\";\nreturn checksig (sig, pk);",
+                PK_HASH
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert!(
+            output.asm().starts_with("OP_DUP OP_HASH160"),
+            "{}",
+            output.asm()
+        );
+        assert!(output.asm().ends_with("OP_EQUALVERIFY OP_CHECKSIG"));
+    }
+
+    #[test]
+    fn test_witness_pubkey_checksig_first() {
+        let source = program(
+            "(pk: pubkey, sig: signature)",
+            &format!(
+                "return checksig (sig, pk) && ripemd160 sha256 pk == \"{}\";",
+                PK_HASH
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert!(
+            output.asm().starts_with("OP_TUCK OP_CHECKSIG"),
+            "{}",
+            output.asm()
+        );
+    }
+
+    #[test]
+    fn test_witness_pubkey_err_uncommitted() {
+        // No commitment at all.
+        let source = program("(pk: pubkey, sig: signature)", "return checksig (sig, pk);");
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::UncommittedPubkey(_)),
+            "{:?}",
+            err
+        );
+
+        // Commitment which doesn't have to hold.
+        let source = program(
+            "(pk: pubkey, sig: signature)",
+            &format!(
+                "return checksig (sig, pk) || ripemd160 sha256 pk == \"{}\";",
+                PK_HASH
+            ),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::UncommittedPubkey(_)),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_witness_pubkey_err_usage() {
+        // Commitment only, without checksig.
+        let source = program(
+            "(pk: pubkey, sig: signature)",
+            &format!(
+                "verify ripemd160 sha256 pk == \"{}\";\nreturn checksig (sig, \"0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212\");",
+                PK_HASH
+            ),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::InvalidConsumptionOrder(_)),
+            "{:?}",
+            err
+        );
+
+        // Signature must be right under pubkey.
+        let source = program(
+            "(sig: signature, pk: pubkey)",
+            &format!(
+                "verify ripemd160 sha256 pk == \"{}\";\nreturn checksig (sig, pk);",
+                PK_HASH
+            ),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::InvalidConsumptionOrder(_)),
+            "{:?}",
+            err
+        );
+
+        // Multisig pushes pubkeys by script.
+        let source = program(
+            "(sig2: signature, sig: signature, pk: pubkey)",
+            &format!(
+                "return checksig [1, (sig2, \"0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212\"), (sig, pk)] && ripemd160 sha256 pk == \"{}\";",
+                PK_HASH
+            ),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::TypeMismatch(_)), "{:?}", err);
+
+        // Pubkey is not a number.
+        let source = program(
+            "(pk: pubkey, sig: signature)",
+            "return pk + 1 == 2 && checksig (sig, pk);",
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::InvalidOperation(_)),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_pubkey_constant_and_parameter() {
+        let source = format!(
+            r#"pragma bithoven version 0.0.1;
+pragma bithoven target segwit;
+const alice: pubkey = "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+fn rotate(s: signature, key: pubkey, digest: string) -> bool {{
+    return checksig (s, key) && ripemd160 sha256 key == digest;
+}}
+(condition: bool, pk: pubkey, sig: signature)
+(condition: bool, sig_alice: signature)
+{{
+    if condition {{
+        return rotate(sig, pk, "{}");
+    }} else {{
+        return checksig (sig_alice, alice);
+    }}
+}}"#,
+            PK_HASH
+        );
+        let res = crate::compile_program(source.to_owned());
+        assert!(res.is_ok(), "Compile failed on valid input: {:?}", res);
+
+        // Malformed pubkey constant.
+        let err = crate::compile_program(source.replace("0245a6", "0945a6")).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::MalformedPubkey(_)),
+            "{:?}",
+            err
+        );
+    }
+
+    // --- SIZED BYTES TESTS ---
+
+    const DIGEST: &str = "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f";
+    const CHECKSIG: &str =
+        "checksig (sig, \"0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212\");";
+
+    #[test]
+    fn test_bytes_size_guard() {
+        let source = program(
+            "(preimage: bytes32, sig: signature)",
+            &format!(
+                "verify sha256 preimage == \"{}\";\nreturn checksig (sig, \"0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212\");",
+                DIGEST
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert!(
+            output
+                .asm()
+                .starts_with("OP_SIZE OP_PUSHBYTES_1 20 OP_EQUALVERIFY OP_SHA256"),
+            "{}",
+            output.asm()
+        );
+    }
+
+    #[test]
+    fn test_bytes_err_length_mismatch() {
+        // Digest of sha256 is 32 bytes, but 20 bytes literal.
+        let source = program(
+            "(preimage: bytes32, sig: signature)",
+            &format!(
+                "return sha256 preimage == \"d4eac89d456a48d2405645c4602264961215968b\" && {}",
+                CHECKSIG
+            ),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::TypeMismatch(_)), "{:?}", err);
+
+        // Sized witness compared with literal of other length.
+        let source = program(
+            "(secret: bytes20, sig: signature)",
+            &format!("return secret == \"{}\" && {}", DIGEST, CHECKSIG),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::TypeMismatch(_)), "{:?}", err);
+
+        // Size out of range.
+        let source = program(
+            "(secret: bytes<521>, sig: signature)",
+            &format!("return len secret == 521 && {}", CHECKSIG),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::TypeMismatch(_)), "{:?}", err);
+
+        // Same name with different type.
+        let source = program(
+            "(condition: bool, secret: bytes20, sig: signature)\n(condition: bool, secret: string, sig: signature)",
+            &format!(
+                "if condition {{\nreturn len secret == 20 && {}\n}} else {{\nreturn len secret == 32 && {}\n}}",
+                CHECKSIG, CHECKSIG
+            ),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::TypeMismatch(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_bytes_constant_and_parameter() {
+        let source = format!(
+            r#"pragma bithoven version 0.0.1;
+pragma bithoven target segwit;
+const digest: bytes32 = "{}";
+fn hashlock(preimage: bytes32, expected: bytes32) -> bool {{
+    return sha256 sha256 preimage == expected;
+}}
+(preimage: bytes32, sig: signature)
+{{
+    return hashlock(preimage, digest) && {}
+}}"#,
+            DIGEST, CHECKSIG
+        );
+        let res = crate::compile_program(source.to_owned());
+        assert!(res.is_ok(), "Compile failed on valid input: {:?}", res);
+
+        // Constant of wrong length.
+        let err = crate::compile_program(source.replace("bytes32 = ", "bytes20 = ")).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::TypeMismatch(_)), "{:?}", err);
+
+        // Argument of wrong length.
+        let err = crate::compile_program(
+            source.replace("(preimage: bytes32, sig", "(preimage: bytes20, sig"),
+        )
+        .unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::TypeMismatch(_)), "{:?}", err);
+    }
+
+    // --- HASH OPCODE TESTS ---
+
+    #[test]
+    fn test_hash_opcodes() {
+        let source = program(
+            "(preimage: bytes32, sig: signature)",
+            &format!(
+                "verify hash256 preimage == \"{}\";\nreturn {}",
+                DIGEST, CHECKSIG
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert!(output.asm().contains("OP_HASH256"), "{}", output.asm());
+        assert!(output.warnings().is_empty());
+
+        let source = program(
+            "(pk: pubkey, sig: signature)",
+            &format!(
+                "verify hash160 pk == \"{}\";\nreturn checksig (sig, pk);",
+                PK_HASH
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert!(
+            output.asm().starts_with("OP_DUP OP_HASH160"),
+            "{}",
+            output.asm()
+        );
+    }
+
+    #[test]
+    fn test_hash_err_digest_length() {
+        for op in ["hash160", "sha1", "ripemd160"] {
+            let source = program(
+                "(preimage: string, sig: signature)",
+                &format!(
+                    "verify {} preimage == \"{}\";\nreturn {}",
+                    op, DIGEST, CHECKSIG
+                ),
+            );
+            let err = crate::compile_program(source).unwrap_err();
+            assert!(matches!(err.kind, ErrorKind::TypeMismatch(_)), "{:?}", err);
+        }
+
+        let source = program(
+            "(preimage: string, sig: signature)",
+            &format!(
+                "verify hash256 preimage == \"{}\";\nreturn {}",
+                PK_HASH, CHECKSIG
+            ),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::TypeMismatch(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_sha1_warning() {
+        let source = program(
+            "(preimage: string, sig: signature)",
+            &format!(
+                "verify sha1 preimage == \"{}\";\nreturn {}",
+                PK_HASH, CHECKSIG
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert!(output.asm().starts_with("OP_SHA1"), "{}", output.asm());
+        let warnings = output.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(warnings[0].kind, WarningKind::WeakHash(_)));
+        assert_eq!(warnings[0].loc.line, 5);
+    }
+
+    // --- RANGE CHECK TESTS ---
+
+    #[test]
+    fn test_within() {
+        let output = crate::compile_program(program(
+            "(price: number, sig: signature)",
+            &format!("verify price in 10..20;\nreturn {}", CHECKSIG),
+        ))
+        .unwrap();
+        assert_eq!(output.asm().matches("OP_WITHIN").count(), 1);
+        // Literal bounds are pushed in order, without alt stack.
+        assert!(
+            output
+                .asm()
+                .starts_with("OP_PUSHNUM_10 OP_PUSHBYTES_1 14 OP_WITHIN OP_VERIFY"),
+            "{}",
+            output.asm()
+        );
+
+        // Every form compiles to the same script.
+        for condition in [
+            "within(price, 10, 20)",
+            "price >= 10 && price < 20",
+            "10 <= price && 20 > price",
+            "price >= 10 && price <= 19",
+            "10 <= price && 19 >= price",
+        ] {
+            let res = crate::compile_program(program(
+                "(price: number, sig: signature)",
+                &format!("verify {};\nreturn {}", condition, CHECKSIG),
+            ))
+            .unwrap();
+            assert_eq!(res.asm(), output.asm(), "{}", condition);
+        }
+
+        // Upper bound of 4 bytes max can't be raised by one, so a copy of the variable is compared.
+        let output = crate::compile_program(program(
+            "(price: number, sig: signature)",
+            &format!(
+                "verify price >= 10 && price <= 2147483647;\nreturn {}",
+                CHECKSIG
+            ),
+        ))
+        .unwrap();
+        assert!(!output.asm().contains("OP_WITHIN"), "{}", output.asm());
+        assert!(
+            output
+                .asm()
+                .starts_with("OP_DUP OP_PUSHNUM_10 OP_GREATERTHANOREQUAL OP_SWAP"),
+            "{}",
+            output.asm()
+        );
+    }
+
+    #[test]
+    fn test_within_err() {
+        // Bound consuming the stack is not rewritten, so the variable is used twice.
+        let source = program(
+            "(price: number, floor: number, sig: signature)",
+            &format!("verify price >= floor && price < 20;\nreturn {}", CHECKSIG),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::VariableConsumed(_)),
+            "{:?}",
+            err
+        );
+
+        // Operand must be numeric.
+        let source = program(
+            "(preimage: string, sig: signature)",
+            &format!("verify preimage in 10..20;\nreturn {}", CHECKSIG),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::InvalidOperation(_)),
+            "{:?}",
+            err
+        );
+    }
+
+    // --- RELATIVE LOCKTIME TESTS ---
+
+    #[test]
+    fn test_older_time() {
+        // 7 days is exactly 1181.25 intervals, so rounded up to 1182 with type flag.
+        let output = crate::compile_program(program(
+            "(sig: signature)",
+            &format!("older 7 days;\nreturn {}", CHECKSIG),
+        ))
+        .unwrap();
+        assert_eq!(output.sequences(), vec![(1 << 22) | 1182]);
+        assert!(
+            output.asm().ends_with("OP_CSV OP_DROP OP_PUSHBYTES_33 0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212 OP_CHECKSIG"),
+            "{}",
+            output.asm()
+        );
+        let warnings = output.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(warnings[0].kind, WarningKind::LocktimeRounding(_)));
+
+        // Multiple of 512 seconds is exact.
+        let output = crate::compile_program(program(
+            "(sig: signature)",
+            &format!("older 1024 seconds;\nreturn {}", CHECKSIG),
+        ))
+        .unwrap();
+        assert_eq!(output.sequences(), vec![(1 << 22) | 2]);
+        assert!(output.warnings().is_empty());
+
+        // Block is the default unit.
+        let output = crate::compile_program(program(
+            "(sig: signature)",
+            &format!("older 144;\nreturn {}", CHECKSIG),
+        ))
+        .unwrap();
+        assert_eq!(output.sequences(), vec![144]);
+        let output = crate::compile_program(program(
+            "(sig: signature)",
+            &format!("older 144 blocks;\nreturn {}", CHECKSIG),
+        ))
+        .unwrap();
+        assert_eq!(output.sequences(), vec![144]);
+    }
+
+    #[test]
+    fn test_older_time_overflow() {
+        let err = crate::compile_program(program(
+            "(sig: signature)",
+            &format!("older 389 days;\nreturn {}", CHECKSIG),
+        ))
+        .unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::IntegerOverflow(_)),
+            "{:?}",
+            err
+        );
+        assert!(crate::compile_program(program(
+            "(sig: signature)",
+            &format!("older 388 days;\nreturn {}", CHECKSIG),
+        ))
+        .is_ok());
+    }
+
+    // --- ABSOLUTE LOCKTIME TESTS ---
+
+    const BOB_CHECKSIG: &str =
+        "checksig (sig_bob, \"0345a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212\");";
+
+    #[test]
+    fn test_after_height_and_date() {
+        // Height and time on different paths.
+        let source = program(
+            "(condition: bool, sig: signature)\n(condition: bool, sig_bob: signature)",
+            &format!(
+                "if condition {{\nafter height 900000;\nreturn {}\n}} else {{\nafter 2027-01-01T00:00:00Z;\nreturn {}\n}}",
+                CHECKSIG, BOB_CHECKSIG
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert!(output.asm().contains("OP_PUSHBYTES_4 80ec366b OP_CLTV"));
+        assert!(output.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_after_err_mixed() {
+        // Height in global scope, and time in a branch.
+        let source = program(
+            "(condition: bool, sig: signature)\n(condition: bool, sig_bob: signature)",
+            &format!(
+                "after 800000;\nif condition {{\nafter 1700000000;\nreturn {}\n}} else {{\nafter height 900000;\nreturn {}\n}}",
+                CHECKSIG, BOB_CHECKSIG
+            ),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::MixedLocktime(_)), "{:?}", err);
+        assert_eq!(err.loc.line, 8);
+
+        // Relative locktime too.
+        let source = program(
+            "(condition: bool, sig: signature)\n(condition: bool, sig_bob: signature)",
+            &format!(
+                "older 144;\nif condition {{\nolder 1 days;\nreturn {}\n}} else {{\nreturn {}\n}}",
+                CHECKSIG, BOB_CHECKSIG
+            ),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::MixedLocktime(_)), "{:?}", err);
+    }
+
+    #[test]
+    fn test_after_err_threshold() {
+        let err = crate::compile_program(program(
+            "(sig: signature)",
+            &format!("after height 500000000;\nreturn {}", CHECKSIG),
+        ))
+        .unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::IntegerOverflow(_)),
+            "{:?}",
+            err
+        );
+
+        let err = crate::compile_program(program(
+            "(sig: signature)",
+            &format!("after 1985-01-01T00:00:00Z;\nreturn {}", CHECKSIG),
+        ))
+        .unwrap_err();
+        assert!(
+            matches!(err.kind, ErrorKind::IntegerOverflow(_)),
+            "{:?}",
+            err
+        );
+
+        let err = crate::compile_program(program(
+            "(sig: signature)",
+            &format!("after 2027-13-01T00:00:00Z;\nreturn {}", CHECKSIG),
+        ))
+        .unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::ParseError(_)), "{:?}", err);
+        assert_eq!(err.loc.line, 5);
+    }
+
+    #[test]
+    fn test_after_threshold_warning() {
+        for (locktime, warned) in [
+            ("499999999", true),
+            ("520000000", true),
+            ("900000", false),
+            ("1700000000", false),
+        ] {
+            let output = crate::compile_program(program(
+                "(sig: signature)",
+                &format!("after {};\nreturn {}", locktime, CHECKSIG),
+            ))
+            .unwrap();
+            let warnings = output.warnings();
+            assert_eq!(!warnings.is_empty(), warned, "{}", locktime);
+            if warned {
+                assert!(matches!(
+                    warnings[0].kind,
+                    WarningKind::AmbiguousLocktime(_)
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn test_dead_path() {
+        // Interpreted on the path alone, before the use of variables is checked.
+        let bodies = [
+            format!("verify {}\nreturn false;", CHECKSIG),
+            format!("verify 1 == 2;\nreturn {}", CHECKSIG),
+            format!("verify x > 20;\nverify x < 10;\nreturn {}", CHECKSIG),
+            format!("verify x in 20..10;\nreturn {}", CHECKSIG),
+            format!(
+                "verify sha256 x == \"{}\";\nverify sha256 x == \"{}\";\nreturn {}",
+                DIGEST,
+                DIGEST.replace('5', "6"),
+                CHECKSIG
+            ),
+        ];
+        for body in bodies {
+            let source = program("(x: number, sig: signature)", &body);
+            let utxo = crate::parse(source, "").unwrap();
+            let paths = crate::path::enumerate_paths(&utxo.output_script);
+            let warnings = crate::interpret::dead_path_warnings(&paths, &[]);
+            assert_eq!(warnings.len(), 1, "{}", body);
+            assert!(matches!(warnings[0].kind, WarningKind::DeadPath(_)));
+        }
+
+        // Dead path is warned, and the contract still compiles.
+        let dead_paths = |source: String| -> Vec<CompileWarning> {
+            crate::compile_program(source)
+                .unwrap()
+                .warnings()
+                .into_iter()
+                .filter(|w| matches!(w.kind, WarningKind::DeadPath(_)))
+                .collect()
+        };
+        let source = program(
+            "(x: number, sig: signature)",
+            &format!("verify x in 20..10;\nreturn {}", CHECKSIG),
+        );
+        assert_eq!(dead_paths(source).len(), 1);
+        let source = program(
+            "(x: number, sig: signature)\n(x: number, sig: signature)",
+            &format!(
+                "if x == 1 {{\nreturn {}\n}} else {{\nverify 1 == 2;\nreturn {}\n}}",
+                CHECKSIG, CHECKSIG
+            ),
+        );
+        let warnings = dead_paths(source);
+        assert_eq!(warnings.len(), 1);
+        assert!(format!("{:?}", warnings[0].kind).contains("path 1 bound to stack 1"));
+
+        // Condition of constant value takes one block only.
+        let source = program(
+            "(sig: signature)\n(sig: signature)",
+            &format!(
+                "if 2 > 1 {{\nreturn {}\n}} else {{\nreturn {}\n}}",
+                CHECKSIG, CHECKSIG
+            ),
+        );
+        let warnings = dead_paths(source);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].loc.line, 6);
+    }
+
+    #[test]
+    fn test_dead_path_alive() {
+        let bodies = [
+            format!("verify x > 10 && y < 20;\nreturn {}", CHECKSIG),
+            format!("verify x in 10..20;\nverify y != 15;\nreturn {}", CHECKSIG),
+        ];
+        for body in bodies {
+            let source = program("(x: number, y: number, sig: signature)", &body);
+            crate::compile_program(source).unwrap();
+        }
+
+        let source = program(
+            "(x: number, y: number, sig: signature)\n(x: number, z: number, sig_bob: signature)",
+            &format!(
+                "if x == 1 {{\nverify y > 1;\nreturn {}\n}} else {{\nverify z < 1;\nreturn {}\n}}",
+                CHECKSIG, BOB_CHECKSIG
+            ),
+        );
+        crate::compile_program(source).unwrap();
+    }
+
+    #[test]
+    fn test_dead_path_unconsumed_stack() {
+        // Signature is left on the stack.
+        let source = program("(sig: signature)", "return true;");
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::DeadPath(_)), "{:?}", err);
+    }
+
+    // --- CLEANSTACK TESTS ---
+
+    #[test]
+    fn test_simulate_stack_depth() {
+        use crate::stack::simulate;
+
+        let script = |asm: &str| -> Vec<u8> {
+            let mut builder = bitcoin::script::Builder::new();
+            for op in asm.split_whitespace() {
+                builder = match op {
+                    "1" => builder.push_int(1),
+                    "2" => builder.push_int(2),
+                    _ => builder.push_opcode(match op {
+                        "IF" => bitcoin::opcodes::all::OP_IF,
+                        "ELSE" => bitcoin::opcodes::all::OP_ELSE,
+                        "ENDIF" => bitcoin::opcodes::all::OP_ENDIF,
+                        "DUP" => bitcoin::opcodes::all::OP_DUP,
+                        "DROP" => bitcoin::opcodes::all::OP_DROP,
+                        "NOTIF" => bitcoin::opcodes::all::OP_NOTIF,
+                        "SIZE" => bitcoin::opcodes::all::OP_SIZE,
+                        "SWAP" => bitcoin::opcodes::all::OP_SWAP,
+                        "TOALT" => bitcoin::opcodes::all::OP_TOALTSTACK,
+                        "FROMALT" => bitcoin::opcodes::all::OP_FROMALTSTACK,
+                        "CHECKSIG" => bitcoin::opcodes::all::OP_CHECKSIG,
+                        "CHECKMULTISIG" => bitcoin::opcodes::all::OP_CHECKMULTISIG,
+                        _ => panic!("{}", op),
+                    }),
+                };
+            }
+            builder.into_bytes()
+        };
+
+        assert_eq!(simulate(&script("SIZE SWAP DROP"), 1, &[]), Ok(1));
+        assert_eq!(
+            simulate(&script("TOALT 1 FROMALT SWAP DROP"), 1, &[]),
+            Ok(1)
+        );
+        // Branch not taken is skipped.
+        let branch = script("IF DUP ELSE 1 2 ENDIF");
+        assert_eq!(simulate(&branch, 2, &[true]), Ok(2));
+        assert_eq!(simulate(&branch, 2, &[false]), Ok(3));
+        // Condition is whether the first block runs, for OP_NOTIF too.
+        let branch = script("NOTIF DUP ELSE 1 2 ENDIF");
+        assert_eq!(simulate(&branch, 2, &[true]), Ok(2));
+        assert_eq!(simulate(&branch, 2, &[false]), Ok(3));
+        // 1-of-2 multisig pops the dummy too.
+        let multisig = script("1 1 1 2 CHECKMULTISIG");
+        assert_eq!(simulate(&multisig, 2, &[]), Ok(1));
+        assert!(simulate(&multisig, 1, &[]).is_err());
+        assert!(simulate(&script("CHECKSIG"), 1, &[]).is_err());
+    }
+
+    #[test]
+    fn test_clean_stack() {
+        let source = program("(sig: signature)", &format!("return {}", CHECKSIG));
+        let mut parsed = BithovenParser::new().parse(&source).unwrap();
+        let script = crate::peephole::encode(&crate::compile::codegen(
+            parsed.output_script.clone(),
+            &parsed.pragma.target,
+            &parsed.input_stack,
+            crate::compile::Encoding::default(),
+        ));
+        let paths = crate::path::enumerate_paths(&parsed.output_script);
+        crate::stack::check_clean_stack(
+            &script,
+            &paths,
+            &parsed.input_stack,
+            &[],
+            &Target::Segwit,
+            &Default::default(),
+        )
+        .unwrap();
+
+        // Item left under the result of checksig.
+        parsed.input_stack[0].insert(0, stack_param("extra", Type::Number));
+        let err = crate::stack::check_clean_stack(
+            &script,
+            &paths,
+            &parsed.input_stack,
+            &[],
+            &Target::Segwit,
+            &Default::default(),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(&err.kind, ErrorKind::CleanStack(msg) if msg.contains("2 left")),
+            "{:?}",
+            err
+        );
+        // Legacy only needs the result on top.
+        crate::stack::check_clean_stack(
+            &script,
+            &paths,
+            &parsed.input_stack,
+            &[],
+            &Target::Legacy,
+            &Default::default(),
+        )
+        .unwrap();
+
+        // Dummy of OP_CHECKMULTISIG is supplied by witness, though not declared.
+        let source = fs::read_to_string("example/inheritance.bithoven").unwrap();
+        crate::compile_program(source).unwrap();
+        // Signature declared for the unsigned key of 2-of-3 is the dummy item.
+        let multisig = |m: usize| {
+            program(
+                "(sig_a: signature, sig_b: signature, sig_c: signature)",
+                &format!(
+                    "return checksig [{}, (sig_a, {:?}), (sig_b, {:?}), (sig_c, {:?})];",
+                    m,
+                    "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212",
+                    "0345a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212",
+                    "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+                ),
+            )
+        };
+        let output = crate::compile_program(multisig(2)).unwrap();
+        assert!(output.asm().ends_with("OP_PUSHNUM_3 OP_CHECKMULTISIG"));
+        assert!(output.optimizations().is_empty());
+        // 1-of-3 leaves one of them, which names OP_CHECKMULTISIG and its location.
+        let err = crate::compile_program(multisig(1)).unwrap_err();
+        assert!(
+            matches!(&err.kind, ErrorKind::CleanStack(msg)
+                if msg.contains("1-of-3 multisig at line 5:17 takes 1 signatures plus a dummy item, 2 of the 3")
+                    && msg.contains("-Os")),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_witness_weight() {
+        use crate::compile::Encoding;
+        use crate::stack::witness_weight;
+
+        let key = |prefix: &str| {
+            format!(
+                "{}45a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212",
+                prefix
+            )
+        };
+        let source = format!(
+            "pragma bithoven version 0.0.1;\npragma bithoven target legacy;\n(sig_a: signature, sig_b: signature, sig_c: signature)\n{{\nreturn checksig [1, (sig_a, \"{}\"), (sig_b, \"{}\"), (sig_c, \"{}\")];\n}}",
+            key("02"),
+            key("03"),
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+        );
+        let script = crate::compile_program(source.to_owned()).unwrap().bytes();
+        let utxo = crate::parse(source.to_owned(), "").unwrap();
+        let paths = crate::path::enumerate_paths(&utxo.output_script);
+        let weight = |script: &[u8], encoding: &Encoding| {
+            witness_weight(script, &paths, &utxo.input_stack, &Target::Legacy, encoding)
+        };
+
+        // One signature of the three keys, and the empty slots of the other two, one of which is the dummy item.
+        let multisig = Encoding::candidates(OptimizeLevel::O1, &Target::Legacy)[0];
+        assert_eq!(script.len(), 105);
+        assert_eq!(weight(&script, &multisig), (105 + 73 + 2) * 4);
+        let chain = Encoding {
+            checksig_chain: true,
+            ..multisig
+        };
+        assert_eq!(weight(&script, &chain), (105 + 73 + 2) * 4);
+
+        // Every slot of 3-of-3 is signed, so the dummy item is one more.
+        let source = source.replace("checksig [1,", "checksig [3,");
+        let utxo = crate::parse(source, "").unwrap();
+        let paths = crate::path::enumerate_paths(&utxo.output_script);
+        let weight = |encoding: &Encoding| {
+            witness_weight(
+                &script,
+                &paths,
+                &utxo.input_stack,
+                &Target::Legacy,
+                encoding,
+            )
+        };
+        assert_eq!(weight(&multisig), (105 + 3 * 73 + 1) * 4);
+        // Chain of OP_CHECKSIG takes no dummy item.
+        assert_eq!(weight(&chain), (105 + 3 * 73) * 4);
+    }
+
+    #[test]
+    fn test_minimal_if_condition() {
+        let stack = "(n: number, sig: signature)\n(n: number, sig_bob: signature)";
+        let body = format!(
+            "if n {{\nreturn {}\n}} else {{\nreturn {}\n}}",
+            CHECKSIG, BOB_CHECKSIG
+        );
+        // Number is normalised before OP_IF under MINIMALIF.
+        let output = crate::compile_program(program(stack, &body)).unwrap();
+        assert!(
+            output.asm().starts_with("OP_0NOTEQUAL OP_IF"),
+            "{}",
+            output.asm()
+        );
+        // Legacy OP_IF takes any number.
+        let legacy = program(stack, &body).replace("target segwit", "target legacy");
+        let output = crate::compile_program(legacy).unwrap();
+        assert!(output.asm().starts_with("OP_IF"), "{}", output.asm());
+
+        // Byte string can't be if condition under MINIMALIF.
+        let source = program(
+            "(preimage: string, sig: signature)\n(preimage: string, sig_bob: signature)",
+            &format!(
+                "if preimage {{\nreturn {}\n}} else {{\nreturn {}\n}}",
+                CHECKSIG, BOB_CHECKSIG
+            ),
+        );
+        let err = crate::compile_program(source).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::TypeMismatch(_)), "{:?}", err);
+        assert_eq!(err.loc.line, 6);
+    }
+
+    #[test]
+    fn test_boolean_encoding() {
+        // `a` is taken by OP_IF as is, while `b` is taken by OP_VERIFY.
+        let source = program(
+            "(a: bool, b: bool, sig: signature)\n(a: bool, sig_bob: signature)",
+            &format!(
+                "if a {{\nverify b;\nreturn {}\n}} else {{\nreturn {}\n}}",
+                CHECKSIG, BOB_CHECKSIG
+            ),
+        );
+        let paths = crate::compile_program(source.clone()).unwrap().paths();
+        assert_eq!(
+            paths[0].booleans,
+            vec![
+                BooleanInput {
+                    name: "a".to_string(),
+                    encoding: BooleanEncoding::MinimalIf
+                },
+                BooleanInput {
+                    name: "b".to_string(),
+                    encoding: BooleanEncoding::Number
+                },
+            ]
+        );
+        assert_eq!(paths[1].booleans.len(), 1);
+
+        // Legacy OP_IF takes any number.
+        let legacy = source.replace("target segwit", "target legacy");
+        let paths = crate::compile_program(legacy).unwrap().paths();
+        assert!(paths
+            .iter()
+            .flat_map(|path| &path.booleans)
+            .all(|boolean| boolean.encoding == BooleanEncoding::Number));
+    }
+
+    #[test]
+    fn test_malleability() {
+        // Owner path is signed, while the others proceed on failed checksig and hashlock.
+        let source = fs::read_to_string("example/inheritance.bithoven").unwrap();
+        let output = crate::compile_program(source).unwrap();
+        let paths = output.paths();
+        assert!(paths[0].malleability.is_empty(), "{:?}", paths[0]);
+        assert_eq!(paths[1].malleability.len(), 1);
+        assert!(paths[1].malleability[0].starts_with("Failed checksig"));
+        assert_eq!(paths[2].malleability.len(), 4);
+        assert!(paths[2].malleability[3].starts_with("Size of preimage \"secret\""));
+        let warnings = output.warnings();
+        assert_eq!(
+            warnings
+                .iter()
+                .filter(|w| matches!(w.kind, WarningKind::Malleability(_)))
+                .count(),
+            4
+        );
+
+        // Selector can be switched to the path requiring no signature.
+        let bob = BOB_CHECKSIG.trim_end_matches(';');
+        let source = program(
+            "(a: bool, sig: signature)\n(a: bool, sig_bob: signature)\n(a: bool, sig_bob: signature)",
+            &format!(
+                "if a {{\nreturn {}\n}} else {{\nif {} {{\nreturn true;\n}} else {{\nolder 100;\nreturn true;\n}}\n}}",
+                CHECKSIG, bob
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        let paths = output.paths();
+        assert!(paths[0].malleability[0].starts_with("Selector \"a\""));
+        assert!(paths[1].malleability[0].starts_with("Selector \"a\""));
+        assert_eq!(paths[2].malleability.len(), 1);
+        assert!(paths[2].malleability[0].starts_with("Failed checksig"));
+        let warnings = output.warnings();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings[0].loc.line, 7);
+
+        // Default arm is taken by any value but 0 and 1, while the other arms take one each.
+        let source = fs::read_to_string("example/match.bithoven").unwrap();
+        let output = crate::compile_program(source).unwrap();
+        let paths = output.paths();
+        assert!(paths[0].malleability.is_empty(), "{:?}", paths[0]);
+        assert!(paths[1].malleability.is_empty(), "{:?}", paths[1]);
+        assert_eq!(paths[2].malleability.len(), 1);
+        assert!(paths[2].malleability[0].starts_with("Default arm"));
+        let warnings = output.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].loc.line, 9);
+    }
+
+    #[test]
+    fn test_path_policy() {
+        let source = fs::read_to_string("example/inheritance.bithoven").unwrap();
+        let paths = crate::compile_program(source).unwrap().paths();
+        let owner = "03daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee8729";
+        assert_eq!(
+            paths[0].policy,
+            PathPolicy {
+                signatures: vec![SignaturePolicy {
+                    threshold: 1,
+                    pubkeys: vec![owner.to_string()]
+                }],
+                ..PathPolicy::default()
+            }
+        );
+        assert_eq!(
+            paths[1].policy.preimages,
+            vec![PreimagePolicy {
+                preimage: "secret".to_string(),
+                hash: "sha256".to_string(),
+                digest: "daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee8729"
+                    .to_string()
+            }]
+        );
+        assert_eq!(
+            paths[1].policy.timelocks,
+            vec![TimelockPolicy::RelativeBlocks(1000)]
+        );
+        // Owner's checksig fails on heir path.
+        assert_eq!(
+            paths[1].policy.conditions,
+            vec![format!("!checksig(sig_owner, {:?})", owner)]
+        );
+        assert_eq!(paths[2].policy.signatures[0].threshold, 2);
+        assert_eq!(paths[2].policy.signatures[0].pubkeys.len(), 2);
+        assert_eq!(paths[2].policy.timelocks.len(), 2);
+
+        // Witness pubkey and extra numeric condition.
+        let source = program(
+            "(price: number, pk: pubkey, sig: signature)",
+            &format!(
+                "verify price in 10..20;\nafter height 800000;\nverify ripemd160 sha256 pk == \"{}\";\nreturn checksig (sig, pk);",
+                PK_HASH
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        let policy = &output.paths()[0].policy;
+        assert_eq!(policy.signatures[0].pubkeys, vec!["<pk>".to_string()]);
+        assert_eq!(policy.preimages[0].hash, "ripemd160 sha256");
+        assert_eq!(
+            policy.timelocks,
+            vec![TimelockPolicy::AbsoluteHeight(800000)]
+        );
+        assert_eq!(policy.conditions, vec!["price in 10..20".to_string()]);
+        assert!(output.explain().contains("From block height 800000"));
+    }
+
+    #[test]
+    fn test_cfg() {
+        let source = fs::read_to_string("example/nestedif.bithoven").unwrap();
+        let output = crate::compile_program(source).unwrap();
+        let dot = output.cfg_dot();
+        assert!(dot.starts_with("digraph cfg {"), "{}", dot);
+        assert_eq!(dot.matches("shape=diamond").count(), 2);
+        assert_eq!(dot.matches("shape=ellipse").count(), 3);
+        // Constant condition labels its edges by value alone.
+        assert!(dot.contains("n3 -> n6 [label=\"false\"]"), "{}", dot);
+        // condition, sig_alice and sig_carol with their length prefix.
+        assert!(dot.contains("stack 0\\n(condition: bool, sig_alice: signature, sig_carol: signature)\\nwitness ~148 bytes"), "{}", dot);
+
+        let mermaid = output.cfg_mermaid();
+        assert!(mermaid.starts_with("flowchart TD\n"), "{}", mermaid);
+        assert!(mermaid.contains("-->|\"condition = true\"|"), "{}", mermaid);
+        // Quote of string literal is escaped in label.
+        assert!(mermaid.contains("#quot;0245a6b3"), "{}", mermaid);
+
+        // Shared tail is repeated under each branch, so every leaf is one path.
+        let source = program(
+            "(a: bool, sig: signature)\n(a: bool, sig: signature)",
+            &format!(
+                "if a {{\nolder 100;\n}} else {{\nafter height 800000;\n}}\nreturn {}",
+                CHECKSIG
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        let dot = output.cfg_dot();
+        assert_eq!(dot.matches("shape=ellipse").count(), 2);
+        assert_eq!(dot.matches("return checksig").count(), 2);
+    }
+
+    #[test]
+    fn test_miniscript_export() {
+        let source = fs::read_to_string("example/htlc.bithoven").unwrap();
+        let export = crate::compile_program(source).unwrap().miniscript();
+        // Double sha256 is hash256, and selector of if is or_i.
+        assert_eq!(
+            export.descriptor.unwrap(),
+            "wsh(or_i(and_v(v:older(1000),pk(0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212)),and_v(v:hash256(53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f),pk(0345a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212))))"
+        );
+        assert!(export.blockers.is_empty());
+
+        // Failed checksig takes the else branch.
+        let source = fs::read_to_string("example/inheritance.bithoven").unwrap();
+        let export = crate::compile_program(source).unwrap().miniscript();
+        assert!(export
+            .miniscript
+            .unwrap()
+            .starts_with("or_i(pk(03daed4f2be3a8bf278e70132fb0beb7522f570e144bf615c07e996d443dee8729),and_v(v:older(1000),"));
+
+        // Multisig of x-only keys in tapscript.
+        let xonly = "45a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+        let source = format!(
+            "pragma bithoven version 0.0.1;\npragma bithoven target taproot;\n(sig_alice: signature, sig_bob: signature)\n{{\nreturn checksig [2, (sig_alice, \"{}\"), (sig_bob, \"{}\")];\n}}",
+            xonly, xonly
+        );
+        let export = crate::compile_program(source).unwrap().miniscript();
+        assert_eq!(
+            export.descriptor.unwrap(),
+            format!(
+                "tr({},multi_a(2,{},{}))",
+                crate::miniscript::NUMS_KEY,
+                xonly,
+                xonly
+            )
+        );
+
+        // Witness pubkey and numeric condition have no counterpart.
+        let source = fs::read_to_string("example/p2pkh.bithoven").unwrap();
+        let export = crate::compile_program(source).unwrap().miniscript();
+        assert_eq!(export.miniscript, None);
+        assert_eq!(export.blockers.len(), 1);
+        assert!(export.blockers[0].contains("supplied by witness"));
+        let export = crate::compile_program(program(
+            "(price: number, sig: signature)",
+            &format!("verify price in 10..20;\nreturn {}", CHECKSIG),
+        ))
+        .unwrap()
+        .miniscript();
+        assert_eq!(export.blockers.len(), 1);
+        assert!(export.blockers[0].starts_with("line 5:"), "{:?}", export);
+    }
+
+    #[test]
+    fn test_from_policy() {
+        let alice = "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+        let bob = "0345a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+        let carol = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+        // More probable branch comes first, and locktime before checksig.
+        let policy = format!("or(1@pk({}), 9@and(pk({}), older(1000)))", alice, bob);
+        let source = crate::from_policy(policy, "segwit".to_string()).unwrap();
+        assert!(source.contains(&format!(
+            "(branch0: bool, sig_1: signature)\n(branch0: bool, sig_0: signature)\n{{\n    if branch0 {{\n        // Probability 0.90\n        older 1000;\n        return checksig (sig_1, \"{}\");",
+            bob
+        )), "{}", source);
+        let output = crate::compile_program(source).unwrap();
+        assert_eq!(output.paths()[0].witness, vec!["true", "<sig_1>"]);
+
+        // Threshold of keys is a path for each pair of keys out of tapscript.
+        let policy = format!(
+            "and(thresh(2, pk({}), pk({}), pk({})), sha256({}))",
+            alice, bob, carol, "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f"
+        );
+        let source = crate::from_policy(policy, "segwit".to_string()).unwrap();
+        let output = crate::compile_program(source).unwrap();
+        assert_eq!(output.paths().len(), 3);
+        assert_eq!(output.paths()[0].policy.signatures[0].threshold, 2);
+        assert_eq!(output.paths()[0].policy.preimages.len(), 1);
+
+        // Named key is left as constant.
+        let source = crate::from_policy("pk(A)".to_string(), "taproot".to_string()).unwrap();
+        assert!(source.contains("const A: pubkey = \"<A>\";"));
+        assert!(source.contains("pragma bithoven target taproot;"));
+        let source = crate::from_policy(
+            "and(sha256(H),hash160(G))".to_string(),
+            "segwit".to_string(),
+        )
+        .unwrap();
+        assert!(source.contains("const H: bytes32 = \"<H>\";"), "{}", source);
+        assert!(source.contains("const G: bytes20 = \"<G>\";"), "{}", source);
+
+        // Error points at the token.
+        let err = crate::from_policy("or(pk(A),)".to_string(), "segwit".to_string()).unwrap_err();
+        assert_eq!((err.loc.line, err.loc.column), (1, 10));
+        let err = crate::from_policy("thresh(3,pk(A),pk(B))".to_string(), "segwit".to_string())
+            .unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::ParseError(_)));
+
+        // Relative locktime BIP 68 can't encode is not truncated.
+        let err = crate::from_policy("older(70000)".to_string(), "segwit".to_string()).unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::ParseError(_)));
+        let source =
+            crate::from_policy("older(4194305)".to_string(), "segwit".to_string()).unwrap();
+        assert!(source.contains("older 512 seconds;"), "{}", source);
+
+        // Weights are positive, and their sum doesn't overflow.
+        let err = crate::from_policy("or(0@pk(A),0@pk(B))".to_string(), "segwit".to_string())
+            .unwrap_err();
+        assert!(matches!(err.kind, ErrorKind::ParseError(_)));
+        let source = crate::from_policy(
+            "or(4294967295@pk(A),1@pk(B))".to_string(),
+            "segwit".to_string(),
+        )
+        .unwrap();
+        assert!(source.contains("// Probability 1.00"), "{}", source);
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let source = program(
+            "(n: number, flag: bool, sig: signature)",
+            &format!(
+                "verify n + 0 == 1 + 2;\nverify !!flag;\nverify sha256 \"abc\" == \"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\";\nreturn {}",
+                CHECKSIG
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        let messages: Vec<String> = output
+            .optimizations()
+            .iter()
+            .map(|o| o.message.to_owned())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Simplified `n + 0` into `n`.",
+                "Folded `1 + 2` into `3`.",
+                "Simplified `!(!flag)` into `flag`.",
+                "Folded `sha256 \"abc\"` into `\"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\"`.",
+                "Folded `\"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\" == \"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad\"` into `true`.",
+                "Removed `verify true;`.",
+            ]
+        );
+        assert_eq!(output.optimizations()[0].loc.line, 5);
+        assert!(output
+            .asm()
+            .starts_with("OP_PUSHNUM_3 OP_EQUALVERIFY OP_VERIFY OP_PUSHBYTES_33"));
+
+        // Double not of number casts it to 0 or 1 for comparison, and sum beyond 4 bytes is left to script.
+        let source = program(
+            "(n: number, m: number, sig: signature)",
+            &format!(
+                "verify !!n == 1;\nverify m == 2147483647 + 1;\nreturn {}",
+                CHECKSIG
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert!(output.optimizations().is_empty());
+        assert!(output.asm().contains("OP_NOT OP_NOT"));
+        assert!(output.asm().contains("OP_ADD"));
+
+        // Double not of bool taken by OP_IF is kept under MINIMALIF, as the path reports any number for it.
+        let stack = "(b: bool, sig: signature)\n(b: bool, sig_bob: signature)";
+        let body = format!(
+            "if !!b {{\nreturn {}\n}} else {{\nreturn {}\n}}",
+            CHECKSIG, BOB_CHECKSIG
+        );
+        let output = crate::compile_program(program(stack, &body)).unwrap();
+        assert!(output.optimizations().is_empty());
+        assert!(
+            output.asm().starts_with("OP_NOT OP_NOTIF"),
+            "{}",
+            output.asm()
+        );
+        assert_eq!(
+            output.paths()[0].booleans[0].encoding,
+            BooleanEncoding::Number
+        );
+        let legacy = program(stack, &body).replace("target segwit", "target legacy");
+        let output = crate::compile_program(legacy).unwrap();
+        assert_eq!(output.optimizations().len(), 1);
+        assert!(output.asm().starts_with("OP_IF"), "{}", output.asm());
+
+        // Verify of true ending a block is removed, if the tail runs after it.
+        let source = program(
+            "(c: bool, sig: signature)\n(c: bool, sig: signature)",
+            &format!(
+                "if c {{\nolder 10;\n}} else {{\nverify 1 == 1;\n}}\nreturn {}",
+                CHECKSIG
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert_eq!(output.optimizations()[1].message, "Removed `verify true;`.");
+        assert!(
+            output
+                .asm()
+                .starts_with("OP_IF OP_PUSHNUM_10 OP_CSV OP_DROP OP_ENDIF OP_PUSHBYTES_33"),
+            "{}",
+            output.asm()
+        );
+    }
+
+    #[test]
+    fn test_operand_order() {
+        let asm = |stack: &str, condition: &str| {
+            let source = program(
+                stack,
+                &format!("verify {};\nreturn {}", condition, CHECKSIG),
+            );
+            crate::compile_program(source).unwrap().asm()
+        };
+        let numbers = "(a: number, b: number, c: number, d: number, sig: signature)";
+        let checksig = "OP_PUSHBYTES_33 0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212 OP_CHECKSIG";
+
+        // Literal rhs is pushed after lhs.
+        assert!(asm(numbers, "a + b + c + d == 10")
+            .starts_with("OP_ADD OP_ADD OP_ADD OP_PUSHNUM_10 OP_EQUALVERIFY"));
+        // Literal lhs is pushed after rhs, with the comparison mirrored.
+        assert!(asm(numbers, "10 < a + b + c + d")
+            .starts_with("OP_ADD OP_ADD OP_ADD OP_PUSHNUM_10 OP_GREATERTHAN OP_VERIFY"));
+        // Witness item right under lhs is swapped in only for sub.
+        assert_eq!(
+            asm(numbers, "a - b - c - d == 0"),
+            format!(
+                "OP_SWAP OP_SUB OP_SWAP OP_SUB OP_SWAP OP_SUB OP_0 OP_EQUALVERIFY {}",
+                checksig
+            )
+        );
+        // Operands both taking witness items keep lhs in alt stack, and equality needs no swap back.
+        assert_eq!(
+            asm(numbers, "a + b == c + d"),
+            format!(
+                "OP_ADD OP_TOALTSTACK OP_ADD OP_FROMALTSTACK OP_EQUALVERIFY {}",
+                checksig
+            )
+        );
+    }
 }
@@ -3,8 +3,13 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, PartialEq)]
 pub struct Bithoven {
     pub pragma: Pragma,
+    pub imports: Vec<Import>,
+    pub constants: Vec<Constant>,
+    pub functions: Vec<Function>,
     pub input_stack: Vec<Vec<StackParam>>,
     pub output_script: Vec<Statement>,
+    /// Name of each spending path, empty for anonymous stacks.
+    pub path_names: Vec<String>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -12,6 +17,19 @@ pub struct Pragma {
     pub language: String,
     pub version: String,
     pub target: Target,
+    pub optimize: OptimizeLevel,
+}
+
+/// Optimization level, by `pragma bithoven optimize O0|O1|Os;` or `-O0|-O1|-Os`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OptimizeLevel {
+    /// Script as generated for each statement, one-to-one with the source for audit.
+    O0,
+    /// Constant folding, peephole rules and branch factoring.
+    #[default]
+    O1,
+    /// Also encoding of the least witness-weighted cost, e.g. multisig by OP_CHECKSIG of each key.
+    Os,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -21,6 +39,13 @@ pub enum Target {
     Taproot,
 }
 
+impl Target {
+    /// Whether OP_IF takes only empty or 0x01, by consensus in tapscript and by policy in segwit.
+    pub fn is_minimal_if(&self) -> bool {
+        !matches!(self, Target::Legacy)
+    }
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct StackParam {
     pub loc: Location,
@@ -28,12 +53,119 @@ pub struct StackParam {
     pub ty: Type,
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+// Imported file, which shares constants and functions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Library {
+    pub imports: Vec<Import>,
+    pub constants: Vec<Constant>,
+    pub functions: Vec<Function>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Import {
+    pub loc: Location,
+    pub path: String,
+}
+
+// Constant. Value is a literal, substituted at every use.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Constant {
+    pub loc: Location,
+    pub identifier: Identifier,
+    pub ty: Type,
+    pub value: Expression,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Definition {
+    Constant(Constant),
+    Function(Function),
+}
+
+pub fn split_definitions(definitions: Vec<Definition>) -> (Vec<Constant>, Vec<Function>) {
+    let mut constants = vec![];
+    let mut functions = vec![];
+    for definition in definitions {
+        match definition {
+            Definition::Constant(c) => constants.push(c),
+            Definition::Function(f) => functions.push(f),
+        }
+    }
+    (constants, functions)
+}
+
+// Named spending path, e.g. path refund(sig_alice: signature) { ... }
+#[derive(Clone, Debug, PartialEq)]
+pub struct NamedPath {
+    pub loc: Location,
+    pub identifier: Identifier,
+    pub params: Vec<StackParam>,
+    pub body: Vec<Statement>,
+}
+
+// Named paths are the stacks and the if tree written by hand.
+// Path k but the last is taken by its selector `name_k: bool` being true,
+// after the selectors of the paths before it are false.
+pub fn desugar_paths(paths: Vec<NamedPath>) -> (Vec<Vec<StackParam>>, Vec<Statement>) {
+    let selector = |path: &NamedPath| StackParam {
+        loc: path.loc.to_owned(),
+        identifier: path.identifier.to_owned(),
+        ty: Type::Boolean,
+    };
+    let last = paths.len() - 1;
+    let input_stack = paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            // Stack is kept bottom first, so the selectors go on top of the params.
+            let mut stack = path.params.to_owned();
+            stack.extend(paths[..(i + 1).min(last)].iter().rev().map(selector));
+            stack
+        })
+        .collect();
+
+    let mut paths = paths.into_iter().rev();
+    let mut script = paths.next().map_or(vec![], |path| path.body);
+    for path in paths {
+        script = vec![Statement::IfStatement {
+            loc: path.loc.to_owned(),
+            condition_expr: Expression::Variable(path.loc, path.identifier),
+            if_block: path.body,
+            else_block: Some(script),
+        }];
+    }
+    (input_stack, script)
+}
+
+// Inline function. Body is a single expression, inlined at every call site.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub loc: Location,
+    pub identifier: Identifier,
+    pub params: Vec<StackParam>,
+    pub ty: Type,
+    pub body: Expression,
+}
+
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct Location {
     pub start: usize,
     pub end: usize,
     pub line: usize,
     pub column: usize,
+    /// Source file, empty for the root source.
+    #[serde(default)]
+    pub file: String,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.file.is_empty() {
+            write!(f, "line {}:{}", self.line, self.column)
+        } else {
+            write!(f, "{}:{}:{}", self.file, self.line, self.column)
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
@@ -44,11 +176,21 @@ pub struct Identifier(pub String);
 
 pub enum Type {
     Signature,
+    PublicKey,
+    // Fixed-size bytes, whose length is checked.
+    Bytes(usize),
     Number,
     String,
     Boolean,
 }
 
+impl Type {
+    /// Whether evaluated to number(including boolean) rather than bytes.
+    pub fn is_numeric(&self) -> bool {
+        matches!(self, Type::Number | Type::Boolean)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Statement {
     IfStatement {
@@ -61,11 +203,27 @@ pub enum Statement {
         loc: Location,
         operand: i64,
         op: LocktimeOp,
+        unit: LocktimeUnit,
+    },
+    // Arm whose value the selector equals is taken, or default arm if none.
+    MatchStatement {
+        loc: Location,
+        selector: Expression,
+        arms: Vec<MatchArm>,
+        default: Option<Vec<Statement>>,
     },
     VerifyStatement(Location, Expression),
     ExpressionStatement(Location, Expression),
 }
 
+// Arm of match statement, e.g. 0 => { ... }
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchArm {
+    pub loc: Location,
+    pub value: i64,
+    pub block: Vec<Statement>,
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Expression {
     Variable(Location, Identifier),
@@ -110,6 +268,111 @@ pub enum Expression {
         operand: Box<Expression>,
         op: ByteOp,
     },
+    // True if min <= operand < max.
+    WithinExpression {
+        loc: Location,
+        operand: Box<Expression>,
+        min: Box<Expression>,
+        max: Box<Expression>,
+    },
+    // Call of inline function as written in source.
+    CallExpression {
+        loc: Location,
+        identifier: Identifier,
+        args: Vec<Expression>,
+    },
+    // Call replaced by function body, with arguments substituted for parameters.
+    // `definition` is the location of the function, `loc` the location of the call.
+    InlineExpression {
+        loc: Location,
+        identifier: Identifier,
+        definition: Location,
+        params: Vec<StackParam>,
+        ty: Type,
+        args: Vec<Expression>,
+        body: Box<Expression>,
+    },
+}
+
+// How the value of if condition reaches OP_IF.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    // Always 0 or 1, as left by compare, logical and signature check.
+    Boolean,
+    // Boolean witness item taken as is, whose encoding is up to the spender.
+    Witness(Identifier),
+    // Any number, which is normalised to 0 or 1 under MINIMALIF.
+    Number,
+    // Byte string, which OP_IF can't take under MINIMALIF.
+    Bytes,
+}
+
+impl Expression {
+    /// Classify the expression used as if condition, with types of variables given by `ty`.
+    pub fn condition(&self, ty: &dyn Fn(&Identifier) -> Option<Type>) -> Condition {
+        match self {
+            Expression::BooleanLiteral(..)
+            | Expression::LogicalExpression { .. }
+            | Expression::CompareExpression { .. }
+            | Expression::CheckSigExpression { .. }
+            | Expression::WithinExpression { .. }
+            | Expression::UnaryMathExpression {
+                op: UnaryMathOp::Not,
+                ..
+            } => Condition::Boolean,
+            Expression::NumberLiteral(..)
+            | Expression::UnaryMathExpression { .. }
+            | Expression::BinaryMathExpression { .. }
+            | Expression::ByteExpression { .. } => Condition::Number,
+            Expression::Variable(_, identifier) => match ty(identifier) {
+                Some(Type::Boolean) => Condition::Witness(identifier.to_owned()),
+                Some(Type::Number) => Condition::Number,
+                _ => Condition::Bytes,
+            },
+            Expression::InlineExpression { body, .. } => body.condition(ty),
+            Expression::StringLiteral(..)
+            | Expression::UnaryCryptoExpression { .. }
+            | Expression::CallExpression { .. } => Condition::Bytes,
+        }
+    }
+
+    /// Direct child expressions, in evaluation order.
+    pub fn sub_expressions(&self) -> Vec<&Expression> {
+        match self {
+            Expression::LogicalExpression { lhs, rhs, .. }
+            | Expression::CompareExpression { lhs, rhs, .. }
+            | Expression::BinaryMathExpression { lhs, rhs, .. } => vec![&**lhs, &**rhs],
+            Expression::UnaryMathExpression { operand, .. }
+            | Expression::UnaryCryptoExpression { operand, .. }
+            | Expression::ByteExpression { operand, .. } => vec![&**operand],
+            Expression::WithinExpression {
+                operand, min, max, ..
+            } => vec![&**operand, &**min, &**max],
+            Expression::CheckSigExpression { operand, .. } => operand.sub_expressions(),
+            Expression::CallExpression { args, .. } => args.iter().collect(),
+            Expression::InlineExpression { body, .. } => vec![&**body],
+            _ => vec![],
+        }
+    }
+
+    /// Mutable counterpart of [`Expression::sub_expressions`], for rewrites in place.
+    pub fn sub_expressions_mut(&mut self) -> Vec<&mut Expression> {
+        match self {
+            Expression::LogicalExpression { lhs, rhs, .. }
+            | Expression::CompareExpression { lhs, rhs, .. }
+            | Expression::BinaryMathExpression { lhs, rhs, .. } => vec![&mut **lhs, &mut **rhs],
+            Expression::UnaryMathExpression { operand, .. }
+            | Expression::UnaryCryptoExpression { operand, .. }
+            | Expression::ByteExpression { operand, .. } => vec![&mut **operand],
+            Expression::WithinExpression {
+                operand, min, max, ..
+            } => vec![&mut **operand, &mut **min, &mut **max],
+            Expression::CheckSigExpression { operand, .. } => operand.sub_expressions_mut(),
+            Expression::CallExpression { args, .. } => args.iter_mut().collect(),
+            Expression::InlineExpression { body, .. } => vec![&mut **body],
+            _ => vec![],
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -156,6 +419,21 @@ pub enum CheckSigOp {
 pub enum UnaryCryptoOp {
     Sha256,
     Ripemd160,
+    Hash160,
+    Hash256,
+    Sha1,
+}
+
+impl UnaryCryptoOp {
+    /// Digest is typed by its length.
+    pub fn result_type(&self) -> Type {
+        match self {
+            UnaryCryptoOp::Sha256 | UnaryCryptoOp::Hash256 => Type::Bytes(32),
+            UnaryCryptoOp::Ripemd160 | UnaryCryptoOp::Hash160 | UnaryCryptoOp::Sha1 => {
+                Type::Bytes(20)
+            }
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -163,12 +441,74 @@ pub enum ByteOp {
     Size,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum LocktimeOp {
     Cltv,
     Csv,
 }
 
+// Time-based operand is in seconds.
+// Raw absolute locktime is either height or time by its value, as consensus does.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LocktimeUnit {
+    Block,
+    Second,
+    Raw,
+}
+
+impl LocktimeUnit {
+    /// Whether the locktime is time-based rather than height-based.
+    pub fn is_time(&self, operand: i64) -> bool {
+        match self {
+            LocktimeUnit::Block => false,
+            LocktimeUnit::Second => true,
+            LocktimeUnit::Raw => operand >= bitcoin::locktime::absolute::LOCK_TIME_THRESHOLD as i64,
+        }
+    }
+}
+
+// UNIX timestamp of `YYYY-MM-DDThh:mm:ssZ`, if the date exists.
+pub fn parse_timestamp(date: &str) -> Option<i64> {
+    let number = |range: std::ops::Range<usize>| date.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+
+    let is_leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_in_month = [
+        31,
+        if is_leap { 29 } else { 28 },
+        31,
+        30,
+        31,
+        30,
+        31,
+        31,
+        30,
+        31,
+        30,
+        31,
+    ];
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month[month as usize - 1]).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return None;
+    }
+
+    // Days since 1970-01-01.
+    // Reference: <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum Factor {
     SingleSigFactor {
@@ -183,6 +523,30 @@ pub enum Factor {
     },
 }
 
+impl Factor {
+    /// Signature and public key expressions of every key in the factor.
+    pub fn sub_expressions(&self) -> Vec<&Expression> {
+        match self {
+            Factor::SingleSigFactor { sig, pubkey, .. } => vec![&**sig, &**pubkey],
+            Factor::MultiSigFactor { n, .. } => n
+                .iter()
+                .flat_map(|factor| factor.sub_expressions())
+                .collect(),
+        }
+    }
+
+    /// Mutable counterpart of [`Factor::sub_expressions`].
+    pub fn sub_expressions_mut(&mut self) -> Vec<&mut Expression> {
+        match self {
+            Factor::SingleSigFactor { sig, pubkey, .. } => vec![&mut **sig, &mut **pubkey],
+            Factor::MultiSigFactor { n, .. } => n
+                .iter_mut()
+                .flat_map(|factor| factor.sub_expressions_mut())
+                .collect(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CompileError {
     pub loc: Location,
@@ -200,6 +564,7 @@ pub enum ErrorKind {
     VariableConsumed(String),
     UnusedVariable(String),
     InvalidConsumptionOrder(String),
+    StackMismatch(String),
 
     // Type Errors
     TypeMismatch(String),
@@ -209,31 +574,203 @@ pub enum ErrorKind {
     StackDepthExceeded(String),
     OpcodeCountExceeded(String),
     DustOutputCreated(String),
+    MixedLocktime(String),
+    CleanStack(String),
+
+    // Function Errors
+    UndefinedFunction(String),
+    DuplicateFunction(String),
+    InvalidArgument(String),
+    RecursiveFunction(String),
+
+    // Import Errors
+    ImportError(String),
+    CyclicImport(String),
 
     // Flow Errors
     MultipleReturn(String),
     NoReturn(String),
     UnreachableCode(String),
-    DeadPath,
+    DeadPath(String),
 
     // Security Errors
     IntegerOverflow(String),
     UselessSig(String),
     MalformedPubkey(String),
     NoSigRequired(String),
+    UncommittedPubkey(String),
+}
+
+impl ErrorKind {
+    /// Detail message of the error, if the kind carries one.
+    pub fn message_mut(&mut self) -> Option<&mut String> {
+        match self {
+            ErrorKind::ParseError(msg)
+            | ErrorKind::DeadPath(msg)
+            | ErrorKind::DuplicateVariable(msg)
+            | ErrorKind::UndefinedVariable(msg)
+            | ErrorKind::VariableConsumed(msg)
+            | ErrorKind::UnusedVariable(msg)
+            | ErrorKind::InvalidConsumptionOrder(msg)
+            | ErrorKind::StackMismatch(msg)
+            | ErrorKind::TypeMismatch(msg)
+            | ErrorKind::InvalidOperation(msg)
+            | ErrorKind::StackDepthExceeded(msg)
+            | ErrorKind::OpcodeCountExceeded(msg)
+            | ErrorKind::DustOutputCreated(msg)
+            | ErrorKind::MixedLocktime(msg)
+            | ErrorKind::CleanStack(msg)
+            | ErrorKind::UndefinedFunction(msg)
+            | ErrorKind::DuplicateFunction(msg)
+            | ErrorKind::InvalidArgument(msg)
+            | ErrorKind::RecursiveFunction(msg)
+            | ErrorKind::ImportError(msg)
+            | ErrorKind::CyclicImport(msg)
+            | ErrorKind::MultipleReturn(msg)
+            | ErrorKind::NoReturn(msg)
+            | ErrorKind::UnreachableCode(msg)
+            | ErrorKind::IntegerOverflow(msg)
+            | ErrorKind::UselessSig(msg)
+            | ErrorKind::MalformedPubkey(msg)
+            | ErrorKind::NoSigRequired(msg)
+            | ErrorKind::UncommittedPubkey(msg) => Some(msg),
+        }
+    }
 }
 
 use std::fmt;
 use wasm_bindgen::JsValue;
 
+// Warning doesn't stop compilation, but is reported along with the output.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CompileWarning {
+    pub loc: Location,
+    pub kind: WarningKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WarningKind {
+    // Security Warnings
+    WeakHash(String),
+    Malleability(String),
+
+    // Path Warnings
+    DeadPath(String),
+
+    // Locktime Warnings
+    LocktimeRounding(String),
+    AmbiguousLocktime(String),
+}
+
+// Spending path, and the input stack bound to it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PathReport {
+    /// Index of the input stack, in the order of declaration.
+    pub stack: usize,
+    /// Name of the path, if declared with `path name(...) { ... }`.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Whether if block is taken at each branch of the path, from the top.
+    pub branches: Vec<bool>,
+    /// Witness items from the top of the stack, with the value of branch selector filled in.
+    #[serde(default)]
+    pub witness: Vec<String>,
+    /// Encoding of each boolean item of the stack, from the top.
+    #[serde(default)]
+    pub booleans: Vec<BooleanInput>,
+    /// Why the witness of the path is malleable, empty if non-malleable.
+    #[serde(default)]
+    pub malleability: Vec<String>,
+    /// Who can spend by the path, when and with what.
+    #[serde(default)]
+    pub policy: PathPolicy,
+}
+
+// How boolean witness item must be encoded.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BooleanEncoding {
+    /// Taken by OP_IF as is under MINIMALIF: empty for false, 0x01 for true.
+    MinimalIf,
+    /// Cast as number: any encoding of zero for false, anything else for true.
+    Number,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BooleanInput {
+    pub name: String,
+    pub encoding: BooleanEncoding,
+}
+
+// Requirements to spend by a path, in the order of execution.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PathPolicy {
+    pub signatures: Vec<SignaturePolicy>,
+    pub preimages: Vec<PreimagePolicy>,
+    pub timelocks: Vec<TimelockPolicy>,
+    /// Any other condition that must hold, in Bithoven syntax.
+    pub conditions: Vec<String>,
+}
+
+// Signatures by `threshold` of the pubkeys, where witness pubkey is written as `<name>`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SignaturePolicy {
+    pub threshold: u32,
+    pub pubkeys: Vec<String>,
+}
+
+// Witness item whose hash, by `hash` applied from the right, must be the digest.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PreimagePolicy {
+    pub preimage: String,
+    pub hash: String,
+    pub digest: String,
+}
+
+// Miniscript with the same spending policy as the script, or why none exists.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MiniscriptExport {
+    pub miniscript: Option<String>,
+    /// Output descriptor of the miniscript for the target, e.g. `wsh(...)`.
+    pub descriptor: Option<String>,
+    /// Constructs with no counterpart in Miniscript, with their location.
+    pub blockers: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TimelockPolicy {
+    /// Blocks since the output is confirmed.
+    RelativeBlocks(i64),
+    /// Seconds since the output is confirmed, in 512 seconds granularity.
+    RelativeSeconds(i64),
+    /// Block height the spending transaction is locked to.
+    AbsoluteHeight(i64),
+    /// Unix time the spending transaction is locked to.
+    AbsoluteTime(i64),
+}
+
+impl fmt::Display for CompileWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Warning at {}: {:?}", self.loc, self.kind)
+    }
+}
+
+// Rewrite made by optimization, reported in verbose log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Optimization {
+    pub loc: Location,
+    pub message: String,
+}
+
+impl fmt::Display for Optimization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Optimized at {}: {}", self.loc, self.message)
+    }
+}
+
 // (Optional but recommended) Create a display implementation for a clean error message.
 impl fmt::Display for CompileError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "Error at line {}:{}: {:?}",
-            self.loc.line, self.loc.column, self.kind
-        )
+        write!(f, "Error at {}: {:?}", self.loc, self.kind)
     }
 }
 
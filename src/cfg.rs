@@ -0,0 +1,247 @@
+use crate::ast::*;
+use crate::path::*;
+use crate::source::*;
+
+// Control-flow graph of the script, as the tree of spending paths.
+// Statements after if and match are repeated under each branch, so every leaf is one path.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cfg {
+    pub nodes: Vec<CfgNode>,
+    /// Edge from node to node, with the selector value taking it if any.
+    pub edges: Vec<(usize, usize, Option<String>)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CfgNode {
+    /// Statements executed in sequence.
+    Block(Vec<String>),
+    /// Condition of if, or selector of match.
+    Decision(String),
+    /// End of the path, with its input stack and estimated witness size in bytes.
+    Leaf {
+        label: String,
+        stack: String,
+        witness_size: usize,
+    },
+}
+
+// Paths still together at a node, by their index and steps left.
+type Subset<'a, 'b> = Vec<(usize, &'b [Step<'a>])>;
+
+pub fn build_cfg(
+    paths: &[Path],
+    reports: &[PathReport],
+    input: &[Vec<StackParam>],
+    target: &Target,
+) -> Cfg {
+    let mut cfg = Cfg::default();
+    let subset: Subset = paths
+        .iter()
+        .enumerate()
+        .map(|(branch, path)| (branch, path.steps.as_slice()))
+        .collect();
+    let leaf = |branch: usize| {
+        let report = &reports[branch];
+        CfgNode::Leaf {
+            label: match &report.name {
+                Some(name) => format!("path {:?}", name),
+                None => format!("stack {}", report.stack),
+            },
+            stack: stack_source(&input[branch]),
+            witness_size: witness_size(&input[branch], &report.witness, target),
+        }
+    };
+    build_subtree(&mut cfg, &subset, None, &leaf);
+    cfg
+}
+
+// Paths of the subset share every step up to the next decision, where they are split by its outcome.
+fn build_subtree(
+    cfg: &mut Cfg,
+    subset: &[(usize, &[Step])],
+    parent: Option<(usize, Option<String>)>,
+    leaf: &dyn Fn(usize) -> CfgNode,
+) {
+    let (branch, steps) = subset[0];
+    let split = steps
+        .iter()
+        .position(|step| !matches!(step, Step::Statement(_)))
+        .unwrap_or(steps.len());
+    let mut parent = parent;
+    if split > 0 {
+        let statements = steps[..split]
+            .iter()
+            .filter_map(|step| match step {
+                Step::Statement(stmt) => Some(statement_source(stmt)),
+                _ => None,
+            })
+            .collect();
+        parent = Some((cfg.add(CfgNode::Block(statements), parent), None));
+    }
+    let Some(decision) = steps.get(split) else {
+        cfg.add(leaf(branch), parent);
+        return;
+    };
+    let label = match decision {
+        Step::Branch { condition, .. } => format!("if {}", expression_source(condition)),
+        Step::Arm { selector, .. } => format!("match {}", expression_source(selector)),
+        Step::Statement(_) => unreachable!(),
+    };
+    let node = cfg.add(CfgNode::Decision(label), parent);
+
+    // Outcomes in the order of paths, which is if block and arm first.
+    let mut outcomes: Vec<(String, Subset)> = vec![];
+    for (branch, steps) in subset {
+        let outcome = outcome_label(&steps[split]);
+        let rest = (*branch, &steps[split + 1..]);
+        match outcomes.iter_mut().find(|(label, _)| *label == outcome) {
+            Some((_, group)) => group.push(rest),
+            None => outcomes.push((outcome, vec![rest])),
+        }
+    }
+    for (outcome, group) in outcomes {
+        build_subtree(cfg, &group, Some((node, Some(outcome))), leaf);
+    }
+}
+
+// Selector value taking the branch, e.g. `a = true` for `if a`.
+fn outcome_label(step: &Step) -> String {
+    match step {
+        Step::Branch {
+            condition: Expression::Variable(_, id),
+            taken,
+        } => format!("{} = {}", id.0, taken),
+        Step::Branch { taken, .. } => taken.to_string(),
+        Step::Arm {
+            selector,
+            arms,
+            taken: Some(k),
+            ..
+        } => format!("{} = {}", expression_source(selector), arms[*k].value),
+        Step::Arm { .. } => "_".to_string(),
+        Step::Statement(_) => unreachable!(),
+    }
+}
+
+fn stack_source(stack: &[StackParam]) -> String {
+    let items: Vec<String> = stack
+        .iter()
+        .rev()
+        .map(|item| format!("{}: {}", item.identifier.0, type_source(&item.ty)))
+        .collect();
+    format!("({})", items.join(", "))
+}
+
+fn type_source(ty: &Type) -> String {
+    match ty {
+        Type::Signature => "signature".to_string(),
+        Type::PublicKey => "pubkey".to_string(),
+        Type::Bytes(n) => format!("bytes{}", n),
+        Type::Number => "number".to_string(),
+        Type::String => "string".to_string(),
+        Type::Boolean => "bool".to_string(),
+    }
+}
+
+// Size of the witness items, each with its length prefix.
+// Signature is taken as 72 bytes for ECDSA and 64 for Schnorr, and string as 32-byte preimage.
+// Number is taken as 4 bytes, unless its value is fixed by the path.
+pub fn witness_size(stack: &[StackParam], witness: &[String], target: &Target) -> usize {
+    stack
+        .iter()
+        .rev()
+        .zip(witness)
+        .map(|(item, value)| {
+            1 + match (&item.ty, value.as_str()) {
+                (Type::Signature, _) if *target == Target::Taproot => 64,
+                (Type::Signature, _) => 72,
+                (Type::PublicKey, _) if *target == Target::Taproot => 32,
+                (Type::PublicKey, _) => 33,
+                (Type::Bytes(n), _) => *n,
+                (Type::String, _) => 32,
+                (Type::Boolean, "false") => 0,
+                (Type::Boolean, _) => 1,
+                (Type::Number, value) => match value.parse::<i64>() {
+                    Ok(n) => bitcoin::script::write_scriptint(&mut [0u8; 8], n),
+                    Err(_) => 4,
+                },
+            }
+        })
+        .sum()
+}
+
+impl Cfg {
+    fn add(&mut self, node: CfgNode, parent: Option<(usize, Option<String>)>) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(node);
+        if let Some((from, label)) = parent {
+            self.edges.push((from, id, label));
+        }
+        id
+    }
+
+    fn lines(node: &CfgNode) -> Vec<String> {
+        match node {
+            CfgNode::Block(statements) => statements.to_owned(),
+            CfgNode::Decision(label) => vec![label.to_owned()],
+            CfgNode::Leaf {
+                label,
+                stack,
+                witness_size,
+            } => vec![
+                label.to_owned(),
+                stack.to_owned(),
+                format!("witness ~{} bytes", witness_size),
+            ],
+        }
+    }
+
+    /// Graphviz DOT, with decision as diamond and leaf as ellipse.
+    pub fn to_dot(&self) -> String {
+        let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut dot = String::from("digraph cfg {\n    node [shape=box];\n");
+        for (id, node) in self.nodes.iter().enumerate() {
+            let shape = match node {
+                CfgNode::Block(_) => "",
+                CfgNode::Decision(_) => "shape=diamond, ",
+                CfgNode::Leaf { .. } => "shape=ellipse, ",
+            };
+            let label: Vec<String> = Cfg::lines(node).iter().map(|s| escape(s)).collect();
+            dot += &format!("    n{} [{}label=\"{}\"];\n", id, shape, label.join("\\n"));
+        }
+        for (from, to, label) in &self.edges {
+            match label {
+                Some(label) => {
+                    dot += &format!("    n{} -> n{} [label=\"{}\"];\n", from, to, escape(label))
+                }
+                None => dot += &format!("    n{} -> n{};\n", from, to),
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+
+    /// Mermaid flowchart, with decision as rhombus and leaf as stadium.
+    pub fn to_mermaid(&self) -> String {
+        let escape = |s: &str| s.replace('"', "#quot;");
+        let mut mermaid = String::from("flowchart TD\n");
+        for (id, node) in self.nodes.iter().enumerate() {
+            let label: Vec<String> = Cfg::lines(node).iter().map(|s| escape(s)).collect();
+            let label = label.join("<br/>");
+            mermaid += &match node {
+                CfgNode::Block(_) => format!("    n{}[\"{}\"]\n", id, label),
+                CfgNode::Decision(_) => format!("    n{}{{\"{}\"}}\n", id, label),
+                CfgNode::Leaf { .. } => format!("    n{}([\"{}\"])\n", id, label),
+            };
+        }
+        for (from, to, label) in &self.edges {
+            match label {
+                Some(label) => {
+                    mermaid += &format!("    n{} -->|\"{}\"| n{}\n", from, escape(label), to)
+                }
+                None => mermaid += &format!("    n{} --> n{}\n", from, to),
+            }
+        }
+        mermaid
+    }
+}
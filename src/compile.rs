@@ -1,10 +1,11 @@
-use bitcoin::opcodes::all::{
-    OP_CHECKMULTISIG, OP_CHECKMULTISIGVERIFY, OP_CHECKSIG, OP_CHECKSIGVERIFY, OP_EQUAL,
-    OP_EQUALVERIFY, OP_HASH160, OP_HASH256, OP_NUMEQUAL, OP_NUMEQUALVERIFY, OP_PUSHDATA1,
-    OP_PUSHDATA2, OP_PUSHDATA4, OP_RIPEMD160, OP_SHA256, OP_VERIFY,
-};
+use bitcoin::opcodes::all::OP_EQUALVERIFY;
+
+use std::collections::{HashMap, HashSet};
 
 use crate::ast::*;
+use crate::interpret::flip;
+use crate::optimize::{is_stack_free, range_form};
+use crate::peephole::*;
 
 /*
     1. Pure Push
@@ -14,22 +15,22 @@ use crate::ast::*;
 
 // OP_0...OP_16, OP_1NEGATE, and other int in range of [-2147483647, 2147483647].
 // Reference: <https://github.com/bitcoin/bips/blob/master/bip-0062.mediawiki#numbers>
-pub fn push_int(script: &mut Vec<u8>, data: i64) {
+pub fn push_int(script: &mut Vec<Instruction>, data: i64) {
     let builder = bitcoin::script::Builder::new().push_int(data);
 
-    script.extend_from_slice(builder.as_bytes());
+    emit(script, builder);
 }
 
 // Push any type of byte. Some are overlapped with push_int.
 // Reference: <https://github.com/bitcoin/bips/blob/master/bip-0062.mediawiki#push-operators>
-pub fn push_bytes(script: &mut Vec<u8>, data: String) {
+pub fn push_bytes(script: &mut Vec<Instruction>, data: String) {
     // Try decoding hex, and then utf-8
     let hex_or_utf8 = hex::decode(&data).unwrap_or(data.into_bytes());
     let bytes = <&bitcoin::script::PushBytes>::try_from(hex_or_utf8.as_slice())
         .expect("String to bytes error");
     let builder = bitcoin::script::Builder::new().push_slice(bytes);
 
-    script.extend_from_slice(builder.as_bytes());
+    emit(script, builder);
 }
 
 /*
@@ -38,30 +39,30 @@ pub fn push_bytes(script: &mut Vec<u8>, data: String) {
 */
 
 // Control: OP_IF, OP_NOTIF, OP_ELSE, OP_ENDIF, and OP_VERIFY.
-pub fn push_control_verify(script: &mut Vec<u8>) {
+pub fn push_control_verify(script: &mut Vec<Instruction>) {
     let builder = bitcoin::script::Builder::new().push_verify();
 
-    script.extend_from_slice(builder.as_bytes());
+    emit(script, builder);
 }
 
 // Control: OP_IF, OP_NOTIF, OP_ELSE, OP_ENDIF, and OP_VERIFY.
-pub fn push_control_if(script: &mut Vec<u8>) {
+pub fn push_control_if(script: &mut Vec<Instruction>) {
     let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_IF);
 
-    script.extend_from_slice(builder.as_bytes());
+    emit(script, builder);
 }
 
 // Control: OP_IF, OP_NOTIF, OP_ELSE, OP_ENDIF, and OP_VERIFY.
-pub fn push_control_else(script: &mut Vec<u8>) {
+pub fn push_control_else(script: &mut Vec<Instruction>) {
     let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_ELSE);
 
-    script.extend_from_slice(builder.as_bytes());
+    emit(script, builder);
 }
 // Control: OP_IF, OP_NOTIF, OP_ELSE, OP_ENDIF, and OP_VERIFY.
-pub fn push_control_end(script: &mut Vec<u8>) {
+pub fn push_control_end(script: &mut Vec<Instruction>) {
     let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_ENDIF);
 
-    script.extend_from_slice(builder.as_bytes());
+    emit(script, builder);
 }
 
 /*
@@ -72,13 +73,13 @@ pub fn push_control_end(script: &mut Vec<u8>) {
 
 // OP_SIZE.
 // This consumes operand here, while OP_SIZE itself doesn't consume.
-pub fn push_bytes_len(script: &mut Vec<u8>) {
+pub fn push_bytes_len(script: &mut Vec<Instruction>) {
     let builder = bitcoin::script::Builder::new()
         .push_opcode(bitcoin::opcodes::all::OP_SIZE)
         .push_opcode(bitcoin::opcodes::all::OP_SWAP)
         .push_opcode(bitcoin::opcodes::all::OP_DROP);
 
-    script.extend_from_slice(builder.as_bytes());
+    emit(script, builder);
 }
 
 /*
@@ -89,75 +90,75 @@ pub fn push_bytes_len(script: &mut Vec<u8>) {
 */
 
 // OP_BOOLAND, OP_BOOLOR
-pub fn push_logical(script: &mut Vec<u8>, operand: BinaryLogicalOp) {
+pub fn push_logical(script: &mut Vec<Instruction>, operand: BinaryLogicalOp) {
     match operand {
         BinaryLogicalOp::BoolOr => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_BOOLOR);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         BinaryLogicalOp::BoolAnd => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_BOOLAND);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
     }
 }
 
 // OP_EQUAL, OP_BOOLAND, OP_BOOLOR, (OP_NUMEQUAL, OP_NUMNOTEQUAL,)
 // OP_LESSTHAN, OP_GREATERTHAN, OP_LESSTHANOREQUAL, and OP_GREATERTHANOREQUAL.
-pub fn push_compare(script: &mut Vec<u8>, operand: BinaryCompareOp) {
+pub fn push_compare(script: &mut Vec<Instruction>, operand: BinaryCompareOp) {
     match operand {
         BinaryCompareOp::Equal => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_EQUAL);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         BinaryCompareOp::NotEqual => {
             let builder = bitcoin::script::Builder::new()
                 .push_opcode(bitcoin::opcodes::all::OP_EQUAL)
                 .push_opcode(bitcoin::opcodes::all::OP_NOT);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         BinaryCompareOp::Greater => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_GREATERTHAN);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         BinaryCompareOp::GreaterOrEqual => {
             let builder = bitcoin::script::Builder::new()
                 .push_opcode(bitcoin::opcodes::all::OP_GREATERTHANOREQUAL);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         BinaryCompareOp::Less => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_LESSTHAN);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         BinaryCompareOp::LessOrEqual => {
             let builder = bitcoin::script::Builder::new()
                 .push_opcode(bitcoin::opcodes::all::OP_LESSTHANOREQUAL);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         BinaryCompareOp::NumEqual => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_NUMEQUAL);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         BinaryCompareOp::NumNotEqual => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_NUMNOTEQUAL);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
     }
 }
@@ -170,41 +171,48 @@ pub fn push_compare(script: &mut Vec<u8>, operand: BinaryCompareOp) {
 */
 
 // OP_1ADD, OP_1SUB, OP_NEGATE, OP_ABS, OP_NOT, (and OP_0NOTEQUAL).
-pub fn push_math_unary(script: &mut Vec<u8>, operand: UnaryMathOp) {
+pub fn push_math_unary(script: &mut Vec<Instruction>, operand: UnaryMathOp) {
     match operand {
         UnaryMathOp::Add => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_1ADD);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         UnaryMathOp::Sub => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_1SUB);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         UnaryMathOp::Negate => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_NEGATE);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         UnaryMathOp::Abs => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_ABS);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         UnaryMathOp::Not => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_NOT);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
     }
 }
 
+// OP_0NOTEQUAL, which normalises number to 0 or 1 for OP_IF under MINIMALIF.
+pub fn push_math_0notequal(script: &mut Vec<Instruction>) {
+    let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_0NOTEQUAL);
+
+    emit(script, builder);
+}
+
 /*
     5. Math push binary
     - See the top 2 stack item.
@@ -213,31 +221,31 @@ pub fn push_math_unary(script: &mut Vec<u8>, operand: UnaryMathOp) {
 */
 
 // OP_ADD, OP_SUB, OP_MIN, OP_MAX
-pub fn push_math_binary(script: &mut Vec<u8>, operand: BinaryMathOp) {
+pub fn push_math_binary(script: &mut Vec<Instruction>, operand: BinaryMathOp) {
     match operand {
         BinaryMathOp::Add => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_ADD);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         BinaryMathOp::Sub => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_SUB);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         BinaryMathOp::Max => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_MAX);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         BinaryMathOp::Min => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_MIN);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
     }
 }
@@ -250,7 +258,11 @@ pub fn push_math_binary(script: &mut Vec<u8>, operand: BinaryMathOp) {
 */
 
 // OP_WITHIN
-pub fn push_math_ternary() {}
+pub fn push_math_ternary(script: &mut Vec<Instruction>) {
+    let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_WITHIN);
+
+    emit(script, builder);
+}
 
 /*
     7. Crypto push
@@ -259,19 +271,37 @@ pub fn push_math_ternary() {}
     - Push new single stack item.
 */
 // OP_RIPEMD160, OP_SHA1, OP_SHA256, OP_HASH160, OP_HASH256,
-pub fn push_crypto_unary(script: &mut Vec<u8>, op: UnaryCryptoOp) {
+pub fn push_crypto_unary(script: &mut Vec<Instruction>, op: UnaryCryptoOp) {
     match op {
         UnaryCryptoOp::Sha256 => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_SHA256);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         UnaryCryptoOp::Ripemd160 => {
             let builder =
                 bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_RIPEMD160);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
+        }
+        UnaryCryptoOp::Hash160 => {
+            let builder =
+                bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_HASH160);
+
+            emit(script, builder);
+        }
+        UnaryCryptoOp::Hash256 => {
+            let builder =
+                bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_HASH256);
+
+            emit(script, builder);
+        }
+        UnaryCryptoOp::Sha1 => {
+            let builder =
+                bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_SHA1);
+
+            emit(script, builder);
         }
     }
 }
@@ -291,14 +321,14 @@ pub enum CheckSigType {
 */
 
 // OP_CHECKSIG, OP_CHECKMULTISIG, OP_CHECKSIGADD
-pub fn push_checksig(script: &mut Vec<u8>, check_sig_ty: CheckSigType) {
+pub fn push_checksig(script: &mut Vec<Instruction>, check_sig_ty: CheckSigType) {
     let builder = bitcoin::script::Builder::new().push_opcode(match check_sig_ty {
         CheckSigType::Single => bitcoin::opcodes::all::OP_CHECKSIG,
         CheckSigType::Multi => bitcoin::opcodes::all::OP_CHECKMULTISIG,
         CheckSigType::Add => bitcoin::opcodes::all::OP_CHECKSIGADD,
     });
 
-    script.extend_from_slice(builder.as_bytes());
+    emit(script, builder);
 }
 
 /*
@@ -308,7 +338,12 @@ pub fn push_checksig(script: &mut Vec<u8>, check_sig_ty: CheckSigType) {
 */
 
 // OP_CHECKLOCKTIMEVERIFY and OP_CHECKSEQUENCEVERIFY
-pub fn push_locktime(script: &mut Vec<u8>, operand: i64, op: LocktimeOp) {
+pub fn push_locktime(
+    script: &mut Vec<Instruction>,
+    operand: i64,
+    op: LocktimeOp,
+    unit: LocktimeUnit,
+) {
     match op {
         LocktimeOp::Cltv => {
             let locktime = bitcoin::locktime::absolute::LockTime::from_consensus(operand as u32);
@@ -317,57 +352,352 @@ pub fn push_locktime(script: &mut Vec<u8>, operand: i64, op: LocktimeOp) {
                 .push_opcode(bitcoin::opcodes::all::OP_CLTV)
                 .push_opcode(bitcoin::opcodes::all::OP_DROP);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
         }
         LocktimeOp::Csv => {
-            let height = bitcoin::locktime::relative::Height::from_height(operand as u16);
             let builder = bitcoin::script::Builder::new()
-                .push_sequence(bitcoin::locktime::relative::LockTime::Blocks(height).to_sequence())
+                .push_sequence(relative_locktime(operand, unit).to_sequence())
                 .push_opcode(bitcoin::opcodes::all::OP_CSV)
                 .push_opcode(bitcoin::opcodes::all::OP_DROP);
 
-            script.extend_from_slice(builder.as_bytes());
+            emit(script, builder);
+        }
+    }
+}
+
+// Time is rounded up to 512 seconds, so the locktime is never shorter than written.
+pub fn relative_locktime(
+    operand: i64,
+    unit: LocktimeUnit,
+) -> bitcoin::locktime::relative::LockTime {
+    match unit {
+        LocktimeUnit::Second => {
+            bitcoin::locktime::relative::LockTime::from_seconds_ceil(operand as u32)
+                .expect("Relative locktime is checked by analyzer.")
+        }
+        _ => bitcoin::locktime::relative::LockTime::from_height(operand as u16),
+    }
+}
+
+// nSequence of spending input, required by each relative locktime in order.
+pub fn collect_sequences(ast: &[Statement], sequences: &mut Vec<u32>) {
+    for stmt in ast {
+        match stmt {
+            Statement::LocktimeStatement {
+                loc: _,
+                operand,
+                op: LocktimeOp::Csv,
+                unit,
+            } => sequences.push(
+                relative_locktime(*operand, unit.to_owned())
+                    .to_sequence()
+                    .to_consensus_u32(),
+            ),
+            Statement::IfStatement {
+                if_block,
+                else_block,
+                ..
+            } => {
+                collect_sequences(if_block, sequences);
+                if let Some(else_b) = else_block {
+                    collect_sequences(else_b, sequences);
+                }
+            }
+            Statement::MatchStatement { arms, default, .. } => {
+                for arm in arms {
+                    collect_sequences(&arm.block, sequences);
+                }
+                if let Some(default_b) = default {
+                    collect_sequences(default_b, sequences);
+                }
+            }
+            _ => (),
         }
     }
 }
 
-pub fn push_to_alt_stack(script: &mut Vec<u8>) {
+pub fn push_to_alt_stack(script: &mut Vec<Instruction>) {
     let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_TOALTSTACK);
 
-    script.extend_from_slice(builder.as_bytes());
+    emit(script, builder);
 }
 
-pub fn push_from_alt_stack(script: &mut Vec<u8>) {
+pub fn push_from_alt_stack(script: &mut Vec<Instruction>) {
     let builder = bitcoin::script::Builder::new()
         .push_opcode(bitcoin::opcodes::all::OP_FROMALTSTACK)
         .push_opcode(bitcoin::opcodes::all::OP_SWAP);
 
-    script.extend_from_slice(builder.as_bytes());
+    emit(script, builder);
+}
+
+// Restore the first of three operands under the other two.
+pub fn push_from_alt_stack_under(script: &mut Vec<Instruction>) {
+    let builder = bitcoin::script::Builder::new()
+        .push_opcode(bitcoin::opcodes::all::OP_FROMALTSTACK)
+        .push_opcode(bitcoin::opcodes::all::OP_ROT)
+        .push_opcode(bitcoin::opcodes::all::OP_ROT);
+
+    emit(script, builder);
+}
+
+/*
+    10. Stack push
+    - See the top 1 or 2 stack item.
+    - Push copy of the top stack item.
+*/
+
+// OP_DUP
+pub fn push_stack_dup(script: &mut Vec<Instruction>) {
+    let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_DUP);
+
+    emit(script, builder);
 }
 
-pub fn compile(ast: Vec<Statement>, target: &Target) -> Vec<u8> {
-    let mut bitcoin_script: Vec<u8> = Vec::new();
+// OP_TUCK
+pub fn push_stack_tuck(script: &mut Vec<Instruction>) {
+    let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_TUCK);
+
+    emit(script, builder);
+}
+
+// OP_SWAP
+pub fn push_stack_swap(script: &mut Vec<Instruction>) {
+    let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_SWAP);
+
+    emit(script, builder);
+}
+
+// OP_DROP
+pub fn push_stack_drop(script: &mut Vec<Instruction>) {
+    let builder = bitcoin::script::Builder::new().push_opcode(bitcoin::opcodes::all::OP_DROP);
+
+    emit(script, builder);
+}
+
+// OP_DUP <n> OP_NUMEQUAL, keeping the selector of match under the result.
+// Comparison with 0 is OP_DUP OP_NOT, which is a byte smaller.
+pub fn push_arm_test(script: &mut Vec<Instruction>, value: i64) {
+    push_stack_dup(script);
+    if value == 0 {
+        push_math_unary(script, UnaryMathOp::Not);
+    } else {
+        push_int(script, value);
+        push_compare(script, BinaryCompareOp::NumEqual);
+    }
+}
+
+/*
+    11. Length push
+    - See the top 1 stack item.
+    - Keep the top 1 stack item.
+*/
+
+// OP_SIZE <n> OP_EQUALVERIFY
+pub fn push_size_guard(script: &mut Vec<Instruction>, size: usize) {
+    let builder = bitcoin::script::Builder::new()
+        .push_opcode(bitcoin::opcodes::all::OP_SIZE)
+        .push_int(size as i64)
+        .push_opcode(OP_EQUALVERIFY);
+
+    emit(script, builder);
+}
+
+// Witness pubkey is used twice: by hash commitment and by checksig.
+// The first use on each path keeps a copy on the stack for the second.
+// Witness bytes is checked for its declared length where used.
+#[derive(Clone, Debug, Default)]
+pub struct WitnessContext {
+    /// Name of witness pubkeys.
+    pub pubkeys: HashSet<String>,
+    /// Witness pubkeys already used on current path.
+    pub used: HashSet<String>,
+    /// Name of witness bytes and its size.
+    pub sizes: HashMap<String, usize>,
+    /// Name of witness numbers.
+    pub numbers: HashSet<String>,
+    /// Encoding of the constructs compiled in more than one way.
+    pub encoding: Encoding,
+}
+
+// Constructs compiled in more than one way, chosen by optimization level.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Encoding {
+    /// Multisig of one key by OP_CHECKSIG.
+    pub single_key: bool,
+    /// Multisig by OP_CHECKSIG of each key, instead of OP_CHECKMULTISIG.
+    pub checksig_chain: bool,
+    /// Operands of binary expression in the cheapest order, instead of lhs first via alt stack.
+    pub operand_order: bool,
+}
+
+impl Encoding {
+    /// Encodings to compile by. Under -Os, the one of least witness-weighted cost is taken.
+    /// Tapscript has no OP_CHECKMULTISIG, and its multisig of one key is shortened by peephole rule.
+    pub fn candidates(level: OptimizeLevel, target: &Target) -> Vec<Encoding> {
+        let standard = Encoding {
+            single_key: true,
+            checksig_chain: false,
+            operand_order: true,
+        };
+        match level {
+            OptimizeLevel::O0 => vec![Encoding::default()],
+            OptimizeLevel::O1 => vec![standard],
+            OptimizeLevel::Os if *target == Target::Taproot => vec![standard],
+            OptimizeLevel::Os => vec![
+                standard,
+                Encoding {
+                    checksig_chain: true,
+                    ..standard
+                },
+            ],
+        }
+    }
+
+    /// Whether the multisig of legacy or segwit is compiled by OP_CHECKSIG, without the dummy item.
+    pub fn is_checksig_chain(&self, factor: &Factor, target: &Target) -> bool {
+        match factor {
+            Factor::MultiSigFactor { m, n, .. } if *target != Target::Taproot => {
+                self.checksig_chain || (self.single_key && *m == 1 && n.len() == 1)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl WitnessContext {
+    pub fn new(input: &[Vec<StackParam>]) -> Self {
+        WitnessContext {
+            pubkeys: input
+                .iter()
+                .flatten()
+                .filter(|item| item.ty == Type::PublicKey)
+                .map(|item| item.identifier.0.to_owned())
+                .collect(),
+            used: HashSet::new(),
+            sizes: input
+                .iter()
+                .flatten()
+                .filter_map(|item| match item.ty {
+                    Type::Bytes(n) => Some((item.identifier.0.to_owned(), n)),
+                    _ => None,
+                })
+                .collect(),
+            numbers: input
+                .iter()
+                .flatten()
+                .filter(|item| item.ty == Type::Number)
+                .map(|item| item.identifier.0.to_owned())
+                .collect(),
+            encoding: Encoding::default(),
+        }
+    }
+
+    /// Whether the if condition is number, which OP_IF doesn't take as is under MINIMALIF.
+    /// Byte string condition is rejected by analyzer, so the other variables are boolean.
+    pub fn is_number(&self, condition: &Expression) -> bool {
+        let ty = |id: &Identifier| {
+            Some(if self.numbers.contains(&id.0) {
+                Type::Number
+            } else {
+                Type::Boolean
+            })
+        };
+        condition.condition(&ty) == Condition::Number
+    }
+
+    /// Whether the expression is a witness item used as is, without size guard or copy.
+    pub fn is_bare(&self, expr: &Expression) -> bool {
+        matches!(expr, Expression::Variable(_loc, id)
+            if !self.pubkeys.contains(&id.0) && !self.sizes.contains_key(&id.0))
+    }
+
+    /// Whether the expression uses witness pubkey, whose copy may be left on the stack.
+    pub fn uses_pubkey(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::Variable(_loc, id) => self.pubkeys.contains(&id.0),
+            _ => expr
+                .sub_expressions()
+                .into_iter()
+                .any(|sub| self.uses_pubkey(sub)),
+        }
+    }
+
+    /// Whether the variable is witness pubkey used first time on current path.
+    pub fn first_use(&mut self, id: &Identifier) -> bool {
+        self.pubkeys.contains(&id.0) && self.used.insert(id.0.to_owned())
+    }
+}
+
+// Instructions for each statement in order, checked for stack before optimization.
+pub fn codegen(
+    mut ast: Vec<Statement>,
+    target: &Target,
+    input: &[Vec<StackParam>],
+    encoding: Encoding,
+) -> Vec<Instruction> {
+    let mut bitcoin_script: Vec<Instruction> = Vec::new();
+    let mut context = WitnessContext {
+        encoding,
+        ..WitnessContext::new(input)
+    };
+
+    implicit_return(&mut ast);
 
     for node in ast {
-        compile_statement(&mut bitcoin_script, node, target);
+        compile_statement(&mut bitcoin_script, node, target, &mut context);
     }
-    let optimized_script = opcode_optimizer(bitcoin_script);
+    bitcoin_script
+}
 
-    return optimized_script;
+// Path ending with verify succeeds by the value verified, so it is compiled as return.
+// Leaving the value instead of OP_VERIFY keeps the one true element the script must end with.
+pub fn implicit_return(block: &mut [Statement]) {
+    match block.last_mut() {
+        Some(last @ Statement::VerifyStatement(..)) => {
+            if let Statement::VerifyStatement(loc, expr) = last.to_owned() {
+                *last = Statement::ExpressionStatement(loc, expr);
+            }
+        }
+        Some(Statement::IfStatement {
+            if_block,
+            else_block,
+            ..
+        }) => {
+            implicit_return(if_block);
+            if let Some(else_b) = else_block.as_mut() {
+                implicit_return(else_b);
+            }
+        }
+        Some(Statement::MatchStatement { arms, default, .. }) => {
+            for arm in arms {
+                implicit_return(&mut arm.block);
+            }
+            if let Some(default_b) = default.as_mut() {
+                implicit_return(default_b);
+            }
+        }
+        _ => (),
+    }
 }
 
-pub fn compile_statement(bitcoin_script: &mut Vec<u8>, stmt: Statement, target: &Target) {
+pub fn compile_statement(
+    bitcoin_script: &mut Vec<Instruction>,
+    stmt: Statement,
+    target: &Target,
+    context: &mut WitnessContext,
+) {
     match stmt {
         Statement::LocktimeStatement {
             loc: _,
             operand,
             op,
+            unit,
         } => {
-            push_locktime(bitcoin_script, operand, op);
+            push_locktime(bitcoin_script, operand, op, unit);
         }
         Statement::VerifyStatement(_loc, condition_expr) => {
             // compile expression first
-            compile_expression(bitcoin_script, condition_expr, target);
+            compile_expression(bitcoin_script, condition_expr, target, context);
             // push verify at last
             push_control_verify(bitcoin_script);
         }
@@ -378,38 +708,148 @@ pub fn compile_statement(bitcoin_script: &mut Vec<u8>, stmt: Statement, target:
             else_block,
         } => {
             // compile expression first
-            compile_expression(bitcoin_script, condition_expr, target);
+            let is_number = target.is_minimal_if() && context.is_number(&condition_expr);
+            compile_expression(bitcoin_script, condition_expr, target, context);
+            if is_number {
+                push_math_0notequal(bitcoin_script);
+            }
             push_control_if(bitcoin_script);
+            // Else block starts from the stack before if block.
+            let used_before = context.used.clone();
             // recursive to compile expression inside if block
             for if_stmt in if_block {
-                compile_statement(bitcoin_script, if_stmt, target);
+                compile_statement(bitcoin_script, if_stmt, target, context);
             }
             if else_block.is_some() {
+                context.used = used_before;
                 push_control_else(bitcoin_script);
                 // recursive to compile expression inside else block
                 for else_stmt in else_block.unwrap() {
-                    compile_statement(bitcoin_script, else_stmt, target);
+                    compile_statement(bitcoin_script, else_stmt, target, context);
                 }
             }
             push_control_end(bitcoin_script);
         }
+        // OP_DUP <n> OP_NUMEQUAL OP_IF OP_DROP ... OP_ELSE for each arm in order.
+        // Without default arm, the last arm is <n> OP_NUMEQUALVERIFY instead.
+        Statement::MatchStatement {
+            loc: _,
+            selector,
+            arms,
+            default,
+        } => {
+            // Selector is on top of the stack.
+            compile_expression(bitcoin_script, selector, target, context);
+            // Every arm starts from the stack before match.
+            let used_before = context.used.clone();
+            let last = arms.len() - 1;
+            let mut depth = 0;
+            for (i, arm) in arms.into_iter().enumerate() {
+                context.used = used_before.clone();
+                if i == last && default.is_none() {
+                    push_int(bitcoin_script, arm.value);
+                    push_compare(bitcoin_script, BinaryCompareOp::NumEqual);
+                    push_control_verify(bitcoin_script);
+                } else {
+                    push_arm_test(bitcoin_script, arm.value);
+                    push_control_if(bitcoin_script);
+                    push_stack_drop(bitcoin_script);
+                    depth += 1;
+                }
+                for arm_stmt in arm.block {
+                    compile_statement(bitcoin_script, arm_stmt, target, context);
+                }
+                if i != last || default.is_some() {
+                    push_control_else(bitcoin_script);
+                }
+            }
+            if let Some(default_b) = default {
+                context.used = used_before;
+                push_stack_drop(bitcoin_script);
+                for default_stmt in default_b {
+                    compile_statement(bitcoin_script, default_stmt, target, context);
+                }
+            }
+            for _ in 0..depth {
+                push_control_end(bitcoin_script);
+            }
+        }
         Statement::ExpressionStatement(_loc, expr) => {
-            compile_expression(bitcoin_script, expr, target);
+            compile_expression(bitcoin_script, expr, target, context);
         }
     }
 }
 
+// Push the operands of binary expression in the cheapest order, returning whether rhs ends under lhs.
+// Operand not touching the stack is pushed after the other, and witness item right under lhs is in place.
+// Otherwise lhs waits in alt stack while rhs takes witness items from the top, as -O0 always does.
+pub fn compile_operands(
+    bitcoin_script: &mut Vec<Instruction>,
+    lhs: Expression,
+    rhs: Expression,
+    target: &Target,
+    context: &mut WitnessContext,
+) -> bool {
+    if !context.encoding.operand_order {
+        compile_expression(bitcoin_script, lhs, target, context);
+        push_to_alt_stack(bitcoin_script);
+        compile_expression(bitcoin_script, rhs, target, context);
+        push_from_alt_stack(bitcoin_script);
+        false
+    } else if is_stack_free(&rhs) {
+        compile_expression(bitcoin_script, lhs, target, context);
+        compile_expression(bitcoin_script, rhs, target, context);
+        false
+    } else if is_stack_free(&lhs) {
+        compile_expression(bitcoin_script, rhs, target, context);
+        compile_expression(bitcoin_script, lhs, target, context);
+        true
+    } else if context.is_bare(&rhs) && !context.uses_pubkey(&lhs) {
+        compile_expression(bitcoin_script, lhs, target, context);
+        compile_expression(bitcoin_script, rhs, target, context);
+        true
+    } else {
+        compile_expression(bitcoin_script, lhs, target, context);
+        push_to_alt_stack(bitcoin_script);
+        compile_expression(bitcoin_script, rhs, target, context);
+        push_from_alt_stack(bitcoin_script);
+        false
+    }
+}
+
 // Bitcoin script follows Reverse Polish Notation.
 // Therefore, we should push operands first, then op.
 // The challenge is when we face the identifier, the given inputs.
-pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target: &Target) {
+pub fn compile_expression(
+    bitcoin_script: &mut Vec<Instruction>,
+    expr: Expression,
+    target: &Target,
+    context: &mut WitnessContext,
+) {
+    // Range form not rewritten to OP_WITHIN compares a copy of its variable first.
+    if let (Some(_), Expression::LogicalExpression { lhs, op, rhs, .. }) =
+        (range_form(&expr), &expr)
+    {
+        push_stack_dup(bitcoin_script);
+        compile_expression(bitcoin_script, *lhs.to_owned(), target, context);
+        push_stack_swap(bitcoin_script);
+        compile_expression(bitcoin_script, *rhs.to_owned(), target, context);
+        push_logical(bitcoin_script, op.to_owned());
+        return;
+    }
     match expr {
         Expression::CheckSigExpression {
             loc: _,
             operand,
             op: _,
         } => {
-            compile_factor(bitcoin_script, *operand.to_owned(), target);
+            if context.encoding.is_checksig_chain(&operand, target) {
+                if let Factor::MultiSigFactor { loc: _, m, n } = *operand {
+                    push_checksig_chain(bitcoin_script, m, n, target, context);
+                }
+                return;
+            }
+            compile_factor(bitcoin_script, *operand.to_owned(), target, context);
             match *operand {
                 Factor::SingleSigFactor {
                     loc: _,
@@ -439,7 +879,7 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
             op,
         } => {
             // To do. need to panic for wrong operand for crypto op
-            compile_expression(bitcoin_script, *operand, target);
+            compile_expression(bitcoin_script, *operand, target, context);
             push_crypto_unary(bitcoin_script, op);
         }
         Expression::LogicalExpression {
@@ -448,11 +888,8 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
             op,
             rhs,
         } => {
-            // recursive to compile condition expression
-            compile_expression(bitcoin_script, *lhs, target);
-            push_to_alt_stack(bitcoin_script);
-            compile_expression(bitcoin_script, *rhs, target);
-            push_from_alt_stack(bitcoin_script);
+            // recursive to compile condition expression, where the order doesn't matter
+            compile_operands(bitcoin_script, *lhs, *rhs, target, context);
             // push logical opcode
             push_logical(bitcoin_script, op);
         }
@@ -462,13 +899,10 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
             op,
             rhs,
         } => {
-            // recursive to compile condition expression
-            compile_expression(bitcoin_script, *lhs, target);
-            push_to_alt_stack(bitcoin_script);
-            compile_expression(bitcoin_script, *rhs, target);
-            push_from_alt_stack(bitcoin_script);
+            // recursive to compile condition expression, mirroring the operator if swapped
+            let swapped = compile_operands(bitcoin_script, *lhs, *rhs, target, context);
             // push compare opcode
-            push_compare(bitcoin_script, op);
+            push_compare(bitcoin_script, if swapped { flip(&op) } else { op });
         }
         Expression::UnaryMathExpression {
             loc: _,
@@ -476,7 +910,7 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
             op,
         } => {
             // recursive to compile condition expression
-            compile_expression(bitcoin_script, *operand, target);
+            compile_expression(bitcoin_script, *operand, target, context);
             // push math unary opcode
             push_math_unary(bitcoin_script, op);
         }
@@ -486,11 +920,11 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
             op,
             rhs,
         } => {
-            // recursive to compile condition expression
-            compile_expression(bitcoin_script, *lhs, target);
-            push_to_alt_stack(bitcoin_script);
-            compile_expression(bitcoin_script, *rhs, target);
-            push_from_alt_stack(bitcoin_script);
+            // recursive to compile condition expression, where only sub depends on the order
+            let swapped = compile_operands(bitcoin_script, *lhs, *rhs, target, context);
+            if swapped && op == BinaryMathOp::Sub {
+                push_stack_swap(bitcoin_script);
+            }
             // push math binary opcode
             push_math_binary(bitcoin_script, op);
         }
@@ -500,10 +934,33 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
             op: _,
         } => {
             // recursive to compile condition expression
-            compile_expression(bitcoin_script, *operand, target);
+            compile_expression(bitcoin_script, *operand, target, context);
             // push byte opcode
             push_bytes_len(bitcoin_script);
         }
+        Expression::WithinExpression {
+            loc: _,
+            operand,
+            min,
+            max,
+        } => {
+            // recursive to compile operands in order, keeping the former ones in alt stack
+            // unless the bounds are pushed without touching the stack
+            compile_expression(bitcoin_script, *operand, target, context);
+            if context.encoding.operand_order && is_stack_free(&min) && is_stack_free(&max) {
+                compile_expression(bitcoin_script, *min, target, context);
+                compile_expression(bitcoin_script, *max, target, context);
+            } else {
+                push_to_alt_stack(bitcoin_script);
+                compile_expression(bitcoin_script, *min, target, context);
+                push_to_alt_stack(bitcoin_script);
+                compile_expression(bitcoin_script, *max, target, context);
+                push_from_alt_stack(bitcoin_script);
+                push_from_alt_stack_under(bitcoin_script);
+            }
+            // push math ternary opcode
+            push_math_ternary(bitcoin_script);
+        }
         Expression::StringLiteral(_loc, data) => {
             push_bytes(bitcoin_script, data);
         }
@@ -513,32 +970,85 @@ pub fn compile_expression(bitcoin_script: &mut Vec<u8>, expr: Expression, target
         Expression::NumberLiteral(_loc, data) => {
             push_int(bitcoin_script, data);
         }
+        // Variable is already on the stack, but witness pubkey keeps a copy for its second use.
+        // Witness bytes fails unless of declared size.
+        Expression::Variable(_loc, id) => {
+            if context.first_use(&id) {
+                push_stack_dup(bitcoin_script);
+            }
+            if let Some(size) = context.sizes.get(&id.0) {
+                push_size_guard(bitcoin_script, *size);
+            }
+        }
+        // Arguments are already substituted into the body.
+        Expression::InlineExpression { body, .. } => {
+            compile_expression(bitcoin_script, *body, target, context);
+        }
         _ => (),
     }
 }
 
-pub fn compile_factor(bitcoin_script: &mut Vec<u8>, factor: Factor, target: &Target) {
+// Multisig by OP_CHECKSIG of each key in order, counting valid signatures.
+// Signature of the next key is brought on top by OP_SWAP, and n-of-n requires all by OP_BOOLAND.
+pub fn push_checksig_chain(
+    bitcoin_script: &mut Vec<Instruction>,
+    m: u32,
+    n: Vec<Factor>,
+    target: &Target,
+    context: &mut WitnessContext,
+) {
+    let all = m as usize == n.len();
+    for (i, factor) in n.into_iter().enumerate() {
+        if i > 0 {
+            push_stack_swap(bitcoin_script);
+        }
+        compile_factor(bitcoin_script, factor, target, context);
+        push_checksig(bitcoin_script, CheckSigType::Single);
+        if i > 0 {
+            if all {
+                push_logical(bitcoin_script, BinaryLogicalOp::BoolAnd);
+            } else {
+                push_math_binary(bitcoin_script, BinaryMathOp::Add);
+            }
+        }
+    }
+    if !all {
+        push_int(bitcoin_script, m as i64);
+        push_compare(bitcoin_script, BinaryCompareOp::NumEqual);
+    }
+}
+
+pub fn compile_factor(
+    bitcoin_script: &mut Vec<Instruction>,
+    factor: Factor,
+    target: &Target,
+    context: &mut WitnessContext,
+) {
     match factor {
         Factor::SingleSigFactor {
             loc: _,
             sig,
             pubkey,
         } => {
-            // For sig, no need to push any but check the type(must be from stack)
+            // For sig, no need to push any as it is from stack.
+            // Any other is rejected by check_type_sig_pubkey of analyze.rs, reached by check_type from every checksig.
             match *sig {
                 Expression::Variable(..) => (),
-                _ => {
-                    panic!("Signature should be from argument(stack).")
-                }
+                _ => unreachable!("Signature should be from argument(stack)."),
             }
             match *pubkey {
                 Expression::StringLiteral(..) => {
-                    compile_expression(bitcoin_script, *pubkey, target);
+                    compile_expression(bitcoin_script, *pubkey, target, context);
                 }
-                _ => {
-                    // Could be changed to embrace variable later
-                    panic!("Public key should be from string literal.")
+                // Witness pubkey is already on top of signature.
+                // On its first use, copy is tucked under signature for commitment.
+                Expression::Variable(_loc, id) => {
+                    if context.first_use(&id) {
+                        push_stack_tuck(bitcoin_script);
+                    }
                 }
+                // Rejected by check_type_sig_pubkey of analyze.rs, as signature above.
+                _ => unreachable!("Public key should be from string literal or witness."),
             }
         }
         Factor::MultiSigFactor { loc, m, n } => {
@@ -547,7 +1057,7 @@ pub fn compile_factor(bitcoin_script: &mut Vec<u8>, factor: Factor, target: &Tar
                     // push pubkey
                     for (i, e) in n.iter().enumerate() {
                         let data = e.to_owned();
-                        compile_factor(bitcoin_script, data, target);
+                        compile_factor(bitcoin_script, data, target, context);
                         push_checksig(
                             bitcoin_script,
                             // 1st key pushes OP_CHECKSIG
@@ -573,7 +1083,7 @@ pub fn compile_factor(bitcoin_script: &mut Vec<u8>, factor: Factor, target: &Tar
                     // OP_CHECKMULTISIG requires the sig list in the same order of pubkeys.
                     for e in n.iter().rev() {
                         let data = e.to_owned();
-                        compile_factor(bitcoin_script, data, target);
+                        compile_factor(bitcoin_script, data, target, context);
                     }
                     // push n
                     push_int(bitcoin_script, num);
@@ -582,107 +1092,3 @@ pub fn compile_factor(bitcoin_script: &mut Vec<u8>, factor: Factor, target: &Tar
         }
     }
 }
-
-// From compiled opcodes, optimize opcodes.
-// e.g. OP_EQUAL + OP_VERIFY => OP_EQUALVERIFY
-pub fn opcode_optimizer(bitcoin_script: Vec<u8>) -> Vec<u8> {
-    let mut optimized_script: Vec<u8> = vec![];
-    let mut i = 0;
-    // loop
-    while i < bitcoin_script.len() {
-        let op = bitcoin_script[i];
-
-        // if last element, just push and break;
-        if i == bitcoin_script.len() - 1 {
-            optimized_script.push(op);
-            break;
-        }
-
-        // Skip OP_PUSHBYTES_N
-        if op <= 0x4b {
-            optimized_script.extend_from_slice(&bitcoin_script[i..=i + op as usize]);
-            i += (op + 1) as usize;
-            continue;
-        }
-
-        match bitcoin::Opcode::from(op) {
-            // Skip OP_PUSHDATAN
-            OP_PUSHDATA1 => {
-                let num_to_read = bitcoin::script::read_scriptint(&[bitcoin_script[i + 1]])
-                    .expect("Script number is wrongly encoded.");
-                optimized_script
-                    .extend_from_slice(&bitcoin_script[i..=i + (num_to_read + 1) as usize]);
-                i += (num_to_read + 1 + 1) as usize;
-                continue;
-            }
-            OP_PUSHDATA2 => {
-                let num_to_read = bitcoin::script::read_scriptint(&bitcoin_script[i + 1..=i + 2])
-                    .expect("Script number is wrongly encoded.");
-                optimized_script
-                    .extend_from_slice(&bitcoin_script[i..=i + (num_to_read + 2) as usize]);
-                i += (num_to_read + 1 + 2) as usize;
-                continue;
-            }
-            OP_PUSHDATA4 => {
-                let num_to_read = bitcoin::script::read_scriptint(&bitcoin_script[i + 1..=i + 4])
-                    .expect("Script number is wrongly encoded.");
-                optimized_script
-                    .extend_from_slice(&bitcoin_script[i..=i + (num_to_read + 4) as usize]);
-                i += (num_to_read + 1 + 4) as usize;
-                continue;
-            }
-            // else try optimizing
-            OP_EQUAL => {
-                let next = bitcoin::Opcode::from(bitcoin_script[i + 1]);
-                if next == OP_VERIFY {
-                    optimized_script.push(OP_EQUALVERIFY.to_u8());
-                    i += 2;
-                    continue;
-                }
-            }
-            OP_NUMEQUAL => {
-                let next = bitcoin::Opcode::from(bitcoin_script[i + 1]);
-                if next == OP_VERIFY {
-                    optimized_script.push(OP_NUMEQUALVERIFY.to_u8());
-                    i += 2;
-                    continue;
-                }
-            }
-            OP_CHECKSIG => {
-                let next = bitcoin::Opcode::from(bitcoin_script[i + 1]);
-                if next == OP_VERIFY {
-                    optimized_script.push(OP_CHECKSIGVERIFY.to_u8());
-                    i += 2;
-                    continue;
-                }
-            }
-            OP_CHECKMULTISIG => {
-                let next = bitcoin::Opcode::from(bitcoin_script[i + 1]);
-                if next == OP_VERIFY {
-                    optimized_script.push(OP_CHECKMULTISIGVERIFY.to_u8());
-                    i += 2;
-                    continue;
-                }
-            }
-            OP_SHA256 => {
-                let next = bitcoin::Opcode::from(bitcoin_script[i + 1]);
-                if next == OP_RIPEMD160 {
-                    optimized_script.push(OP_HASH160.to_u8());
-                    i += 2;
-                    continue;
-                }
-                if next == OP_SHA256 {
-                    optimized_script.push(OP_HASH256.to_u8());
-                    i += 2;
-                    continue;
-                }
-            }
-            //OP_NOT => {},
-            _ => {}
-        }
-        optimized_script.push(op);
-        i += 1;
-    }
-
-    return optimized_script;
-}
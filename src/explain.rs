@@ -0,0 +1,194 @@
+use std::fmt;
+
+use crate::ast::*;
+use crate::path::*;
+use crate::source::*;
+
+// Policy of the path, from what must hold at each step.
+// Selector of branch and match is left to the witness template.
+pub fn path_policy(path: &Path) -> PathPolicy {
+    let mut policy = PathPolicy::default();
+    for step in &path.steps {
+        match step {
+            Step::Branch {
+                condition: Expression::Variable(..),
+                ..
+            } => (),
+            Step::Branch {
+                condition,
+                taken: true,
+            } => require(condition, &mut policy),
+            Step::Branch {
+                condition,
+                taken: false,
+            } => {
+                let negated = Expression::UnaryMathExpression {
+                    loc: Location::default(),
+                    operand: Box::new((*condition).to_owned()),
+                    op: UnaryMathOp::Not,
+                };
+                policy.conditions.push(expression_source(&negated));
+            }
+            Step::Arm { .. } => (),
+            Step::Statement(Statement::LocktimeStatement {
+                operand, op, unit, ..
+            }) => policy.timelocks.push(timelock(*operand, op, unit)),
+            Step::Statement(Statement::VerifyStatement(_loc, expr))
+            | Step::Statement(Statement::ExpressionStatement(_loc, expr)) => {
+                require(expr, &mut policy)
+            }
+            Step::Statement(_) => (),
+        }
+    }
+    policy
+}
+
+// Split the expression that must be true into requirements.
+fn require(expr: &Expression, policy: &mut PathPolicy) {
+    match expr {
+        Expression::InlineExpression { body, .. } => require(body, policy),
+        Expression::LogicalExpression {
+            lhs,
+            op: BinaryLogicalOp::BoolAnd,
+            rhs,
+            ..
+        } => {
+            require(lhs, policy);
+            require(rhs, policy);
+        }
+        Expression::BooleanLiteral(_, true) => (),
+        Expression::CheckSigExpression { operand, .. } => {
+            policy.signatures.push(signature(operand));
+        }
+        Expression::CompareExpression {
+            lhs,
+            op: BinaryCompareOp::Equal,
+            rhs,
+            ..
+        } => match preimage(lhs, rhs).or_else(|| preimage(rhs, lhs)) {
+            Some(preimage) => policy.preimages.push(preimage),
+            None => policy.conditions.push(expression_source(expr)),
+        },
+        _ => policy.conditions.push(expression_source(expr)),
+    }
+}
+
+fn signature(factor: &Factor) -> SignaturePolicy {
+    match factor {
+        Factor::SingleSigFactor { pubkey, .. } => SignaturePolicy {
+            threshold: 1,
+            pubkeys: vec![pubkey_name(pubkey)],
+        },
+        Factor::MultiSigFactor { m, n, .. } => SignaturePolicy {
+            threshold: *m,
+            pubkeys: n
+                .iter()
+                .flat_map(|factor| signature(factor).pubkeys)
+                .collect(),
+        },
+    }
+}
+
+fn pubkey_name(pubkey: &Expression) -> String {
+    match pubkey {
+        Expression::StringLiteral(_, s) => s.to_owned(),
+        Expression::Variable(_, id) => format!("<{}>", id.0),
+        _ => expression_source(pubkey),
+    }
+}
+
+// Hash of witness item compared to digest, e.g. `sha256 secret == "..."`.
+fn preimage(hashed: &Expression, digest: &Expression) -> Option<PreimagePolicy> {
+    let Expression::StringLiteral(_, digest) = digest else {
+        return None;
+    };
+    let mut hash = vec![];
+    let mut operand = hashed;
+    while let Expression::UnaryCryptoExpression {
+        operand: inner, op, ..
+    } = operand
+    {
+        hash.push(crypto_source(op));
+        operand = inner;
+    }
+    match (operand, hash.is_empty()) {
+        (Expression::Variable(_, id), false) => Some(PreimagePolicy {
+            preimage: id.0.to_owned(),
+            hash: hash.join(" "),
+            digest: digest.to_owned(),
+        }),
+        _ => None,
+    }
+}
+
+fn timelock(operand: i64, op: &LocktimeOp, unit: &LocktimeUnit) -> TimelockPolicy {
+    match (op, unit) {
+        (LocktimeOp::Csv, LocktimeUnit::Second) => TimelockPolicy::RelativeSeconds(operand),
+        (LocktimeOp::Csv, _) => TimelockPolicy::RelativeBlocks(operand),
+        (LocktimeOp::Cltv, unit) if unit.is_time(operand) => TimelockPolicy::AbsoluteTime(operand),
+        (LocktimeOp::Cltv, _) => TimelockPolicy::AbsoluteHeight(operand),
+    }
+}
+
+/// Plain-language summary of every spending path, for reviewers.
+pub fn explain(paths: &[PathReport]) -> String {
+    paths
+        .iter()
+        .map(|path| {
+            let label = match &path.name {
+                Some(name) => format!("Path {:?}", name),
+                None => format!("Path {}", path.stack),
+            };
+            format!(
+                "{} (witness: {})\n{}",
+                label,
+                path.witness.join(" "),
+                path.policy
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl fmt::Display for PathPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for signature in &self.signatures {
+            match signature.pubkeys.as_slice() {
+                [pubkey] => writeln!(f, "  - Signature by {}", pubkey)?,
+                pubkeys => writeln!(
+                    f,
+                    "  - Signatures by {} of {}: {}",
+                    signature.threshold,
+                    pubkeys.len(),
+                    pubkeys.join(", ")
+                )?,
+            }
+        }
+        for preimage in &self.preimages {
+            writeln!(
+                f,
+                "  - Preimage <{}> whose {} is {}",
+                preimage.preimage, preimage.hash, preimage.digest
+            )?;
+        }
+        for timelock in &self.timelocks {
+            match timelock {
+                TimelockPolicy::RelativeBlocks(n) => {
+                    writeln!(f, "  - {} blocks after the output is confirmed", n)?
+                }
+                TimelockPolicy::RelativeSeconds(n) => {
+                    writeln!(f, "  - {} seconds after the output is confirmed", n)?
+                }
+                TimelockPolicy::AbsoluteHeight(n) => writeln!(f, "  - From block height {}", n)?,
+                TimelockPolicy::AbsoluteTime(n) => writeln!(f, "  - From unix time {}", n)?,
+            }
+        }
+        for condition in &self.conditions {
+            writeln!(f, "  - Condition {}", condition)?;
+        }
+        if self.signatures.is_empty() {
+            writeln!(f, "  - No signature required")?;
+        }
+        Ok(())
+    }
+}
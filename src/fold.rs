@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::interpret::*;
+use crate::optimize::is_stack_free;
+use crate::source::*;
+
+// Fold expressions known at compile time, and simplify the ones equivalent to their operand.
+// Runs after analysis, on the script to be compiled, so analysis sees the script as written.
+pub fn fold_constants(
+    ast: &mut Vec<Statement>,
+    input: &[Vec<StackParam>],
+    target: &Target,
+) -> Vec<Optimization> {
+    let types: HashMap<String, Type> = input
+        .iter()
+        .flatten()
+        .map(|item| (item.identifier.0.to_owned(), item.ty.to_owned()))
+        .collect();
+    let ty = |id: &Identifier| types.get(&id.0).cloned();
+    let mut log = vec![];
+    fold_block(ast, false, target, &ty, &mut log);
+    log
+}
+
+// `tail` is whether statements run after the block, as the shared tail of if or match statement.
+fn fold_block(
+    block: &mut Vec<Statement>,
+    tail: bool,
+    target: &Target,
+    ty: &dyn Fn(&Identifier) -> Option<Type>,
+    log: &mut Vec<Optimization>,
+) {
+    let last = block.len().saturating_sub(1);
+    for (i, stmt) in block.iter_mut().enumerate() {
+        // Statements run after this one, in the block or the tail.
+        let followed = tail || i != last;
+        match stmt {
+            Statement::IfStatement {
+                condition_expr,
+                if_block,
+                else_block,
+                ..
+            } => {
+                // OP_IF takes only 0 or 1 under MINIMALIF, so more than the truth of condition matters,
+                // e.g. `!!b` keeps a witness bool of other value from being taken as is.
+                fold_expression(condition_expr, !target.is_minimal_if(), ty, log);
+                fold_block(if_block, followed, target, ty, log);
+                if let Some(else_b) = else_block.as_mut() {
+                    fold_block(else_b, followed, target, ty, log);
+                }
+            }
+            Statement::MatchStatement {
+                selector,
+                arms,
+                default,
+                ..
+            } => {
+                fold_expression(selector, false, ty, log);
+                for arm in arms {
+                    fold_block(&mut arm.block, followed, target, ty, log);
+                }
+                if let Some(default_b) = default.as_mut() {
+                    fold_block(default_b, followed, target, ty, log);
+                }
+            }
+            Statement::VerifyStatement(loc, expr) => {
+                fold_expression(expr, true, ty, log);
+                if followed && matches!(expr, Expression::BooleanLiteral(_, true)) {
+                    log.push(Optimization {
+                        loc: loc.to_owned(),
+                        message: "Removed `verify true;`.".to_string(),
+                    });
+                }
+            }
+            // Script succeeds by the truth of the last item, as verify does.
+            Statement::ExpressionStatement(_loc, expr) => fold_expression(expr, true, ty, log),
+            Statement::LocktimeStatement { .. } => (),
+        }
+    }
+
+    // Verify of true is a no-op, but the one ending the path leaves the value it succeeds by.
+    let mut i = 0;
+    block.retain(|stmt| {
+        i += 1;
+        (i - 1 == last && !tail)
+            || !matches!(
+                stmt,
+                Statement::VerifyStatement(_loc, Expression::BooleanLiteral(_, true))
+            )
+    });
+}
+
+// `boolean` is whether only the truth of the value matters, as in if, verify and logical operand.
+fn fold_expression(
+    expression: &mut Expression,
+    boolean: bool,
+    ty: &dyn Fn(&Identifier) -> Option<Type>,
+    log: &mut Vec<Optimization>,
+) {
+    let child_boolean = match expression {
+        Expression::LogicalExpression { .. } => true,
+        Expression::InlineExpression { .. } => boolean,
+        _ => false,
+    };
+    for child in expression.sub_expressions_mut() {
+        fold_expression(child, child_boolean, ty, log);
+    }
+
+    let (rewrite, verb) = match fold_literal(expression, ty) {
+        Some(literal) => (literal, "Folded"),
+        None => match simplify(expression, boolean, ty) {
+            Some(operand) => (operand, "Simplified"),
+            None => return,
+        },
+    };
+    log.push(Optimization {
+        loc: expression.to_owned().loc(),
+        message: format!(
+            "{} `{}` into `{}`.",
+            verb,
+            expression_source(expression),
+            expression_source(&rewrite)
+        ),
+    });
+    *expression = rewrite;
+}
+
+// Literal of the value, if every operand is literal and script computes the same.
+fn fold_literal(
+    expression: &Expression,
+    ty: &dyn Fn(&Identifier) -> Option<Type>,
+) -> Option<Expression> {
+    if is_literal(expression) || !is_stack_free(expression) {
+        return None;
+    }
+    let operands = expression.sub_expressions();
+    if operands.is_empty() || !operands.iter().all(|operand| is_literal(operand)) {
+        return None;
+    }
+    // Arithmetic opcodes take numbers up to 4 bytes, so larger ones are left to fail in script.
+    if operands.iter().any(|operand| match operand {
+        Expression::NumberLiteral(_loc, n) => !is_script_number(*n),
+        _ => false,
+    }) {
+        return None;
+    }
+    let loc = expression.to_owned().loc();
+    match Facts::default().evaluate(expression) {
+        Value::Number(n) if expression.condition(ty) == Condition::Boolean => {
+            Some(Expression::BooleanLiteral(loc, n != 0))
+        }
+        Value::Number(n) if is_script_number(n) => Some(Expression::NumberLiteral(loc, n)),
+        Value::Bytes(bytes) => Some(Expression::StringLiteral(loc, hex::encode(bytes))),
+        _ => None,
+    }
+}
+
+// `!!x`, `x + 0`, `x - 0` and `max(a, a)` into the operand.
+fn simplify(
+    expression: &Expression,
+    boolean: bool,
+    ty: &dyn Fn(&Identifier) -> Option<Type>,
+) -> Option<Expression> {
+    match expression {
+        // Double not casts to 0 or 1, which only boolean already is, unless only its truth matters.
+        Expression::UnaryMathExpression {
+            operand,
+            op: UnaryMathOp::Not,
+            ..
+        } => match &**operand {
+            Expression::UnaryMathExpression {
+                operand,
+                op: UnaryMathOp::Not,
+                ..
+            } => match (operand.condition(ty), boolean) {
+                (Condition::Boolean, _)
+                | (Condition::Witness(_), true)
+                | (Condition::Number, true) => Some((**operand).to_owned()),
+                _ => None,
+            },
+            _ => None,
+        },
+        Expression::BinaryMathExpression {
+            lhs,
+            op: BinaryMathOp::Add,
+            rhs,
+            ..
+        } => match (&**lhs, &**rhs) {
+            (operand, Expression::NumberLiteral(_loc, 0))
+            | (Expression::NumberLiteral(_loc, 0), operand)
+                if operand.condition(ty) == Condition::Number =>
+            {
+                Some(operand.to_owned())
+            }
+            _ => None,
+        },
+        Expression::BinaryMathExpression {
+            lhs,
+            op: BinaryMathOp::Sub,
+            rhs,
+            ..
+        } => match &**rhs {
+            Expression::NumberLiteral(_loc, 0) if lhs.condition(ty) == Condition::Number => {
+                Some((**lhs).to_owned())
+            }
+            _ => None,
+        },
+        // Witness item is used once, so only the same stack-free operand is repeated.
+        Expression::BinaryMathExpression {
+            lhs,
+            op: BinaryMathOp::Max | BinaryMathOp::Min,
+            rhs,
+            ..
+        } if is_stack_free(lhs) && normalize(lhs) == normalize(rhs) => Some((**lhs).to_owned()),
+        _ => None,
+    }
+}
+
+fn is_literal(expression: &Expression) -> bool {
+    matches!(
+        expression,
+        Expression::NumberLiteral(..)
+            | Expression::BooleanLiteral(..)
+            | Expression::StringLiteral(..)
+    )
+}
+
+// Number of 4 bytes, which arithmetic opcodes take.
+fn is_script_number(n: i64) -> bool {
+    n.unsigned_abs() <= i32::MAX as u64
+}
@@ -0,0 +1,109 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::ast::*;
+use crate::parse_library;
+
+// File on the import chain, to detect cycle and report it by name.
+struct ImportedFile {
+    path: PathBuf,
+    name: String,
+}
+
+// Resolve the import graph from the root file.
+// Constants and functions of every reachable file are merged into the root,
+// so the rest of the pipeline sees a single contract.
+// A file imported several times is loaded once, while a cycle is an error.
+pub fn resolve_imports(utxo: &mut Bithoven, root: &Path) -> Result<(), CompileError> {
+    let mut import_stack = vec![ImportedFile {
+        path: fs::canonicalize(root).unwrap_or(root.to_path_buf()),
+        name: root.display().to_string(),
+    }];
+    let mut loaded: HashSet<PathBuf> = HashSet::new();
+    let mut constants: Vec<Constant> = vec![];
+    let mut functions: Vec<Function> = vec![];
+
+    let base = root.parent().unwrap_or(Path::new(""));
+    for import in &utxo.imports {
+        resolve_import(
+            import,
+            base,
+            &mut import_stack,
+            &mut loaded,
+            &mut constants,
+            &mut functions,
+        )?;
+    }
+
+    // Imported definitions come first, as if written on top of the root file.
+    constants.append(&mut utxo.constants);
+    functions.append(&mut utxo.functions);
+    utxo.constants = constants;
+    utxo.functions = functions;
+
+    Ok(())
+}
+
+// Path is relative to the directory of importing file.
+fn resolve_import(
+    import: &Import,
+    base: &Path,
+    import_stack: &mut Vec<ImportedFile>,
+    loaded: &mut HashSet<PathBuf>,
+    constants: &mut Vec<Constant>,
+    functions: &mut Vec<Function>,
+) -> Result<(), CompileError> {
+    let path = base.join(&import.path);
+    let name = path.display().to_string();
+    let canonical = fs::canonicalize(&path).map_err(|e| CompileError {
+        loc: import.loc.to_owned(),
+        kind: ErrorKind::ImportError(format!("Cannot resolve import {:?}: {}.", name, e)),
+    })?;
+
+    if let Some(i) = import_stack.iter().position(|f| f.path == canonical) {
+        let cycle: Vec<&str> = import_stack[i..]
+            .iter()
+            .map(|f| f.name.as_str())
+            .chain(std::iter::once(name.as_str()))
+            .collect();
+        return Err(CompileError {
+            loc: import.loc.to_owned(),
+            kind: ErrorKind::CyclicImport(format!(
+                "Import cycle detected: {}.",
+                cycle.join(" -> ")
+            )),
+        });
+    }
+    if !loaded.insert(canonical.to_owned()) {
+        return Ok(());
+    }
+
+    let source = fs::read_to_string(&canonical).map_err(|e| CompileError {
+        loc: import.loc.to_owned(),
+        kind: ErrorKind::ImportError(format!("Cannot read import {:?}: {}.", name, e)),
+    })?;
+    let mut library = parse_library(&source, &name)?;
+
+    import_stack.push(ImportedFile {
+        path: canonical,
+        name,
+    });
+    let nested_base = path.parent().unwrap_or(Path::new(""));
+    for nested in &library.imports {
+        resolve_import(
+            nested,
+            nested_base,
+            import_stack,
+            loaded,
+            constants,
+            functions,
+        )?;
+    }
+    import_stack.pop();
+
+    constants.append(&mut library.constants);
+    functions.append(&mut library.functions);
+
+    Ok(())
+}
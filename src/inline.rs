@@ -0,0 +1,376 @@
+use std::collections::HashMap;
+
+use crate::analyze::{check_byte_size, check_pubkey_literal, literal_length};
+use crate::ast::*;
+use crate::source::*;
+
+// Inline every constant and function call in the script.
+// Constant is replaced by its literal, located at the use site.
+// Each call is replaced by InlineExpression holding the function body,
+// where the parameters are substituted by the arguments of the call.
+// As the body is analyzed at each call site, stack rules are enforced through the inlined body.
+pub fn inline_functions(utxo: &mut Bithoven) -> Result<(), CompileError> {
+    let constant_table = build_constant_table(&utxo.constants)?;
+    for stack in &utxo.input_stack {
+        check_shadowing(stack, &constant_table)?;
+    }
+    for function in utxo.functions.iter_mut() {
+        check_shadowing(&function.params, &constant_table)?;
+        substitute_constant(&mut function.body, &constant_table);
+    }
+
+    let function_table = build_function_table(&utxo.functions)?;
+
+    for stmt in utxo.output_script.iter_mut() {
+        inline_statement(stmt, &constant_table, &function_table)?;
+    }
+
+    Ok(())
+}
+
+// Check the duplication of constant, and its literal against declared type.
+pub fn build_constant_table(
+    constants: &Vec<Constant>,
+) -> Result<HashMap<String, Constant>, CompileError> {
+    let mut constant_table: HashMap<String, Constant> = HashMap::new();
+
+    for constant in constants {
+        if constant_table.contains_key(&constant.identifier.0) {
+            return Err(CompileError {
+                loc: constant.loc.to_owned(),
+                kind: ErrorKind::DuplicateVariable(format!(
+                    "The name of constant cannot be duplicate: {:?} already exists.",
+                    constant.identifier.0,
+                )),
+            });
+        }
+        let is_valid = match (&constant.ty, &constant.value) {
+            (Type::Boolean, Expression::BooleanLiteral(..))
+            | (Type::Number, Expression::NumberLiteral(..))
+            | (Type::String, Expression::StringLiteral(..))
+            | (Type::PublicKey, Expression::StringLiteral(..)) => true,
+            (Type::Bytes(n), Expression::StringLiteral(_loc, data)) => literal_length(data) == *n,
+            _ => false,
+        };
+        if !is_valid {
+            return Err(CompileError {
+                loc: constant.loc.to_owned(),
+                kind: ErrorKind::TypeMismatch(format!(
+                    "Constant {:?} must be literal of type {:?} but: {:?}.",
+                    constant.identifier.0, constant.ty, constant.value,
+                )),
+            });
+        }
+
+        if let (Type::PublicKey, Expression::StringLiteral(loc, data)) =
+            (&constant.ty, &constant.value)
+        {
+            check_pubkey_literal(loc, data)?;
+        }
+
+        constant_table.insert(constant.identifier.0.to_owned(), constant.to_owned());
+    }
+
+    Ok(constant_table)
+}
+
+// Stack item or parameter cannot take the name of constant.
+fn check_shadowing(
+    params: &Vec<StackParam>,
+    constant_table: &HashMap<String, Constant>,
+) -> Result<(), CompileError> {
+    for param in params {
+        if let Some(constant) = constant_table.get(&param.identifier.0) {
+            return Err(CompileError {
+                loc: param.loc.to_owned(),
+                kind: ErrorKind::DuplicateVariable(format!(
+                    "The name of constant cannot be reused: {:?} already exists at {}.",
+                    param.identifier.0, constant.loc,
+                )),
+            });
+        }
+    }
+    Ok(())
+}
+
+// Replace constant with its literal.
+pub fn substitute_constant(
+    expression: &mut Expression,
+    constant_table: &HashMap<String, Constant>,
+) {
+    if let Expression::Variable(loc, id) = expression {
+        if let Some(constant) = constant_table.get(&id.0) {
+            let mut value = constant.value.to_owned();
+            *value.loc_mut() = loc.to_owned();
+            *expression = value;
+        }
+        return;
+    }
+
+    for child in expression.sub_expressions_mut() {
+        substitute_constant(child, constant_table);
+    }
+}
+
+// Check the duplication of function and its parameters.
+// Body can only refer to its parameters, and every parameter must be used.
+pub fn build_function_table(
+    functions: &Vec<Function>,
+) -> Result<HashMap<String, Function>, CompileError> {
+    let mut function_table: HashMap<String, Function> = HashMap::new();
+
+    for function in functions {
+        if function_table.contains_key(&function.identifier.0) {
+            return Err(CompileError {
+                loc: function.loc.to_owned(),
+                kind: ErrorKind::DuplicateFunction(format!(
+                    "The name of function cannot be duplicate: {:?} already exists.",
+                    function.identifier.0,
+                )),
+            });
+        }
+        // Signature and pubkey are checked where they are written, so they come from arguments or literals.
+        if matches!(function.ty, Type::Signature | Type::PublicKey) {
+            return Err(CompileError {
+                loc: function.loc.to_owned(),
+                kind: ErrorKind::TypeMismatch(format!(
+                    "Function must return bool, number or string but: {:?}.",
+                    function.identifier.0,
+                )),
+            });
+        }
+
+        let mut used: HashMap<String, usize> = HashMap::new();
+        for param in &function.params {
+            check_byte_size(param)?;
+            if used.insert(param.identifier.0.to_owned(), 0).is_some() {
+                return Err(CompileError {
+                    loc: param.loc.to_owned(),
+                    kind: ErrorKind::DuplicateVariable(format!(
+                        "The name of parameter cannot be duplicate: {:?} already exists.",
+                        param.identifier.0,
+                    )),
+                });
+            }
+        }
+        count_parameter_use(&function.body, &mut used)?;
+        for param in &function.params {
+            if used
+                .get(&param.identifier.0)
+                .is_some_and(|count| *count == 0)
+            {
+                return Err(CompileError {
+                    loc: param.loc.to_owned(),
+                    kind: ErrorKind::UnusedVariable(format!(
+                        "Parameter unused: {:?}.",
+                        param.identifier
+                    )),
+                });
+            }
+        }
+
+        function_table.insert(function.identifier.0.to_owned(), function.to_owned());
+    }
+
+    Ok(function_table)
+}
+
+// Count how many times each parameter is used in function body.
+// Any other variable is undefined, as function can't see the stack directly.
+fn count_parameter_use(
+    expression: &Expression,
+    used: &mut HashMap<String, usize>,
+) -> Result<(), CompileError> {
+    match expression {
+        Expression::Variable(loc, id) => match used.get_mut(&id.0) {
+            Some(count) => {
+                *count += 1;
+                Ok(())
+            }
+            None => Err(CompileError {
+                loc: loc.to_owned(),
+                kind: ErrorKind::UndefinedVariable(format!(
+                    "Undefined variable in function body: {:?}.",
+                    id.0
+                )),
+            }),
+        },
+        _ => {
+            for child in expression.sub_expressions() {
+                count_parameter_use(child, used)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+pub fn inline_statement(
+    stmt: &mut Statement,
+    constant_table: &HashMap<String, Constant>,
+    function_table: &HashMap<String, Function>,
+) -> Result<(), CompileError> {
+    match stmt {
+        Statement::IfStatement {
+            loc: _,
+            condition_expr,
+            if_block,
+            else_block,
+        } => {
+            substitute_constant(condition_expr, constant_table);
+            inline_expression(condition_expr, function_table, &mut vec![])?;
+            for if_stmt in if_block {
+                inline_statement(if_stmt, constant_table, function_table)?;
+            }
+            if let Some(else_b) = else_block.as_mut() {
+                for else_stmt in else_b {
+                    inline_statement(else_stmt, constant_table, function_table)?;
+                }
+            }
+            Ok(())
+        }
+        Statement::MatchStatement {
+            loc: _,
+            selector: _,
+            arms,
+            default,
+        } => {
+            for arm in arms {
+                for arm_stmt in &mut arm.block {
+                    inline_statement(arm_stmt, constant_table, function_table)?;
+                }
+            }
+            if let Some(default_b) = default.as_mut() {
+                for default_stmt in default_b {
+                    inline_statement(default_stmt, constant_table, function_table)?;
+                }
+            }
+            Ok(())
+        }
+        Statement::VerifyStatement(_loc, expr) => {
+            substitute_constant(expr, constant_table);
+            inline_expression(expr, function_table, &mut vec![])
+        }
+        Statement::ExpressionStatement(_loc, expr) => {
+            substitute_constant(expr, constant_table);
+            inline_expression(expr, function_table, &mut vec![])
+        }
+        Statement::LocktimeStatement { .. } => Ok(()),
+    }
+}
+
+// `call_stack` holds functions being inlined to detect recursion.
+pub fn inline_expression(
+    expression: &mut Expression,
+    function_table: &HashMap<String, Function>,
+    call_stack: &mut Vec<String>,
+) -> Result<(), CompileError> {
+    if let Expression::CallExpression {
+        loc,
+        identifier,
+        args,
+    } = expression
+    {
+        let function = match function_table.get(&identifier.0) {
+            Some(function) => function,
+            None => {
+                return Err(CompileError {
+                    loc: loc.to_owned(),
+                    kind: ErrorKind::UndefinedFunction(format!(
+                        "Undefined function: {:?}.",
+                        identifier.0
+                    )),
+                })
+            }
+        };
+        if call_stack.contains(&identifier.0) {
+            return Err(CompileError {
+                loc: loc.to_owned(),
+                kind: ErrorKind::RecursiveFunction(format!(
+                    "Function cannot call itself, as it is inlined: {}.",
+                    call_stack.join(" -> ") + " -> " + &identifier.0
+                )),
+            });
+        }
+        if function.params.len() != args.len() {
+            return Err(CompileError {
+                loc: loc.to_owned(),
+                kind: ErrorKind::InvalidArgument(format!(
+                    "Function {:?} takes {} argument(s) but {} given.",
+                    identifier.0,
+                    function.params.len(),
+                    args.len()
+                )),
+            });
+        }
+
+        // Arguments are inlined first, so the body only sees plain expressions.
+        for arg in args.iter_mut() {
+            inline_expression(arg, function_table, call_stack)?;
+        }
+        let bindings: HashMap<String, Expression> = function
+            .params
+            .iter()
+            .zip(args.iter())
+            .map(|(param, arg)| (param.identifier.0.to_owned(), arg.to_owned()))
+            .collect();
+        let mut body = substitute_parameter(&function.body, &bindings);
+
+        call_stack.push(identifier.0.to_owned());
+        inline_expression(&mut body, function_table, call_stack)?;
+        call_stack.pop();
+
+        *expression = Expression::InlineExpression {
+            loc: loc.to_owned(),
+            identifier: identifier.to_owned(),
+            definition: function.loc.to_owned(),
+            params: function.params.to_owned(),
+            ty: function.ty.to_owned(),
+            args: args.to_owned(),
+            body: Box::new(body),
+        };
+        return Ok(());
+    }
+
+    for child in expression.sub_expressions_mut() {
+        inline_expression(child, function_table, call_stack)?;
+    }
+    Ok(())
+}
+
+// Replace parameter with the argument expression.
+// Argument keeps the location of call site.
+pub fn substitute_parameter(
+    expression: &Expression,
+    bindings: &HashMap<String, Expression>,
+) -> Expression {
+    if let Expression::Variable(_loc, id) = expression {
+        if let Some(arg) = bindings.get(&id.0) {
+            return arg.to_owned();
+        }
+    }
+
+    let mut substituted = expression.to_owned();
+    for child in substituted.sub_expressions_mut() {
+        *child = substitute_parameter(child, bindings);
+    }
+    substituted
+}
+
+// Errors raised inside an inlined body point at the call site,
+// and mention both the original location and the function definition.
+pub fn inline_error(
+    mut error: CompileError,
+    loc: &Location,
+    identifier: &Identifier,
+    definition: &Location,
+) -> CompileError {
+    let note = format!(
+        " (at {} in function {:?} defined at {})",
+        error.loc, identifier.0, definition
+    );
+    if let Some(msg) = error.kind.message_mut() {
+        msg.push_str(&note);
+    }
+    error.loc = loc.to_owned();
+    error
+}
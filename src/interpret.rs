@@ -0,0 +1,493 @@
+use bitcoin::hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+
+use crate::analyze::path_label;
+use crate::ast::*;
+use crate::path::*;
+use crate::source::*;
+
+// Abstract interpretation of each path.
+// Value known at compile time is computed as script does,
+// and comparison of unknown value is kept as fact for the rest of the path.
+// Path is dead if any condition on it can never hold, which is warned as it can't be spent.
+pub fn dead_path_warnings(paths: &[Path], names: &[String]) -> Vec<CompileWarning> {
+    let mut warnings: Vec<CompileWarning> = vec![];
+    for (branch, path) in paths.iter().enumerate() {
+        if let Some((loc, message)) = find_dead_step(path) {
+            let warning = CompileWarning {
+                loc,
+                kind: WarningKind::DeadPath(format!("{} ({})", message, path_label(names, branch))),
+            };
+            warnings.push(warning);
+        }
+    }
+    warnings
+}
+
+// First step on the path whose condition can never hold, with the reason.
+fn find_dead_step(path: &Path) -> Option<(Location, String)> {
+    let mut facts = Facts::default();
+    for step in &path.steps {
+        let (condition, truth, loc, message) = match step {
+            Step::Branch { condition, taken } => (
+                *condition,
+                *taken,
+                (*condition).to_owned().loc(),
+                format!(
+                    "{} block is never taken, as the condition can't be {}.",
+                    if *taken { "If" } else { "Else" },
+                    taken
+                ),
+            ),
+            Step::Statement(Statement::VerifyStatement(loc, expr)) => (
+                expr,
+                true,
+                loc.to_owned(),
+                "Verify can never pass on this path.".to_string(),
+            ),
+            Step::Statement(Statement::ExpressionStatement(loc, expr)) => (
+                expr,
+                true,
+                loc.to_owned(),
+                "Return value can never be true on this path.".to_string(),
+            ),
+            Step::Arm {
+                selector,
+                arms,
+                taken,
+                ..
+            } => {
+                if let Some(dead) = assume_arm(&mut facts, selector, arms, *taken) {
+                    return Some(dead);
+                }
+                continue;
+            }
+            Step::Statement(_) => continue,
+        };
+        if !facts.assume(condition, truth) {
+            return Some((loc, message));
+        }
+    }
+    None
+}
+
+// Arm is taken if the selector equals its value, after none of the arms before it does.
+fn assume_arm(
+    facts: &mut Facts,
+    selector: &Expression,
+    arms: &[MatchArm],
+    taken: Option<usize>,
+) -> Option<(Location, String)> {
+    let tested = match taken {
+        Some(k) => &arms[..=k],
+        None => arms,
+    };
+    for (i, arm) in tested.iter().enumerate() {
+        let truth = taken == Some(i);
+        let condition = Expression::CompareExpression {
+            loc: arm.loc.to_owned(),
+            lhs: Box::new(selector.to_owned()),
+            op: BinaryCompareOp::NumEqual,
+            rhs: Box::new(Expression::NumberLiteral(arm.loc.to_owned(), arm.value)),
+        };
+        if !facts.assume(&condition, truth) {
+            let message = if truth {
+                format!(
+                    "Arm is never taken, as the selector can't be {}.",
+                    arm.value
+                )
+            } else {
+                format!(
+                    "{} is never taken, as the selector is always {}.",
+                    match taken {
+                        Some(k) => format!("Arm {}", arms[k].value),
+                        None => "Default arm".to_string(),
+                    },
+                    arm.value
+                )
+            };
+            return Some((arm.loc.to_owned(), message));
+        }
+    }
+    None
+}
+
+// Value of expression, if known at compile time.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Number(i64),
+    Bytes(Vec<u8>),
+    Unknown,
+}
+
+impl Value {
+    /// Stack item, as script pushes it.
+    pub fn to_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            Value::Number(n) => {
+                let mut buf = [0u8; 8];
+                let len = bitcoin::script::write_scriptint(&mut buf, *n);
+                Some(buf[..len].to_vec())
+            }
+            Value::Bytes(bytes) => Some(bytes.to_owned()),
+            Value::Unknown => None,
+        }
+    }
+
+    /// Number of stack item, which is up to 4 bytes in script.
+    pub fn to_number(&self) -> Option<i64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Bytes(bytes) if bytes.len() <= 4 => bitcoin::script::read_scriptint(bytes).ok(),
+            _ => None,
+        }
+    }
+
+    /// Boolean of stack item, as OP_IF and OP_VERIFY cast it.
+    pub fn to_bool(&self) -> Option<bool> {
+        let bytes = self.to_bytes()?;
+        Some(bytes.iter().enumerate().any(|(i, byte)| {
+            // Negative zero is false.
+            !(*byte == 0 || (i == bytes.len() - 1 && *byte == 0x80))
+        }))
+    }
+}
+
+// What is known about an unknown value on the path.
+#[derive(Clone, Debug)]
+enum Fact {
+    Equal(Vec<u8>),
+    NotEqual(Vec<u8>),
+    // Half-open range of number.
+    Range(i64, i64),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Facts {
+    facts: Vec<(Expression, Fact)>,
+}
+
+impl Facts {
+    /// Take the condition as holding to be `truth`. False if it can't.
+    pub fn assume(&mut self, condition: &Expression, truth: bool) -> bool {
+        if self
+            .evaluate(condition)
+            .to_bool()
+            .is_some_and(|value| value != truth)
+        {
+            return false;
+        }
+        self.learn(condition, truth);
+        self.is_consistent()
+    }
+
+    pub fn evaluate(&self, expression: &Expression) -> Value {
+        let value = match expression {
+            Expression::NumberLiteral(_loc, n) => Value::Number(*n),
+            Expression::BooleanLiteral(_loc, b) => Value::Number(*b as i64),
+            Expression::StringLiteral(_loc, data) => {
+                Value::Bytes(hex::decode(data).unwrap_or(data.to_owned().into_bytes()))
+            }
+            Expression::InlineExpression { body, .. } => self.evaluate(body),
+            Expression::LogicalExpression { lhs, op, rhs, .. } => {
+                let lhs = self.evaluate(lhs).to_bool();
+                let rhs = self.evaluate(rhs).to_bool();
+                match (op, lhs, rhs) {
+                    (BinaryLogicalOp::BoolAnd, Some(false), _)
+                    | (BinaryLogicalOp::BoolAnd, _, Some(false)) => Value::Number(0),
+                    (BinaryLogicalOp::BoolOr, Some(true), _)
+                    | (BinaryLogicalOp::BoolOr, _, Some(true)) => Value::Number(1),
+                    (_, Some(lhs), Some(rhs)) => Value::Number((lhs && rhs) as i64),
+                    _ => Value::Unknown,
+                }
+            }
+            Expression::CompareExpression { lhs, op, rhs, .. } => {
+                let lhs_value = self.evaluate(lhs);
+                let rhs_value = self.evaluate(rhs);
+                let result = match (&lhs_value, &rhs_value) {
+                    (Value::Unknown, Value::Unknown) => None,
+                    (Value::Unknown, value) => self.decide(lhs, op, value),
+                    (value, Value::Unknown) => self.decide(rhs, &flip(op), value),
+                    _ => compare(&lhs_value, op, &rhs_value),
+                };
+                result.map_or(Value::Unknown, |b| Value::Number(b as i64))
+            }
+            Expression::WithinExpression {
+                operand, min, max, ..
+            } => match (
+                self.evaluate(min).to_number(),
+                self.evaluate(max).to_number(),
+            ) {
+                (Some(min), Some(max)) => {
+                    let (lo, hi) = self.range(operand);
+                    if lo >= min && hi <= max {
+                        Value::Number(1)
+                    } else if hi <= min || lo >= max || min >= max {
+                        Value::Number(0)
+                    } else {
+                        Value::Unknown
+                    }
+                }
+                _ => Value::Unknown,
+            },
+            Expression::UnaryMathExpression { operand, op, .. } => {
+                match self.evaluate(operand).to_number() {
+                    Some(n) => match op {
+                        UnaryMathOp::Add => number(n.checked_add(1)),
+                        UnaryMathOp::Sub => number(n.checked_sub(1)),
+                        UnaryMathOp::Negate => number(n.checked_neg()),
+                        UnaryMathOp::Abs => number(n.checked_abs()),
+                        UnaryMathOp::Not => Value::Number((n == 0) as i64),
+                    },
+                    None => Value::Unknown,
+                }
+            }
+            Expression::BinaryMathExpression { lhs, op, rhs, .. } => match (
+                self.evaluate(lhs).to_number(),
+                self.evaluate(rhs).to_number(),
+            ) {
+                (Some(lhs), Some(rhs)) => match op {
+                    BinaryMathOp::Add => number(lhs.checked_add(rhs)),
+                    BinaryMathOp::Sub => number(lhs.checked_sub(rhs)),
+                    BinaryMathOp::Max => Value::Number(lhs.max(rhs)),
+                    BinaryMathOp::Min => Value::Number(lhs.min(rhs)),
+                },
+                _ => Value::Unknown,
+            },
+            Expression::UnaryCryptoExpression { operand, op, .. } => {
+                match self.evaluate(operand).to_bytes() {
+                    Some(bytes) => Value::Bytes(digest(op, &bytes)),
+                    None => Value::Unknown,
+                }
+            }
+            Expression::ByteExpression { operand, .. } => match self.evaluate(operand).to_bytes() {
+                Some(bytes) => Value::Number(bytes.len() as i64),
+                None => Value::Unknown,
+            },
+            _ => Value::Unknown,
+        };
+        if value != Value::Unknown {
+            return value;
+        }
+
+        // Otherwise, what is known on the path.
+        let term = normalize(expression);
+        for (key, fact) in &self.facts {
+            if *key == term {
+                if let Fact::Equal(bytes) = fact {
+                    return Value::Bytes(bytes.to_owned());
+                }
+            }
+        }
+        match self.range(expression) {
+            (lo, hi) if hi.checked_sub(lo) == Some(1) => Value::Number(lo),
+            _ => Value::Unknown,
+        }
+    }
+
+    // Compare unknown term with known value, by the facts of the term.
+    fn decide(&self, term: &Expression, op: &BinaryCompareOp, value: &Value) -> Option<bool> {
+        let key = normalize(term);
+        let bytes = value.to_bytes()?;
+        let (lo, hi) = self.range(term);
+        let n = value.to_number();
+        let in_range = n.map(|n| lo <= n && n < hi);
+        let is_excluded = self.facts.iter().any(|(k, fact)| {
+            *k == key && matches!(fact, Fact::NotEqual(excluded) if *excluded == bytes)
+        });
+        match op {
+            BinaryCompareOp::Equal | BinaryCompareOp::NumEqual => {
+                if is_excluded || in_range == Some(false) {
+                    Some(false)
+                } else {
+                    None
+                }
+            }
+            BinaryCompareOp::NotEqual | BinaryCompareOp::NumNotEqual => {
+                if is_excluded || in_range == Some(false) {
+                    Some(true)
+                } else {
+                    None
+                }
+            }
+            BinaryCompareOp::Greater => decide_range(lo > n?, hi - 1 <= n?),
+            BinaryCompareOp::GreaterOrEqual => decide_range(lo >= n?, hi <= n?),
+            BinaryCompareOp::Less => decide_range(hi <= n?, lo >= n?),
+            BinaryCompareOp::LessOrEqual => decide_range(hi - 1 <= n?, lo > n?),
+        }
+    }
+
+    // Range of number the term can be on the path.
+    fn range(&self, term: &Expression) -> (i64, i64) {
+        let key = normalize(term);
+        let mut range = (i64::MIN, i64::MAX);
+        for (k, fact) in &self.facts {
+            if *k != key {
+                continue;
+            }
+            let (lo, hi) = match fact {
+                Fact::Range(lo, hi) => (*lo, *hi),
+                Fact::Equal(bytes) => match Value::Bytes(bytes.to_owned()).to_number() {
+                    Some(n) => (n, n.saturating_add(1)),
+                    None => continue,
+                },
+                Fact::NotEqual(_) => continue,
+            };
+            range = (range.0.max(lo), range.1.min(hi));
+        }
+        range
+    }
+
+    fn learn(&mut self, condition: &Expression, truth: bool) {
+        match condition {
+            Expression::InlineExpression { body, .. } => self.learn(body, truth),
+            Expression::LogicalExpression { lhs, op, rhs, .. } => {
+                let is_both = match op {
+                    BinaryLogicalOp::BoolAnd => truth,
+                    BinaryLogicalOp::BoolOr => !truth,
+                };
+                if is_both {
+                    self.learn(lhs, truth);
+                    self.learn(rhs, truth);
+                }
+            }
+            Expression::UnaryMathExpression {
+                operand,
+                op: UnaryMathOp::Not,
+                ..
+            } => self.learn(operand, !truth),
+            Expression::CompareExpression { lhs, op, rhs, .. } => {
+                let (term, op, value) = match (self.evaluate(lhs), self.evaluate(rhs)) {
+                    (Value::Unknown, Value::Unknown) => return,
+                    (Value::Unknown, value) => (lhs, op.to_owned(), value),
+                    (value, Value::Unknown) => (rhs, flip(op), value),
+                    _ => return,
+                };
+                let op = if truth { op } else { negate(&op) };
+                let (Some(bytes), n) = (value.to_bytes(), value.to_number()) else {
+                    return;
+                };
+                let fact = match (op, n) {
+                    (BinaryCompareOp::Equal, _) => Fact::Equal(bytes),
+                    (BinaryCompareOp::NotEqual, _) => Fact::NotEqual(bytes),
+                    (BinaryCompareOp::NumEqual, Some(n)) => Fact::Range(n, n.saturating_add(1)),
+                    (BinaryCompareOp::NumNotEqual, Some(_)) => Fact::NotEqual(bytes),
+                    (BinaryCompareOp::Greater, Some(n)) => {
+                        Fact::Range(n.saturating_add(1), i64::MAX)
+                    }
+                    (BinaryCompareOp::GreaterOrEqual, Some(n)) => Fact::Range(n, i64::MAX),
+                    (BinaryCompareOp::Less, Some(n)) => Fact::Range(i64::MIN, n),
+                    (BinaryCompareOp::LessOrEqual, Some(n)) => {
+                        Fact::Range(i64::MIN, n.saturating_add(1))
+                    }
+                    _ => return,
+                };
+                self.facts.push((normalize(term), fact));
+            }
+            Expression::WithinExpression {
+                operand, min, max, ..
+            } if truth => {
+                if let (Some(min), Some(max)) = (
+                    self.evaluate(min).to_number(),
+                    self.evaluate(max).to_number(),
+                ) {
+                    self.facts.push((normalize(operand), Fact::Range(min, max)));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // Facts of each term can hold together.
+    fn is_consistent(&self) -> bool {
+        self.facts.iter().all(|(term, fact)| {
+            let (lo, hi) = self.range(term);
+            lo < hi
+                && match fact {
+                    Fact::Equal(bytes) => self.facts.iter().all(|(k, other)| {
+                        k != term
+                            || match other {
+                                Fact::Equal(other) => other == bytes,
+                                Fact::NotEqual(other) => other != bytes,
+                                Fact::Range(..) => true,
+                            }
+                    }),
+                    _ => true,
+                }
+        })
+    }
+}
+
+fn number(n: Option<i64>) -> Value {
+    n.map_or(Value::Unknown, Value::Number)
+}
+
+fn decide_range(always: bool, never: bool) -> Option<bool> {
+    if always {
+        Some(true)
+    } else if never {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn compare(lhs: &Value, op: &BinaryCompareOp, rhs: &Value) -> Option<bool> {
+    match op {
+        BinaryCompareOp::Equal => Some(lhs.to_bytes()? == rhs.to_bytes()?),
+        BinaryCompareOp::NotEqual => Some(lhs.to_bytes()? != rhs.to_bytes()?),
+        BinaryCompareOp::NumEqual => Some(lhs.to_number()? == rhs.to_number()?),
+        BinaryCompareOp::NumNotEqual => Some(lhs.to_number()? != rhs.to_number()?),
+        BinaryCompareOp::Greater => Some(lhs.to_number()? > rhs.to_number()?),
+        BinaryCompareOp::GreaterOrEqual => Some(lhs.to_number()? >= rhs.to_number()?),
+        BinaryCompareOp::Less => Some(lhs.to_number()? < rhs.to_number()?),
+        BinaryCompareOp::LessOrEqual => Some(lhs.to_number()? <= rhs.to_number()?),
+    }
+}
+
+// `a op b` is `b flip(op) a`.
+pub fn flip(op: &BinaryCompareOp) -> BinaryCompareOp {
+    match op {
+        BinaryCompareOp::Greater => BinaryCompareOp::Less,
+        BinaryCompareOp::GreaterOrEqual => BinaryCompareOp::LessOrEqual,
+        BinaryCompareOp::Less => BinaryCompareOp::Greater,
+        BinaryCompareOp::LessOrEqual => BinaryCompareOp::GreaterOrEqual,
+        _ => op.to_owned(),
+    }
+}
+
+// `!(a op b)` is `a negate(op) b`.
+fn negate(op: &BinaryCompareOp) -> BinaryCompareOp {
+    match op {
+        BinaryCompareOp::Equal => BinaryCompareOp::NotEqual,
+        BinaryCompareOp::NotEqual => BinaryCompareOp::Equal,
+        BinaryCompareOp::NumEqual => BinaryCompareOp::NumNotEqual,
+        BinaryCompareOp::NumNotEqual => BinaryCompareOp::NumEqual,
+        BinaryCompareOp::Greater => BinaryCompareOp::LessOrEqual,
+        BinaryCompareOp::GreaterOrEqual => BinaryCompareOp::Less,
+        BinaryCompareOp::Less => BinaryCompareOp::GreaterOrEqual,
+        BinaryCompareOp::LessOrEqual => BinaryCompareOp::Greater,
+    }
+}
+
+pub fn digest(op: &UnaryCryptoOp, bytes: &[u8]) -> Vec<u8> {
+    match op {
+        UnaryCryptoOp::Sha256 => sha256::Hash::hash(bytes).to_byte_array().to_vec(),
+        UnaryCryptoOp::Ripemd160 => ripemd160::Hash::hash(bytes).to_byte_array().to_vec(),
+        UnaryCryptoOp::Hash160 => hash160::Hash::hash(bytes).to_byte_array().to_vec(),
+        UnaryCryptoOp::Hash256 => sha256d::Hash::hash(bytes).to_byte_array().to_vec(),
+        UnaryCryptoOp::Sha1 => sha1::Hash::hash(bytes).to_byte_array().to_vec(),
+    }
+}
+
+// Same value is written at different location, or through inline function.
+pub fn normalize(expression: &Expression) -> Expression {
+    if let Expression::InlineExpression { body, .. } = expression {
+        return normalize(body);
+    }
+    let mut term = expression.to_owned();
+    *term.loc_mut() = Location::default();
+    for child in term.sub_expressions_mut() {
+        *child = normalize(child);
+    }
+    term
+}
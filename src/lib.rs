@@ -1,12 +1,31 @@
 mod analyze;
 mod analyze_test;
 mod ast;
+mod cfg;
 mod compile;
+mod explain;
+mod fold;
+mod import;
+mod inline;
+mod interpret;
+mod lint;
+mod malleability;
+mod miniscript;
+mod optimize;
 mod parser_test;
+mod path;
+mod peephole;
+mod peephole_test;
+mod policy;
 mod source;
+mod stack;
 
-// Re-export only type for error.
-pub use ast::{CompileError, ErrorKind, Location};
+// Re-export only type for error, warning and report.
+pub use ast::{
+    BooleanEncoding, BooleanInput, CompileError, CompileWarning, ErrorKind, Location,
+    MiniscriptExport, Optimization, OptimizeLevel, PathPolicy, PathReport, PreimagePolicy,
+    SignaturePolicy, TimelockPolicy, WarningKind,
+};
 
 use ast::*;
 use compile::*;
@@ -17,11 +36,26 @@ use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
 use crate::analyze::*;
+use crate::cfg::*;
+use crate::explain::explain;
+use crate::fold::fold_constants;
+use crate::import::*;
+use crate::inline::*;
+use crate::interpret::dead_path_warnings;
+use crate::lint::*;
+use crate::malleability::*;
+use crate::miniscript::export_miniscript;
+use crate::optimize::*;
+use crate::path::enumerate_paths;
+use crate::peephole::{encode, minimize, optimize_script};
+use crate::policy::policy_source;
 use crate::source::*;
+use crate::stack::*;
 
 use lalrpop_util::ParseError;
 
 use std::fmt::Debug;
+use std::path::Path;
 use std::result::Result;
 
 lalrpop_mod!(bithoven); // synthesized by LALRPOP
@@ -33,13 +67,35 @@ pub struct BithovenOutput {
     asm: String,
     hex: String,
     bytes: Vec<u8>,
+    #[serde(default)]
+    warnings: Vec<CompileWarning>,
+    #[serde(default)]
+    sequences: Vec<u32>,
+    #[serde(default)]
+    paths: Vec<PathReport>,
+    #[serde(default)]
+    miniscript: MiniscriptExport,
+    #[serde(default)]
+    optimizations: Vec<Optimization>,
+    #[serde(skip)]
+    cfg: Cfg,
 }
 
 #[wasm_bindgen]
 impl BithovenOutput {
     #[wasm_bindgen(constructor)]
     pub fn new(asm: String, hex: String, bytes: Vec<u8>) -> Self {
-        BithovenOutput { asm, hex, bytes }
+        BithovenOutput {
+            asm,
+            hex,
+            bytes,
+            warnings: vec![],
+            sequences: vec![],
+            paths: vec![],
+            miniscript: MiniscriptExport::default(),
+            optimizations: vec![],
+            cfg: Cfg::default(),
+        }
     }
     #[wasm_bindgen]
     pub fn to_object(&self) -> JsValue {
@@ -65,48 +121,113 @@ impl BithovenOutput {
     }
 }
 
-fn parse(source: String) -> Result<Bithoven, CompileError> {
-    let line_index = build_line_index(&source);
+impl BithovenOutput {
+    /// Warnings found while compiling, which don't stop the compilation.
+    pub fn warnings(&self) -> Vec<CompileWarning> {
+        self.warnings.clone()
+    }
+    /// nSequence the spending input needs, for each relative locktime in the script.
+    pub fn sequences(&self) -> Vec<u32> {
+        self.sequences.clone()
+    }
+    /// Spending paths, with the input stack bound to each.
+    pub fn paths(&self) -> Vec<PathReport> {
+        self.paths.clone()
+    }
+    /// Who can spend by each path, when and with what, in plain language.
+    pub fn explain(&self) -> String {
+        explain(&self.paths)
+    }
+    /// Miniscript and descriptor with the same spending policy, or constructs blocking them.
+    /// Their script and address differ from the compiled script.
+    pub fn miniscript(&self) -> MiniscriptExport {
+        self.miniscript.clone()
+    }
+    /// Rewrites made by optimization, for verbose log.
+    pub fn optimizations(&self) -> Vec<Optimization> {
+        self.optimizations.clone()
+    }
+    /// Control-flow graph in Graphviz DOT, with each leaf labelled by its input stack.
+    pub fn cfg_dot(&self) -> String {
+        self.cfg.to_dot()
+    }
+    /// Control-flow graph in Mermaid flowchart, with each leaf labelled by its input stack.
+    pub fn cfg_mermaid(&self) -> String {
+        self.cfg.to_mermaid()
+    }
+}
+
+fn parse(source: String, file: &str) -> Result<Bithoven, CompileError> {
+    let source_file = SourceFile::new(file, &source);
     match bithoven::BithovenParser::new().parse(&source) {
         Ok(mut utxo) => {
-            set_stack_location(&mut utxo.input_stack, &line_index);
-            set_ast_location(&mut utxo.output_script, &line_index);
+            set_import_location(&mut utxo.imports, &source_file);
+            set_constant_location(&mut utxo.constants, &source_file);
+            set_stack_location(&mut utxo.input_stack, &source_file);
+            set_function_location(&mut utxo.functions, &source_file);
+            set_ast_location(&mut utxo.output_script, &source_file);
             Ok(utxo)
         }
-        Err(e) => {
-            // FAILURE PATH: Use the index to report the parse error location.
-            let location = match &e {
-                ParseError::InvalidToken { location } => *location,
-                ParseError::UnrecognizedEof { location, .. } => *location,
-                ParseError::UnrecognizedToken { token, .. } => token.0,
-                ParseError::ExtraToken { token, .. } => token.0,
-                // Handle other error variants...
-                _ => 0,
-            };
-
-            let (line, column) = get_line_and_column(&line_index, location);
-            let invalid_statement = source[if line != line_index.len() {
-                line_index[line - 1]..line_index[line]
-            } else {
-                line_index[line - 1]..source.len()
-            }]
-            .trim();
-
-            Err(CompileError {
-                loc: Location {
-                    start: 0,
-                    end: 0,
-                    line,
-                    column,
-                },
-                kind: ErrorKind::ParseError(format!(
-                    "Invalid Statement: \"{}\"",
-                    invalid_statement
-                )),
-            })
+        Err(e) => Err(parse_error(&e, &source, &source_file)),
+    }
+}
+
+fn parse_library(source: &str, file: &str) -> Result<Library, CompileError> {
+    let source_file = SourceFile::new(file, source);
+    match bithoven::LibraryParser::new().parse(source) {
+        Ok(mut library) => {
+            set_import_location(&mut library.imports, &source_file);
+            set_constant_location(&mut library.constants, &source_file);
+            set_function_location(&mut library.functions, &source_file);
+            Ok(library)
         }
+        Err(e) => Err(parse_error(&e, source, &source_file)),
     }
 }
+
+fn parse_error<T>(
+    e: &ParseError<usize, T, CompileError>,
+    source: &str,
+    source_file: &SourceFile,
+) -> CompileError {
+    // Error raised by grammar action is already located.
+    if let ParseError::User { error } = e {
+        let mut error = error.to_owned();
+        source_file.locate(&mut error.loc);
+        return error;
+    }
+
+    // FAILURE PATH: Use the index to report the parse error location.
+    let location = match e {
+        ParseError::InvalidToken { location } => *location,
+        ParseError::UnrecognizedEof { location, .. } => *location,
+        ParseError::UnrecognizedToken { token, .. } => token.0,
+        ParseError::ExtraToken { token, .. } => token.0,
+        // Handle other error variants...
+        _ => 0,
+    };
+
+    let line_index = &source_file.line_index;
+    let (line, column) = get_line_and_column(line_index, location);
+    let invalid_statement = source[if line != line_index.len() {
+        line_index[line - 1]..line_index[line]
+    } else {
+        line_index[line - 1]..source.len()
+    }]
+    .trim();
+
+    CompileError {
+        loc: Location {
+            start: 0,
+            end: 0,
+            line,
+            column,
+            file: source_file.name.to_owned(),
+        },
+        kind: ErrorKind::ParseError(format!("Invalid Statement: \"{}\"", invalid_statement)),
+    }
+}
+
 /// Compiles Bithoven source code into Bitcoin Script.
 ///
 /// # Arguments
@@ -115,25 +236,215 @@ fn parse(source: String) -> Result<Bithoven, CompileError> {
 ///
 /// # Returns
 ///
-/// A `BithovenOutput` containing ASM, Hex, and Bytes.
+/// A `BithovenOutput` containing ASM, Hex, Bytes, Warnings, nSequences, and Paths.
 #[wasm_bindgen]
 pub fn compile_program(source: String) -> Result<BithovenOutput, CompileError> {
     // Parse
-    let utxo: Bithoven = parse(source)?;
+    let utxo: Bithoven = parse(source, "")?;
+
+    // Source without file has nowhere to resolve import from.
+    if let Some(import) = utxo.imports.first() {
+        return Err(CompileError {
+            loc: import.loc.to_owned(),
+            kind: ErrorKind::ImportError(format!(
+                "Import requires compiling from a file: {:?}.",
+                import.path
+            )),
+        });
+    }
+
+    compile_utxo(utxo)
+}
+
+/// Generates Bithoven source from Bitcoin policy language, e.g. `or(9@pk(A), and(pk(B), older(1000)))`.
+///
+/// # Arguments
+///
+/// * `policy` - A string containing the policy
+/// * `target` - Target of the contract, one of "legacy", "segwit" and "taproot"
+///
+/// # Returns
+///
+/// Source with an input stack and if/else branch for each spending path, more probable first.
+#[wasm_bindgen]
+pub fn from_policy(policy: String, target: String) -> Result<String, CompileError> {
+    let target = match target.as_str() {
+        "legacy" => Target::Legacy,
+        "segwit" => Target::Segwit,
+        "taproot" => Target::Taproot,
+        _ => {
+            return Err(CompileError {
+                loc: Location::default(),
+                kind: ErrorKind::ParseError(format!("Unknown target: {:?}.", target)),
+            })
+        }
+    };
+    policy_source(&policy, &target)
+}
+
+/// Compiles Bithoven source file into Bitcoin Script.
+///
+/// # Arguments
+///
+/// * `source` - A string containing the source code of root file
+/// * `path` - Path of root file, which imports are resolved relative to
+///
+/// # Returns
+///
+/// A `BithovenOutput` containing ASM, Hex, Bytes, Warnings, nSequences, and Paths.
+pub fn compile_file(source: String, path: &Path) -> Result<BithovenOutput, CompileError> {
+    compile_file_with(source, path, None)
+}
+
+/// Same as [`compile_file`], at the optimization level given in place of `pragma bithoven optimize`.
+/// With none given, the level of the pragma is kept.
+pub fn compile_file_with(
+    source: String,
+    path: &Path,
+    optimize: Option<OptimizeLevel>,
+) -> Result<BithovenOutput, CompileError> {
+    // Parse
+    let mut utxo: Bithoven = parse(source, &path.display().to_string())?;
+    if let Some(level) = optimize {
+        utxo.pragma.optimize = level;
+    }
+
+    // Import
+    resolve_imports(&mut utxo, path)?;
+
+    compile_utxo(utxo)
+}
+
+// Script by each encoding of the optimization level, checked for the final stack of each path as generated.
+// Optimization keeps what the script does, but may reorder the blocks of a branch.
+// Of the encodings passing the check, the one of least witness-weighted cost is taken.
+fn generate_script(
+    ast: Vec<Statement>,
+    utxo: &Bithoven,
+    paths: &[crate::path::Path],
+    optimizations: &mut Vec<Optimization>,
+) -> Result<Vec<u8>, CompileError> {
+    let target = &utxo.pragma.target;
+    let level = utxo.pragma.optimize;
+    let candidates = Encoding::candidates(level, target);
+    let mut costs = vec![];
+    let mut error = None;
+    for encoding in &candidates {
+        let instructions = codegen(ast.clone(), target, &utxo.input_stack, *encoding);
+        if let Err(err) = check_clean_stack(
+            &encode(&instructions),
+            paths,
+            &utxo.input_stack,
+            &utxo.path_names,
+            target,
+            encoding,
+        ) {
+            error.get_or_insert(err);
+            costs.push(None);
+            continue;
+        }
+        let weight =
+            |script: &[u8]| witness_weight(script, paths, &utxo.input_stack, target, encoding);
+        let script = encode(&match level {
+            OptimizeLevel::O0 => instructions,
+            OptimizeLevel::O1 => optimize_script(instructions),
+            OptimizeLevel::Os => minimize(instructions, weight),
+        });
+        costs.push(Some((weight(&script), script)));
+    }
+
+    let Some(chosen) = (0..candidates.len())
+        .filter(|i| costs[*i].is_some())
+        .min_by_key(|i| costs[*i].as_ref().map(|(cost, _script)| *cost))
+    else {
+        return Err(error.expect("Every encoding is checked."));
+    };
+    if chosen > 0 {
+        let reason = match &costs[0] {
+            Some((cost, _script)) => format!(
+                "for {} weight units instead of {}",
+                costs[chosen].as_ref().unwrap().0,
+                cost
+            ),
+            None => "as OP_CHECKMULTISIG doesn't fit the input stack".to_string(),
+        };
+        let mut locations: Vec<Location> = paths
+            .iter()
+            .flat_map(|path| checkmultisigs(path, target, &candidates[0]))
+            .map(|factor| factor.to_owned().loc())
+            .collect();
+        locations.sort_by_key(|loc| loc.start);
+        locations.dedup();
+        for loc in locations {
+            optimizations.push(Optimization {
+                loc,
+                message: format!("Encoded multisig by OP_CHECKSIG of each key, {}.", reason),
+            });
+        }
+    }
+    Ok(costs.swap_remove(chosen).unwrap().1)
+}
+
+fn compile_utxo(mut utxo: Bithoven) -> Result<BithovenOutput, CompileError> {
+    // Inline
+    inline_functions(&mut utxo)?;
 
     // Analyze
-    analyze(
+    let mut paths = analyze_paths(
         &utxo.output_script,
         utxo.input_stack.clone(),
+        &utxo.path_names,
         &utxo.pragma.target,
     )?;
 
+    // Optimize, on the script as analyzed.
+    if utxo.pragma.optimize != OptimizeLevel::O0 {
+        optimize(&mut utxo.output_script);
+    }
+
+    // Lint
+    let mut warnings = lint(&utxo.output_script);
+
+    // Malleability of each path, warned with the rewrite suggested.
+    let enumerated = enumerate_paths(&utxo.output_script);
+    let malleations = check_malleability(&enumerated, &utxo.input_stack, &utxo.pragma.target);
+    for (report, found) in paths.iter_mut().zip(&malleations) {
+        report.malleability = found.iter().map(|m| m.reason.to_owned()).collect();
+    }
+    warnings.extend(malleability_warnings(&malleations));
+
+    // Dead path, whose stack can never spend the output.
+    warnings.extend(dead_path_warnings(&enumerated, &utxo.path_names));
+
+    // Fold constants, on the script as analyzed.
+    let mut folded = utxo.output_script.clone();
+    let mut optimizations = match utxo.pragma.optimize {
+        OptimizeLevel::O0 => vec![],
+        _ => fold_constants(&mut folded, &utxo.input_stack, &utxo.pragma.target),
+    };
+
     // Compile
-    let script = compile(utxo.output_script.clone(), &utxo.pragma.target);
+    let mut sequences = vec![];
+    collect_sequences(&utxo.output_script, &mut sequences);
+    let script = generate_script(folded, &utxo, &enumerated, &mut optimizations)?;
+
+    // Miniscript for wallets, if every construct has a counterpart.
+    let miniscript = export_miniscript(&utxo.output_script, &utxo.pragma.target);
+
+    // Graph of the paths, for design review.
+    let cfg = build_cfg(&enumerated, &paths, &utxo.input_stack, &utxo.pragma.target);
 
-    Ok(BithovenOutput::new(
-        bitcoin::Script::from_bytes(&script).to_asm_string(),
-        bitcoin::Script::from_bytes(&script).to_hex_string(),
-        bitcoin::Script::from_bytes(&script).to_bytes(),
-    ))
+    Ok(BithovenOutput {
+        warnings,
+        sequences,
+        paths,
+        miniscript,
+        optimizations,
+        cfg,
+        ..BithovenOutput::new(
+            bitcoin::Script::from_bytes(&script).to_asm_string(),
+            bitcoin::Script::from_bytes(&script).to_hex_string(),
+            bitcoin::Script::from_bytes(&script).to_bytes(),
+        )
+    })
 }
@@ -0,0 +1,114 @@
+use crate::ast::*;
+
+// Collect warnings over the inlined script.
+// Unlike analysis, lint never rejects the program.
+pub fn lint(ast: &[Statement]) -> Vec<CompileWarning> {
+    let mut warnings: Vec<CompileWarning> = vec![];
+    for stmt in ast {
+        lint_statement(stmt, &mut warnings);
+    }
+    warnings
+}
+
+pub fn lint_statement(stmt: &Statement, warnings: &mut Vec<CompileWarning>) {
+    match stmt {
+        Statement::IfStatement {
+            loc: _,
+            condition_expr,
+            if_block,
+            else_block,
+        } => {
+            lint_expression(condition_expr, warnings);
+            for if_stmt in if_block {
+                lint_statement(if_stmt, warnings);
+            }
+            if let Some(else_b) = else_block {
+                for else_stmt in else_b {
+                    lint_statement(else_stmt, warnings);
+                }
+            }
+        }
+        Statement::MatchStatement { arms, default, .. } => {
+            for arm in arms {
+                for arm_stmt in &arm.block {
+                    lint_statement(arm_stmt, warnings);
+                }
+            }
+            if let Some(default_b) = default {
+                for default_stmt in default_b {
+                    lint_statement(default_stmt, warnings);
+                }
+            }
+        }
+        Statement::VerifyStatement(_loc, expr) => lint_expression(expr, warnings),
+        Statement::ExpressionStatement(_loc, expr) => lint_expression(expr, warnings),
+        Statement::LocktimeStatement {
+            loc,
+            operand,
+            op: LocktimeOp::Csv,
+            unit: LocktimeUnit::Second,
+        } => lint_locktime_rounding(loc, *operand, warnings),
+        Statement::LocktimeStatement {
+            loc,
+            operand,
+            op: LocktimeOp::Cltv,
+            unit: LocktimeUnit::Raw,
+        } => lint_locktime_threshold(loc, *operand, warnings),
+        Statement::LocktimeStatement { .. } => (),
+    }
+}
+
+// Raw value near the threshold is likely mistaken for the other of height or time.
+pub fn lint_locktime_threshold(loc: &Location, operand: i64, warnings: &mut Vec<CompileWarning>) {
+    let threshold = bitcoin::locktime::absolute::LOCK_TIME_THRESHOLD as i64;
+    if (operand - threshold).abs() < LOCKTIME_THRESHOLD_MARGIN {
+        warnings.push(CompileWarning {
+            loc: loc.to_owned(),
+            kind: WarningKind::AmbiguousLocktime(format!(
+                "Absolute locktime {} is {} as it is {} {}, use `after height N;` or a date instead.",
+                operand,
+                if operand >= threshold { "time" } else { "height" },
+                if operand >= threshold { "from" } else { "below" },
+                threshold
+            )),
+        });
+    }
+}
+
+// About 1.6 years as timestamp, or 950 years as height.
+pub const LOCKTIME_THRESHOLD_MARGIN: i64 = 50_000_000;
+
+// BIP 68 counts time in 512 seconds, so the locktime is rounded up.
+pub fn lint_locktime_rounding(loc: &Location, seconds: i64, warnings: &mut Vec<CompileWarning>) {
+    if seconds % 512 != 0 {
+        warnings.push(CompileWarning {
+            loc: loc.to_owned(),
+            kind: WarningKind::LocktimeRounding(format!(
+                "Relative locktime of {} seconds is rounded up to {} seconds, multiple of 512.",
+                seconds,
+                (seconds + 511) / 512 * 512
+            )),
+        });
+    }
+}
+
+pub fn lint_expression(expression: &Expression, warnings: &mut Vec<CompileWarning>) {
+    // Collision of sha1 is practical, so it only fits to prove a collision(e.g. bounty).
+    if let Expression::UnaryCryptoExpression {
+        loc,
+        op: UnaryCryptoOp::Sha1,
+        ..
+    } = expression
+    {
+        warnings.push(CompileWarning {
+            loc: loc.to_owned(),
+            kind: WarningKind::WeakHash(
+                "sha1 is not collision resistant, use it only for collision bounty.".to_string(),
+            ),
+        });
+    }
+
+    for child in expression.sub_expressions() {
+        lint_expression(child, warnings);
+    }
+}
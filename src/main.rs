@@ -1,5 +1,5 @@
-use bithoven::compile_program;
-use clap::{Parser, Subcommand};
+use bithoven::{compile_file_with, from_policy, BithovenOutput, OptimizeLevel};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::fs;
 use std::io::Write;
 use std::path::PathBuf;
@@ -16,7 +16,7 @@ struct Cli {
 enum Commands {
     /// Compiles a .bithoven file to Bitcoin Script
     Compile {
-        /// The source file to compile
+        /// The root source file to compile, along with the files it imports
         file: PathBuf,
 
         /// Output format.
@@ -26,72 +26,196 @@ enum Commands {
         /// - "hex": Prints Hex to stdout
         #[arg(short, long, default_value = "file")]
         format: String,
+
+        /// Emits another artifact to stdout in place of the output.
+        /// - "cfg=dot": Control-flow graph in Graphviz DOT
+        /// - "cfg=mermaid": Control-flow graph in Mermaid
+        /// - "miniscript": Miniscript with the same spending policy
+        /// - "descriptor": Output descriptor of the miniscript
+        #[arg(long)]
+        emit: Option<String>,
+
+        /// Prints each rewrite made by optimization to stderr
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Optimization level, in place of `pragma bithoven optimize`
+        #[arg(short = 'O', value_name = "LEVEL", value_enum)]
+        optimize: Option<Level>,
+    },
+    /// Explains who can spend by each path of a .bithoven file, when and with what
+    Explain {
+        /// The root source file to explain, along with the files it imports
+        file: PathBuf,
+
+        /// Output format.
+        /// - "text" (default): Prints plain-language summary to stdout
+        /// - "json": Prints spending paths with their policy as JSON to stdout
+        #[arg(short, long, default_value = "text")]
+        format: String,
+    },
+    /// Generates a .bithoven contract from Bitcoin policy language, printed to stdout
+    FromPolicy {
+        /// The policy, e.g. "or(9@pk(A), and(pk(B), older(1000)))"
+        policy: String,
+
+        /// Target of the contract: "legacy", "segwit" (default) or "taproot"
+        #[arg(short, long, default_value = "segwit")]
+        target: String,
     },
 }
 
+// Level of `-O0|-O1|-Os`, as `pragma bithoven optimize` takes.
+#[derive(Clone, Copy, ValueEnum)]
+enum Level {
+    #[value(name = "0")]
+    O0,
+    #[value(name = "1")]
+    O1,
+    #[value(name = "s")]
+    Os,
+}
+
+impl From<Level> for OptimizeLevel {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::O0 => OptimizeLevel::O0,
+            Level::O1 => OptimizeLevel::O1,
+            Level::Os => OptimizeLevel::Os,
+        }
+    }
+}
+
+// Read and compile the file, or exit with the error.
+fn compile_or_exit(file: &PathBuf, optimize: Option<OptimizeLevel>) -> BithovenOutput {
+    let source = match fs::read_to_string(file) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error reading file {:?}: {}", file, e);
+            std::process::exit(1);
+        }
+    };
+    match compile_file_with(source, file, optimize) {
+        Ok(output) => {
+            // Warnings don't stop compilation, so print them to stderr
+            for warning in output.warnings() {
+                eprintln!("{}", warning);
+            }
+            output
+        }
+        Err(e) => {
+            // Print compile error to stderr
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Compile { file, format } => {
-            // 1. Read the source file
-            let source = match fs::read_to_string(&file) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("Error reading file {:?}: {}", file, e);
-                    std::process::exit(1);
+        Commands::Compile {
+            file,
+            format,
+            emit,
+            verbose,
+            optimize,
+        } => {
+            // 1. Compile using the library, resolving imports relative to the file
+            let output = compile_or_exit(&file, optimize.map(OptimizeLevel::from));
+            if verbose {
+                for optimization in output.optimizations() {
+                    eprintln!("{}", optimization);
                 }
-            };
-
-            // 2. Compile using the library
-            match compile_program(source) {
-                Ok(output) => {
-                    // 3. Serialize output to JSON (used for both 'json' and 'file' modes)
-                    let json_output = serde_json::to_string_pretty(&output)
-                        .expect("Failed to serialize output to JSON");
-
-                    // 4. Handle output based on format flag
-                    match format.as_str() {
-                        "hex" => println!("{}", output.hex()),
-                        "asm" => println!("{}", output.asm()),
-                        "json" => println!("{}", json_output),
-                        _ => {
-                            // Default: Write to file "filename.bithoven.json"
-
-                            // Construct output filename
-                            // If input is "contract.bithoven", output becomes "contract.bithoven.json"
-                            let mut output_path = file.clone();
-                            if let Some(file_name) = output_path.file_name() {
-                                let new_name = format!("{}.json", file_name.to_string_lossy());
-                                output_path.set_file_name(new_name);
-                            }
+            }
 
-                            // Write file
-                            match fs::File::create(&output_path) {
-                                Ok(mut f) => {
-                                    if let Err(e) = f.write_all(json_output.as_bytes()) {
-                                        eprintln!("Error writing to file {:?}: {}", output_path, e);
-                                        std::process::exit(1);
-                                    }
-                                    println!(
-                                        "Success! Compiled artifact saved to: {:?}",
-                                        output_path
-                                    );
-                                }
-                                Err(e) => {
-                                    eprintln!("Error creating file {:?}: {}", output_path, e);
-                                    std::process::exit(1);
+            if let Some(emit) = emit {
+                match emit.as_str() {
+                    "cfg=dot" => print!("{}", output.cfg_dot()),
+                    "cfg=mermaid" => print!("{}", output.cfg_mermaid()),
+                    "miniscript" | "descriptor" => {
+                        let export = output.miniscript();
+                        let artifact = match emit.as_str() {
+                            "miniscript" => export.miniscript,
+                            _ => export.descriptor,
+                        };
+                        match artifact {
+                            Some(artifact) => {
+                                println!("{}", artifact);
+                                eprintln!("Note: it matches the spending policy only, as its script and address differ from the compiled script, so fund the descriptor's address to spend with Miniscript wallets.");
+                            }
+                            None => {
+                                eprintln!("No Miniscript counterpart:");
+                                for blocker in export.blockers {
+                                    eprintln!("  {}", blocker);
                                 }
+                                std::process::exit(1);
                             }
                         }
                     }
+                    _ => {
+                        eprintln!("Unknown artifact to emit: {:?}", emit);
+                        std::process::exit(1);
+                    }
                 }
-                Err(e) => {
-                    // Print compile error to stderr
-                    eprintln!("{}", e);
-                    std::process::exit(1);
+                return;
+            }
+
+            // 2. Serialize output to JSON (used for both 'json' and 'file' modes)
+            let json_output =
+                serde_json::to_string_pretty(&output).expect("Failed to serialize output to JSON");
+
+            // 3. Handle output based on format flag
+            match format.as_str() {
+                "hex" => println!("{}", output.hex()),
+                "asm" => println!("{}", output.asm()),
+                "json" => println!("{}", json_output),
+                _ => {
+                    // Default: Write to file "filename.bithoven.json"
+
+                    // Construct output filename
+                    // If input is "contract.bithoven", output becomes "contract.bithoven.json"
+                    let mut output_path = file.clone();
+                    if let Some(file_name) = output_path.file_name() {
+                        let new_name = format!("{}.json", file_name.to_string_lossy());
+                        output_path.set_file_name(new_name);
+                    }
+
+                    // Write file
+                    match fs::File::create(&output_path) {
+                        Ok(mut f) => {
+                            if let Err(e) = f.write_all(json_output.as_bytes()) {
+                                eprintln!("Error writing to file {:?}: {}", output_path, e);
+                                std::process::exit(1);
+                            }
+                            println!("Success! Compiled artifact saved to: {:?}", output_path);
+                        }
+                        Err(e) => {
+                            eprintln!("Error creating file {:?}: {}", output_path, e);
+                            std::process::exit(1);
+                        }
+                    }
                 }
             }
         }
+        Commands::Explain { file, format } => {
+            let output = compile_or_exit(&file, None);
+            match format.as_str() {
+                "json" => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&output.paths())
+                        .expect("Failed to serialize paths to JSON")
+                ),
+                _ => print!("{}", output.explain()),
+            }
+        }
+        Commands::FromPolicy { policy, target } => match from_policy(policy, target) {
+            Ok(source) => print!("{}", source),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        },
     }
 }
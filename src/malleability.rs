@@ -0,0 +1,174 @@
+use crate::ast::*;
+use crate::path::*;
+use crate::source::*;
+
+// Way a third party could change the witness of a path without invalidating it.
+// As in Miniscript, third party has no private key, but may know every preimage.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Malleation {
+    pub loc: Location,
+    pub reason: String,
+    pub rewrite: String,
+}
+
+// Classify each path as non-malleable(empty) or malleable with the reasons, in the order of steps.
+pub fn check_malleability(
+    paths: &[Path],
+    input: &[Vec<StackParam>],
+    target: &Target,
+) -> Vec<Vec<Malleation>> {
+    let signed: Vec<bool> = paths.iter().map(requires_signature).collect();
+    paths
+        .iter()
+        .zip(input)
+        .enumerate()
+        .map(|(branch, (path, stack))| {
+            // Selector can be switched to any other path satisfied without signature.
+            let unsigned = signed
+                .iter()
+                .enumerate()
+                .any(|(other, is_signed)| other != branch && !is_signed);
+            let mut found = vec![];
+            for step in &path.steps {
+                match step {
+                    Step::Branch { condition, taken } => {
+                        if unsigned {
+                            let ty = |id: &Identifier| stack_type(stack, id);
+                            if let Condition::Witness(id) = condition.condition(&ty) {
+                                found.push(unbound_selector(condition, &id));
+                            }
+                        }
+                        if !taken {
+                            found.extend(dissatisfied(condition, stack, target));
+                        }
+                    }
+                    Step::Arm {
+                        selector,
+                        taken,
+                        default,
+                        ..
+                    } => {
+                        if let (true, Expression::Variable(_loc, id)) = (unsigned, selector) {
+                            found.push(unbound_selector(selector, id));
+                        }
+                        if taken.is_none() && *default {
+                            found.push(default_arm(selector));
+                        }
+                    }
+                    Step::Statement(_) => (),
+                }
+            }
+            found
+        })
+        .collect()
+}
+
+// Each malleation is warned once, even if shared by several paths.
+pub fn malleability_warnings(malleations: &[Vec<Malleation>]) -> Vec<CompileWarning> {
+    let mut warnings: Vec<CompileWarning> = vec![];
+    for malleation in malleations.iter().flatten() {
+        let warning = CompileWarning {
+            loc: malleation.loc.to_owned(),
+            kind: WarningKind::Malleability(format!(
+                "{} {}",
+                malleation.reason, malleation.rewrite
+            )),
+        };
+        if !warnings.contains(&warning) {
+            warnings.push(warning);
+        }
+    }
+    warnings
+}
+
+// Path requires a signature if a checksig on it must succeed.
+pub fn requires_signature(path: &Path) -> bool {
+    path.steps.iter().any(|step| match step {
+        Step::Branch {
+            condition,
+            taken: true,
+        } => find_checksig(condition).is_some(),
+        Step::Statement(Statement::VerifyStatement(_loc, expr))
+        | Step::Statement(Statement::ExpressionStatement(_loc, expr)) => {
+            find_checksig(expr).is_some()
+        }
+        _ => false,
+    })
+}
+
+fn unbound_selector(selector: &Expression, id: &Identifier) -> Malleation {
+    Malleation {
+        loc: selector.to_owned().loc(),
+        reason: format!(
+            "Selector {:?} is not bound by signature, so third party can switch it to a path requiring none.",
+            id.0
+        ),
+        rewrite: "Require a signature on every path, e.g. `verify checksig(sig, pk);`.".to_string(),
+    }
+}
+
+// Default arm is selected by any value not matched by the other arms.
+fn default_arm(selector: &Expression) -> Malleation {
+    Malleation {
+        loc: selector.to_owned().loc(),
+        reason: "Default arm is taken by any selector value no other arm matches, so third party can switch it to another one.".to_string(),
+        rewrite: "Replace `_` by the arm of one exact value, which is checked by OP_NUMEQUALVERIFY as the last arm.".to_string(),
+    }
+}
+
+// Failed condition is satisfied by more than one witness.
+fn dissatisfied(condition: &Expression, stack: &[StackParam], target: &Target) -> Vec<Malleation> {
+    let mut found = vec![];
+    // NULLFAIL is consensus only in tapscript, so failed checksig takes any invalid signature.
+    if let (false, Some(loc)) = (*target == Target::Taproot, find_checksig(condition)) {
+        found.push(Malleation {
+            loc,
+            reason: "Failed checksig is satisfied by any invalid signature, empty only by NULLFAIL policy.".to_string(),
+            rewrite: "Select the path with a `bool` item and `verify checksig(...)` in the branch instead."
+                .to_string(),
+        });
+    }
+    if let Some((loc, operand)) = find_hash(condition) {
+        found.push(Malleation {
+            loc,
+            reason: "Failed hashlock is satisfied by any value but the preimage, which third party can replace.".to_string(),
+            rewrite: "Select the path with a `bool` item and `verify` the hashlock in the branch instead."
+                .to_string(),
+        });
+        // Witness string of any size is hashed, where bytes32 is guarded by OP_SIZE.
+        if let Expression::Variable(loc, id) = operand {
+            if stack_type(stack, id) == Some(Type::String) {
+                found.push(Malleation {
+                    loc: loc.to_owned(),
+                    reason: format!(
+                        "Size of preimage {:?} is not constrained, so the value may be of any size.",
+                        id.0
+                    ),
+                    rewrite: format!("Declare it as `{}: bytes32`, whose size is checked.", id.0),
+                });
+            }
+        }
+    }
+    found
+}
+
+fn find_checksig(expr: &Expression) -> Option<Location> {
+    match expr {
+        Expression::CheckSigExpression { loc, .. } => Some(loc.to_owned()),
+        _ => expr.sub_expressions().into_iter().find_map(find_checksig),
+    }
+}
+
+fn find_hash(expr: &Expression) -> Option<(Location, &Expression)> {
+    match expr {
+        Expression::UnaryCryptoExpression { loc, operand, .. } => Some((loc.to_owned(), operand)),
+        _ => expr.sub_expressions().into_iter().find_map(find_hash),
+    }
+}
+
+fn stack_type(stack: &[StackParam], id: &Identifier) -> Option<Type> {
+    stack
+        .iter()
+        .find(|item| item.identifier == *id)
+        .map(|item| item.ty.to_owned())
+}
@@ -0,0 +1,310 @@
+use crate::ast::*;
+use crate::compile::relative_locktime;
+use crate::source::*;
+
+// Spending policy of the script, as far as Miniscript can express it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Policy {
+    True,
+    Key(String),
+    Multi(u32, Vec<String>),
+    Hash(&'static str, String),
+    Older(u32),
+    After(u32),
+    And(Vec<Policy>),
+    Or(Vec<Policy>),
+}
+
+// Construct with no counterpart in Miniscript.
+pub type Blocker = (Location, String);
+
+// Unspendable internal key of BIP 341, so that taproot output is spent only by the script.
+pub const NUMS_KEY: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+// Miniscript and descriptor with the same spending policy as the script, or what blocks them.
+// The compiled script differs from the one of Miniscript, so wallets spend from the descriptor address.
+pub fn export_miniscript(ast: &[Statement], target: &Target) -> MiniscriptExport {
+    let mut blockers = vec![];
+    let policy = block_policy(ast, target, &mut blockers);
+    if !blockers.is_empty() {
+        return MiniscriptExport {
+            miniscript: None,
+            descriptor: None,
+            blockers: blockers
+                .iter()
+                .map(|(loc, reason)| format!("{}: {}", loc, reason))
+                .collect(),
+        };
+    }
+    let miniscript = miniscript_source(&simplify(policy), target);
+    let descriptor = match target {
+        Target::Legacy => format!("sh({})", miniscript),
+        Target::Segwit => format!("wsh({})", miniscript),
+        Target::Taproot => format!("tr({},{})", NUMS_KEY, miniscript),
+    };
+    MiniscriptExport {
+        miniscript: Some(miniscript),
+        descriptor: Some(descriptor),
+        blockers: vec![],
+    }
+}
+
+// Statements of the block must all hold, including the branch taken at each if and match.
+fn block_policy(block: &[Statement], target: &Target, blockers: &mut Vec<Blocker>) -> Policy {
+    let mut conjuncts = vec![];
+    for stmt in block {
+        match stmt {
+            Statement::LocktimeStatement {
+                operand,
+                op: LocktimeOp::Csv,
+                unit,
+                ..
+            } => conjuncts.push(Policy::Older(
+                relative_locktime(*operand, unit.to_owned())
+                    .to_sequence()
+                    .to_consensus_u32(),
+            )),
+            Statement::LocktimeStatement {
+                operand,
+                op: LocktimeOp::Cltv,
+                ..
+            } => conjuncts.push(Policy::After(*operand as u32)),
+            Statement::VerifyStatement(_loc, expr) | Statement::ExpressionStatement(_loc, expr) => {
+                conjuncts.push(expression_policy(expr, target, blockers))
+            }
+            Statement::IfStatement {
+                condition_expr,
+                if_block,
+                else_block,
+                ..
+            } => {
+                let if_policy = block_policy(if_block, target, blockers);
+                let else_policy =
+                    block_policy(else_block.as_deref().unwrap_or(&[]), target, blockers);
+                let if_policy = match condition_expr {
+                    // Witness selector picks either branch.
+                    Expression::Variable(..) => if_policy,
+                    // Condition can always be failed, e.g. by empty signature or wrong preimage.
+                    condition => Policy::And(vec![
+                        expression_policy(condition, target, blockers),
+                        if_policy,
+                    ]),
+                };
+                conjuncts.push(Policy::Or(vec![if_policy, else_policy]));
+            }
+            Statement::MatchStatement {
+                selector,
+                arms,
+                default,
+                ..
+            } => {
+                if !matches!(selector, Expression::Variable(..)) {
+                    blockers.push((
+                        selector.to_owned().loc(),
+                        format!(
+                            "Selector {} is not a witness item.",
+                            expression_source(selector)
+                        ),
+                    ));
+                }
+                let arms = arms
+                    .iter()
+                    .map(|arm| &arm.block)
+                    .chain(default.iter())
+                    .map(|block| block_policy(block, target, blockers))
+                    .collect();
+                conjuncts.push(Policy::Or(arms));
+            }
+        }
+    }
+    Policy::And(conjuncts)
+}
+
+// Expression that must be true.
+fn expression_policy(expr: &Expression, target: &Target, blockers: &mut Vec<Blocker>) -> Policy {
+    match expr {
+        Expression::InlineExpression { body, .. } => expression_policy(body, target, blockers),
+        Expression::BooleanLiteral(_, true) => Policy::True,
+        Expression::LogicalExpression { lhs, op, rhs, .. } => {
+            let operands = vec![
+                expression_policy(lhs, target, blockers),
+                expression_policy(rhs, target, blockers),
+            ];
+            match op {
+                BinaryLogicalOp::BoolAnd => Policy::And(operands),
+                BinaryLogicalOp::BoolOr => Policy::Or(operands),
+            }
+        }
+        Expression::CheckSigExpression { operand, .. } => match &**operand {
+            Factor::SingleSigFactor { pubkey, .. } => {
+                Policy::Key(key_source(pubkey, target, blockers))
+            }
+            Factor::MultiSigFactor { m, n, .. } => Policy::Multi(
+                *m,
+                n.iter()
+                    .filter_map(|factor| match factor {
+                        Factor::SingleSigFactor { pubkey, .. } => {
+                            Some(key_source(pubkey, target, blockers))
+                        }
+                        Factor::MultiSigFactor { .. } => None,
+                    })
+                    .collect(),
+            ),
+        },
+        Expression::CompareExpression {
+            lhs,
+            op: BinaryCompareOp::Equal,
+            rhs,
+            loc,
+        } => match hash_policy(lhs, rhs).or_else(|| hash_policy(rhs, lhs)) {
+            Some(policy) => policy,
+            None => blocked(
+                loc,
+                format!(
+                    "Only hash of a witness item compared to digest has a counterpart, but: {}.",
+                    expression_source(expr)
+                ),
+                blockers,
+            ),
+        },
+        _ => blocked(
+            &expr.to_owned().loc(),
+            format!(
+                "Only checksig, multisig, hashlock, older and after have a counterpart, but: {}.",
+                expression_source(expr)
+            ),
+            blockers,
+        ),
+    }
+}
+
+fn blocked(loc: &Location, reason: String, blockers: &mut Vec<Blocker>) -> Policy {
+    blockers.push((loc.to_owned(), reason));
+    Policy::True
+}
+
+// Pubkey must be literal, and x-only in tapscript.
+fn key_source(pubkey: &Expression, target: &Target, blockers: &mut Vec<Blocker>) -> String {
+    match pubkey {
+        Expression::StringLiteral(loc, key) if *target == Target::Taproot && key.len() != 64 => {
+            blockers.push((
+                loc.to_owned(),
+                format!(
+                    "Pubkey in tapscript must be x-only of 32 bytes, but: {}.",
+                    key
+                ),
+            ));
+            key.to_owned()
+        }
+        Expression::StringLiteral(_, key) => key.to_owned(),
+        _ => {
+            blockers.push((
+                pubkey.to_owned().loc(),
+                format!(
+                    "Pubkey {} is supplied by witness, which descriptor can't commit to.",
+                    expression_source(pubkey)
+                ),
+            ));
+            String::new()
+        }
+    }
+}
+
+// Hash fragment, where double hash of Bitcoin is a single fragment.
+fn hash_policy(hashed: &Expression, digest: &Expression) -> Option<Policy> {
+    let Expression::StringLiteral(_, digest) = digest else {
+        return None;
+    };
+    let Expression::UnaryCryptoExpression { operand, op, .. } = hashed else {
+        return None;
+    };
+    let hash = match (op, &**operand) {
+        (UnaryCryptoOp::Sha256, Expression::Variable(..)) => "sha256",
+        (UnaryCryptoOp::Hash256, Expression::Variable(..)) => "hash256",
+        (UnaryCryptoOp::Ripemd160, Expression::Variable(..)) => "ripemd160",
+        (UnaryCryptoOp::Hash160, Expression::Variable(..)) => "hash160",
+        (
+            UnaryCryptoOp::Sha256,
+            Expression::UnaryCryptoExpression {
+                op: UnaryCryptoOp::Sha256,
+                operand,
+                ..
+            },
+        ) if matches!(**operand, Expression::Variable(..)) => "hash256",
+        (
+            UnaryCryptoOp::Ripemd160,
+            Expression::UnaryCryptoExpression {
+                op: UnaryCryptoOp::Sha256,
+                operand,
+                ..
+            },
+        ) if matches!(**operand, Expression::Variable(..)) => "hash160",
+        _ => return None,
+    };
+    Some(Policy::Hash(hash, digest.to_owned()))
+}
+
+// Flatten nested and/or, and drop `1` from and.
+pub fn simplify(policy: Policy) -> Policy {
+    match policy {
+        Policy::And(conjuncts) => {
+            let mut flat = vec![];
+            for conjunct in conjuncts.into_iter().map(simplify) {
+                match conjunct {
+                    Policy::True => (),
+                    Policy::And(inner) => flat.extend(inner),
+                    conjunct => flat.push(conjunct),
+                }
+            }
+            match flat.len() {
+                0 => Policy::True,
+                1 => flat.remove(0),
+                _ => Policy::And(flat),
+            }
+        }
+        Policy::Or(disjuncts) => {
+            let mut flat = vec![];
+            for disjunct in disjuncts.into_iter().map(simplify) {
+                match disjunct {
+                    Policy::Or(inner) => flat.extend(inner),
+                    disjunct => flat.push(disjunct),
+                }
+            }
+            Policy::Or(flat)
+        }
+        policy => policy,
+    }
+}
+
+// Every fragment is of type B, so sequence is and_v with v: wrapper and branch is or_i.
+pub fn miniscript_source(policy: &Policy, target: &Target) -> String {
+    match policy {
+        Policy::True => "1".to_string(),
+        Policy::Key(key) => format!("pk({})", key),
+        Policy::Multi(m, keys) => match target {
+            Target::Taproot => format!("multi_a({},{})", m, keys.join(",")),
+            _ => format!("multi({},{})", m, keys.join(",")),
+        },
+        Policy::Hash(hash, digest) => format!("{}({})", hash, digest),
+        Policy::Older(n) => format!("older({})", n),
+        Policy::After(n) => format!("after({})", n),
+        Policy::And(conjuncts) => match conjuncts.split_last() {
+            None => "1".to_string(),
+            Some((last, init)) => init
+                .iter()
+                .rev()
+                .fold(miniscript_source(last, target), |rest, policy| {
+                    format!("and_v(v:{},{})", miniscript_source(policy, target), rest)
+                }),
+        },
+        Policy::Or(disjuncts) => match disjuncts.split_last() {
+            None => "0".to_string(),
+            Some((last, init)) => init
+                .iter()
+                .rev()
+                .fold(miniscript_source(last, target), |rest, policy| {
+                    format!("or_i({},{})", miniscript_source(policy, target), rest)
+                }),
+        },
+    }
+}
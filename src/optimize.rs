@@ -0,0 +1,145 @@
+use crate::ast::*;
+use crate::source::*;
+
+// Rewrite the script into cheaper equivalent after analysis, unless under -O0.
+// Rewrite must keep what is legal, so analysis on the source holds for the result.
+pub fn optimize(ast: &mut [Statement]) {
+    for stmt in ast {
+        optimize_statement(stmt);
+    }
+}
+
+pub fn optimize_statement(stmt: &mut Statement) {
+    match stmt {
+        Statement::IfStatement {
+            loc: _,
+            condition_expr,
+            if_block,
+            else_block,
+        } => {
+            optimize_expression(condition_expr);
+            optimize(if_block);
+            if let Some(else_b) = else_block.as_mut() {
+                optimize(else_b);
+            }
+        }
+        Statement::MatchStatement { arms, default, .. } => {
+            for arm in arms {
+                optimize(&mut arm.block);
+            }
+            if let Some(default_b) = default.as_mut() {
+                optimize(default_b);
+            }
+        }
+        Statement::VerifyStatement(_loc, expr) => optimize_expression(expr),
+        Statement::ExpressionStatement(_loc, expr) => optimize_expression(expr),
+        Statement::LocktimeStatement { .. } => (),
+    }
+}
+
+pub fn optimize_expression(expression: &mut Expression) {
+    for child in expression.sub_expressions_mut() {
+        optimize_expression(child);
+    }
+    if let Some(within) = fold_within(expression) {
+        *expression = within;
+    }
+}
+
+// `x >= a && x < b` is `x in a..b`, which uses the variable once with a single OP_WITHIN.
+// `x <= b` is `x < b + 1`, unless b + 1 overflows the 4 bytes OP_WITHIN takes.
+pub fn fold_within(expression: &Expression) -> Option<Expression> {
+    let range = range_form(expression)?;
+    let max = match (range.inclusive, range.max) {
+        (false, max) => max.to_owned(),
+        (true, Expression::NumberLiteral(loc, n)) if *n < i32::MAX as i64 => {
+            Expression::NumberLiteral(loc.to_owned(), n + 1)
+        }
+        _ => return None,
+    };
+    Some(Expression::WithinExpression {
+        loc: expression.to_owned().loc(),
+        operand: Box::new(range.operand.to_owned()),
+        min: Box::new(range.min.to_owned()),
+        max: Box::new(max),
+    })
+}
+
+// Range check written as comparisons, `x >= a && x < b` or `x >= a && x <= b`.
+pub struct Range<'a> {
+    pub operand: &'a Expression,
+    pub min: &'a Expression,
+    pub max: &'a Expression,
+    /// Whether the upper bound is taken by `<=`.
+    pub inclusive: bool,
+}
+
+// Range form is a single use of its variable, compiled to OP_WITHIN or by a copy of the variable.
+// Bounds must not touch the stack, as either one changes their evaluation order.
+pub fn range_form(expression: &Expression) -> Option<Range<'_>> {
+    let Expression::LogicalExpression {
+        lhs,
+        op: BinaryLogicalOp::BoolAnd,
+        rhs,
+        ..
+    } = expression
+    else {
+        return None;
+    };
+    let (lower_operand, min) = lower_bound(lhs)?;
+    let (upper_operand, max, inclusive) = upper_bound(rhs)?;
+    match (lower_operand, upper_operand) {
+        (Expression::Variable(_, lower_id), Expression::Variable(_, upper_id))
+            if lower_id == upper_id && is_stack_free(min) && is_stack_free(max) =>
+        {
+            Some(Range {
+                operand: lower_operand,
+                min,
+                max,
+                inclusive,
+            })
+        }
+        _ => None,
+    }
+}
+
+// `x >= a` or `a <= x`
+fn lower_bound(expression: &Expression) -> Option<(&Expression, &Expression)> {
+    match expression {
+        Expression::CompareExpression {
+            lhs,
+            op: BinaryCompareOp::GreaterOrEqual,
+            rhs,
+            ..
+        } => Some((lhs, rhs)),
+        Expression::CompareExpression {
+            lhs,
+            op: BinaryCompareOp::LessOrEqual,
+            rhs,
+            ..
+        } => Some((rhs, lhs)),
+        _ => None,
+    }
+}
+
+// `x < b` or `b > x`, and `x <= b` or `b >= x` as inclusive
+fn upper_bound(expression: &Expression) -> Option<(&Expression, &Expression, bool)> {
+    match expression {
+        Expression::CompareExpression { lhs, op, rhs, .. } => match op {
+            BinaryCompareOp::Less => Some((lhs, rhs, false)),
+            BinaryCompareOp::Greater => Some((rhs, lhs, false)),
+            BinaryCompareOp::LessOrEqual => Some((lhs, rhs, true)),
+            BinaryCompareOp::GreaterOrEqual => Some((rhs, lhs, true)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Whether the expression is computed without any stack item.
+pub fn is_stack_free(expression: &Expression) -> bool {
+    match expression {
+        Expression::Variable(..) | Expression::CheckSigExpression { .. } => false,
+        _ => expression.sub_expressions().into_iter().all(is_stack_free),
+    }
+}
@@ -4,8 +4,9 @@ mod tests {
     use crate::{
         ast::*,
         bithoven::{
-            BithovenParser, BooleanLiteralParser, Expression0Parser, MultiSigFactorParser,
-            NumberLiteralParser, StackParser, StatementParser, StringLiteralParser,
+            BithovenParser, BooleanLiteralParser, Expression0Parser, LibraryParser,
+            MultiSigFactorParser, NumberLiteralParser, StackParser, StatementParser,
+            StringLiteralParser,
         },
     }; // Import your AST definitions
     use std::fs;
@@ -283,6 +284,64 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_else_if_statement() {
+        let input = "if a { return 1; } else if b { return 2; } else { return 3; }";
+        let ast = assert_parses!(StatementParser::new(), input);
+        if let Statement::IfStatement {
+            else_block: Some(else_block),
+            ..
+        } = ast
+        {
+            assert_eq!(else_block.len(), 1);
+            assert!(matches!(
+                else_block[0],
+                Statement::IfStatement {
+                    else_block: Some(_),
+                    ..
+                }
+            ));
+        } else {
+            panic!("Expected IfStatement with else block");
+        }
+    }
+
+    #[test]
+    fn test_match_statement() {
+        let input = "match choice { 0 => { return 1; }, -1 => { return 2; } _ => { return 3; }, }";
+        let ast = assert_parses!(StatementParser::new(), input);
+        if let Statement::MatchStatement {
+            selector,
+            arms,
+            default,
+            ..
+        } = ast
+        {
+            assert!(
+                matches!(selector, Expression::Variable(_, Identifier(ref id)) if id == "choice")
+            );
+            let values: Vec<i64> = arms.iter().map(|arm| arm.value).collect();
+            assert_eq!(values, vec![0, -1]);
+            assert!(default.is_some());
+        } else {
+            panic!("Expected MatchStatement");
+        }
+
+        // Default arm is optional, but must be the last.
+        assert_parses!(
+            StatementParser::new(),
+            "match choice { 1 => { return 1; } }"
+        );
+        assert_fails!(
+            StatementParser::new(),
+            "match choice { _ => { return 1; } }"
+        );
+        assert_fails!(
+            StatementParser::new(),
+            "match choice { _ => { return 1; } 0 => { return 2; } }"
+        );
+    }
+
     #[test]
     fn test_verify_statement() {
         let input = "verify true;";
@@ -318,6 +377,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_relative_locktime_units() {
+        let cases = vec![
+            ("older 144 blocks;", 144, LocktimeUnit::Block),
+            ("older 3600 seconds;", 3600, LocktimeUnit::Second),
+            ("older 90 minutes;", 5400, LocktimeUnit::Second),
+            ("older 2 hours;", 7200, LocktimeUnit::Second),
+            ("older 7 days;", 604800, LocktimeUnit::Second),
+            ("older 2 weeks;", 1209600, LocktimeUnit::Second),
+        ];
+        for (input, seconds, time_unit) in cases {
+            let ast = assert_parses!(StatementParser::new(), input);
+            if let Statement::LocktimeStatement {
+                op, operand, unit, ..
+            } = ast
+            {
+                assert_eq!(op, LocktimeOp::Csv);
+                assert_eq!(operand, seconds);
+                assert_eq!(unit, time_unit);
+            } else {
+                panic!("Expected LocktimeStatement for {}", input);
+            }
+        }
+
+        // Absolute locktime doesn't take relative unit.
+        assert!(StatementParser::new().parse("after 7 days;").is_err());
+    }
+
     #[test]
     fn test_logical_or_expression() {
         let input = "true || false";
@@ -408,6 +495,21 @@ mod tests {
         } else {
             panic!("Expected UnaryCryptoExpression for ripemd160");
         }
+
+        let ops = vec![
+            ("hash160", UnaryCryptoOp::Hash160),
+            ("hash256", UnaryCryptoOp::Hash256),
+            ("sha1", UnaryCryptoOp::Sha1),
+        ];
+        for (op_str, op_enum) in ops {
+            let input = format!("{} a", op_str);
+            let ast = assert_parses!(Expression0Parser::new(), &input);
+            if let Expression::UnaryCryptoExpression { op, .. } = ast {
+                assert_eq!(op, op_enum);
+            } else {
+                panic!("Expected UnaryCryptoExpression for {}", op_str);
+            }
+        }
     }
 
     #[test]
@@ -421,6 +523,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_within_expression() {
+        for input in ["x in 10..a + 1", "within(x, 10, a + 1)"] {
+            let ast = assert_parses!(Expression0Parser::new(), input);
+            if let Expression::WithinExpression {
+                operand, min, max, ..
+            } = ast
+            {
+                assert!(matches!(*operand, Expression::Variable(..)));
+                assert!(matches!(*min, Expression::NumberLiteral(_, 10)));
+                assert!(matches!(*max, Expression::BinaryMathExpression { .. }));
+            } else {
+                panic!("Expected WithinExpression for {}", input);
+            }
+        }
+    }
+
     #[test]
     fn test_checksig_with_multisig() {
         let input = "checksig [2, (s1, p1), (s2, p2)]";
@@ -499,4 +618,143 @@ mod tests {
         "#;
         assert_fails!(BithovenParser::new(), input);
     }
+
+    #[test]
+    fn test_function_declaration() {
+        let input = r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+
+            fn hashlock_sig(p: string, s: signature) -> bool {
+                return sha256 p == "53de742e2e323e3290234052a702458589c30d2c813bf9f866bef1b651c4e45f" && checksig (s, "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212");
+            }
+
+            (s: signature, p: string)
+            {
+                return hashlock_sig(p, s);
+            }
+        "#;
+        let ast = assert_parses!(BithovenParser::new(), input);
+
+        assert_eq!(ast.functions.len(), 1);
+        let function = &ast.functions[0];
+        assert_eq!(function.identifier.0, "hashlock_sig");
+        assert_eq!(function.ty, Type::Boolean);
+        // Parameters keep declared order, unlike stack.
+        assert_eq!(function.params[0].identifier.0, "p");
+        assert_eq!(function.params[1].identifier.0, "s");
+        assert!(matches!(
+            function.body,
+            Expression::LogicalExpression { .. }
+        ));
+    }
+
+    #[test]
+    fn test_call_expression() {
+        let ast = assert_parses!(Expression0Parser::new(), "f(a, 1 + 2, g())");
+        if let Expression::CallExpression {
+            identifier, args, ..
+        } = ast
+        {
+            assert_eq!(identifier.0, "f");
+            assert_eq!(args.len(), 3);
+            assert!(matches!(args[1], Expression::BinaryMathExpression { .. }));
+            assert!(matches!(
+                &args[2],
+                Expression::CallExpression { args, .. } if args.is_empty()
+            ));
+        } else {
+            panic!("Expected CallExpression");
+        }
+    }
+
+    #[test]
+    fn test_invalid_syntax_function_without_return() {
+        let input = r#"
+            pragma bithoven version 0.0.1;
+            pragma bithoven target segwit;
+            fn f(a: bool) -> bool { a; }
+            (s: signature)
+            {}
+        "#;
+        assert_fails!(BithovenParser::new(), input);
+    }
+
+    #[test]
+    fn test_library() {
+        let input = r#"
+            import "hashes.bithoven";
+            const alice: string = "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+            fn f(a: bool) -> bool { return a; }
+            const limit: number = -5;
+        "#;
+        let library = assert_parses!(LibraryParser::new(), input);
+        assert_eq!(library.imports[0].path, "hashes.bithoven");
+        assert_eq!(library.constants.len(), 2);
+        assert!(matches!(
+            library.constants[1].value,
+            Expression::NumberLiteral(_, -5)
+        ));
+        assert_eq!(library.functions.len(), 1);
+
+        // Constant must be literal.
+        assert_fails!(LibraryParser::new(), "const a: number = 1 + 2;");
+        // Library has no stack and script.
+        assert_fails!(LibraryParser::new(), "(s: signature) {}");
+    }
+
+    #[test]
+    fn test_pubkey_type() {
+        let stack = assert_parses!(StackParser::new(), "(pk: pubkey, sig: signature)");
+        assert_eq!(stack[1].identifier.0, "pk");
+        assert_eq!(stack[1].ty, Type::PublicKey);
+    }
+
+    #[test]
+    fn test_bytes_type() {
+        let stack = assert_parses!(
+            StackParser::new(),
+            "(a: bytes20, b: bytes32, c: bytes<64>, bytes_count: number)"
+        );
+        assert_eq!(stack[3].ty, Type::Bytes(20));
+        assert_eq!(stack[2].ty, Type::Bytes(32));
+        assert_eq!(stack[1].ty, Type::Bytes(64));
+        assert_eq!(stack[0].identifier.0, "bytes_count");
+    }
+
+    #[test]
+    fn test_absolute_locktime_forms() {
+        let cases = vec![
+            ("after 500000;", 500000, LocktimeUnit::Raw),
+            ("after height 900000;", 900000, LocktimeUnit::Block),
+            (
+                "after 2027-01-01T00:00:00Z;",
+                1798761600,
+                LocktimeUnit::Second,
+            ),
+            (
+                "after 2024-02-29T12:30:15Z;",
+                1709209815,
+                LocktimeUnit::Second,
+            ),
+        ];
+        for (input, expected, expected_unit) in cases {
+            let ast = assert_parses!(StatementParser::new(), input);
+            if let Statement::LocktimeStatement {
+                op, operand, unit, ..
+            } = ast
+            {
+                assert_eq!(op, LocktimeOp::Cltv);
+                assert_eq!(operand, expected, "{}", input);
+                assert_eq!(unit, expected_unit);
+            } else {
+                panic!("Expected LocktimeStatement for {}", input);
+            }
+        }
+
+        // Date which doesn't exist.
+        for input in ["after 2027-02-29T00:00:00Z;", "after 2027-01-01T24:00:00Z;"] {
+            assert!(StatementParser::new().parse(input).is_err(), "{}", input);
+        }
+    }
 }
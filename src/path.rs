@@ -0,0 +1,232 @@
+use crate::ast::*;
+use crate::source::*;
+
+// Step of path, in the order of execution.
+#[derive(Clone, Debug)]
+pub enum Step<'a> {
+    /// Condition of if statement, with whether if block is taken.
+    Branch {
+        condition: &'a Expression,
+        taken: bool,
+    },
+    /// Arm of match statement taken, `None` for the default arm.
+    Arm {
+        selector: &'a Expression,
+        arms: &'a [MatchArm],
+        taken: Option<usize>,
+        default: bool,
+    },
+    /// Statement executed, except if and match statement itself.
+    Statement(&'a Statement),
+}
+
+// One way through the script, from the top to its end.
+// Bitcoin script has no loop, so every spending path is one of these.
+#[derive(Clone, Debug, Default)]
+pub struct Path<'a> {
+    pub steps: Vec<Step<'a>>,
+}
+
+impl<'a> Path<'a> {
+    /// Statements executed on the path.
+    pub fn statements(&self) -> impl Iterator<Item = &'a Statement> + '_ {
+        self.steps.iter().filter_map(|step| match step {
+            Step::Statement(stmt) => Some(*stmt),
+            Step::Branch { .. } | Step::Arm { .. } => None,
+        })
+    }
+
+    /// Whether if block is taken at each branch, from the top.
+    /// Match counts each arm tested as a branch, as if written in else if chain.
+    pub fn branches(&self) -> Vec<bool> {
+        self.steps
+            .iter()
+            .flat_map(|step| match step {
+                Step::Branch { taken, .. } => vec![*taken],
+                Step::Arm { arms, taken, .. } => match taken {
+                    Some(k) => (0..=*k).map(|i| i == *k).collect(),
+                    None => vec![false; arms.len()],
+                },
+                Step::Statement(_) => vec![],
+            })
+            .collect()
+    }
+
+    /// Whether OP_IF is taken at each one run on the path, in the order of execution.
+    /// Match tests every arm by OP_IF, but the last one if there's no default arm.
+    pub fn conditions(&self) -> Vec<bool> {
+        self.steps
+            .iter()
+            .flat_map(|step| match step {
+                Step::Branch { taken, .. } => vec![*taken],
+                Step::Arm {
+                    arms,
+                    taken,
+                    default,
+                    ..
+                } => {
+                    let tested = if *default { arms.len() } else { arms.len() - 1 };
+                    (0..tested)
+                        .take(taken.map_or(tested, |k| k + 1))
+                        .map(|i| *taken == Some(i))
+                        .collect()
+                }
+                Step::Statement(_) => vec![],
+            })
+            .collect()
+    }
+
+    /// Witness template for the input stack of the path, from the top.
+    /// Boolean item taken by branch as is, e.g. `if condition`, must be the branch taken.
+    /// Selector of match must be the value of the arm taken, or none of them for default arm.
+    pub fn witness(&self, stack: &[StackParam]) -> Vec<String> {
+        stack
+            .iter()
+            .rev()
+            .map(|item| {
+                let selected = self.steps.iter().find_map(|step| match step {
+                    Step::Branch {
+                        condition: Expression::Variable(_loc, id),
+                        taken,
+                    } if *id == item.identifier && item.ty == Type::Boolean => {
+                        Some(taken.to_string())
+                    }
+                    Step::Arm {
+                        selector: Expression::Variable(_loc, id),
+                        arms,
+                        taken,
+                        ..
+                    } if *id == item.identifier => Some(match taken {
+                        Some(k) => arms[*k].value.to_string(),
+                        None => (0..)
+                            .find(|n| arms.iter().all(|arm| arm.value != *n))
+                            .unwrap_or_default()
+                            .to_string(),
+                    }),
+                    _ => None,
+                });
+                selected.unwrap_or_else(|| format!("<{}>", item.identifier.0))
+            })
+            .collect()
+    }
+
+    /// Encoding of each boolean item of the input stack, from the top.
+    /// Item taken by OP_IF as is must be minimal under MINIMALIF, the others are cast as number.
+    pub fn booleans(&self, stack: &[StackParam], target: &Target) -> Vec<BooleanInput> {
+        let ty = |id: &Identifier| {
+            stack
+                .iter()
+                .find(|item| item.identifier == *id)
+                .map(|item| item.ty.to_owned())
+        };
+        stack
+            .iter()
+            .rev()
+            .filter(|item| item.ty == Type::Boolean)
+            .map(|item| {
+                let direct = self.steps.iter().any(|step| match step {
+                    Step::Branch { condition, .. } => {
+                        condition.condition(&ty) == Condition::Witness(item.identifier.to_owned())
+                    }
+                    _ => false,
+                });
+                BooleanInput {
+                    name: item.identifier.0.to_owned(),
+                    encoding: if direct && target.is_minimal_if() {
+                        BooleanEncoding::MinimalIf
+                    } else {
+                        BooleanEncoding::Number
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Location of the last step, where the path ends.
+    pub fn end(&self) -> Location {
+        match self.steps.last() {
+            Some(Step::Branch { condition, .. }) => (*condition).to_owned().loc(),
+            Some(Step::Arm { selector, .. }) => (*selector).to_owned().loc(),
+            Some(Step::Statement(stmt)) => (*stmt).to_owned().loc(),
+            None => Location::default(),
+        }
+    }
+
+    // Path followed by the branch and its sub path.
+    fn join(&self, step: Step<'a>, sub_path: &Path<'a>) -> Path<'a> {
+        let mut path = self.to_owned();
+        path.steps.push(step);
+        path.steps.extend(sub_path.steps.iter().cloned());
+        path
+    }
+}
+
+// Enumerate every path, in the order of if block first.
+pub fn enumerate_paths(ast: &[Statement]) -> Vec<Path<'_>> {
+    let mut paths = vec![Path::default()];
+    for stmt in ast {
+        match stmt {
+            Statement::IfStatement {
+                loc: _,
+                condition_expr,
+                if_block,
+                else_block,
+            } => {
+                let if_paths = enumerate_paths(if_block);
+                let else_paths = enumerate_paths(else_block.as_deref().unwrap_or(&[]));
+                paths = paths
+                    .iter()
+                    .flat_map(|path| {
+                        let branch = |taken| Step::Branch {
+                            condition: condition_expr,
+                            taken,
+                        };
+                        let if_branch = if_paths
+                            .iter()
+                            .map(|sub_path| path.join(branch(true), sub_path));
+                        let else_branch = else_paths
+                            .iter()
+                            .map(|sub_path| path.join(branch(false), sub_path));
+                        if_branch.chain(else_branch).collect::<Vec<_>>()
+                    })
+                    .collect();
+            }
+            Statement::MatchStatement {
+                loc: _,
+                selector,
+                arms,
+                default,
+            } => {
+                // Arms in order, and default arm at last if any.
+                let arm_paths: Vec<(Option<usize>, Vec<Path>)> = arms
+                    .iter()
+                    .enumerate()
+                    .map(|(k, arm)| (Some(k), enumerate_paths(&arm.block)))
+                    .chain(default.iter().map(|block| (None, enumerate_paths(block))))
+                    .collect();
+                paths = paths
+                    .iter()
+                    .flat_map(|path| {
+                        arm_paths.iter().flat_map(move |(taken, sub_paths)| {
+                            sub_paths.iter().map(move |sub_path| {
+                                let arm = Step::Arm {
+                                    selector,
+                                    arms,
+                                    taken: *taken,
+                                    default: default.is_some(),
+                                };
+                                path.join(arm, sub_path)
+                            })
+                        })
+                    })
+                    .collect();
+            }
+            _ => {
+                for path in paths.iter_mut() {
+                    path.steps.push(Step::Statement(stmt));
+                }
+            }
+        }
+    }
+    paths
+}
@@ -0,0 +1,423 @@
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::{Class, ClassifyContext};
+use bitcoin::script::{Builder, PushBytesBuf};
+use bitcoin::Opcode;
+
+// Script as codegen emits it: opcodes and data pushes, before encoding.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instruction {
+    Op(Opcode),
+    /// Data push, encoded as OP_PUSHBYTES_N or OP_PUSHDATA1/2/4 by its length.
+    Push(Vec<u8>),
+}
+
+// Append the script written by builder, as instructions.
+pub fn emit(script: &mut Vec<Instruction>, builder: Builder) {
+    for instruction in builder.as_script().instructions() {
+        script.push(
+            match instruction.expect("Builder writes well-formed script.") {
+                bitcoin::script::Instruction::Op(op) => Instruction::Op(op),
+                bitcoin::script::Instruction::PushBytes(bytes) => {
+                    Instruction::Push(bytes.as_bytes().to_vec())
+                }
+            },
+        );
+    }
+}
+
+pub fn encode(script: &[Instruction]) -> Vec<u8> {
+    let mut builder = Builder::new();
+    for instruction in script {
+        builder = match instruction {
+            Instruction::Op(op) => builder.push_opcode(*op),
+            Instruction::Push(data) => builder.push_slice(
+                PushBytesBuf::try_from(data.to_owned()).expect("Push is shorter than 4GB."),
+            ),
+        };
+    }
+    builder.into_bytes()
+}
+
+// Rewrite of the instructions at the start of the window, returning how many it replaces and by what.
+// Each keeps what the script does for every input stack it succeeds on.
+pub type Rule = fn(&[Instruction]) -> Option<(usize, Vec<Instruction>)>;
+
+pub const RULES: &[Rule] = &[
+    verify_fusion,
+    double_hash,
+    negated_numequal,
+    notif,
+    noop_pair,
+    double_drop,
+    commutative_swap,
+    single_key_multisig,
+    empty_block,
+    negated_notif,
+];
+
+// Peephole rules and branch factoring, until the script doesn't get any shorter.
+pub fn optimize_script(mut script: Vec<Instruction>) -> Vec<Instruction> {
+    loop {
+        script = peephole(script);
+        match factor(&script) {
+            Some(factored) => script = factored,
+            None => return script,
+        }
+    }
+}
+
+// Branch order of the least weight, swapping each branch in turn if it weighs less.
+// OP_IF <a> OP_ELSE <b> OP_ENDIF runs as OP_NOTIF <b> OP_ELSE <a> OP_ENDIF, which may leave other rewrites.
+pub fn minimize(script: Vec<Instruction>, weight: impl Fn(&[u8]) -> usize) -> Vec<Instruction> {
+    let mut best = optimize_script(script.to_owned());
+    let mut script = script;
+    for i in 0..branches(&script).len() {
+        let mut order = branches(&script);
+        order.sort();
+        let (start, else_at, end) = order[i];
+        let negated = match script[start] {
+            Instruction::Op(OP_IF) => OP_NOTIF,
+            _ => OP_IF,
+        };
+        let swapped = [
+            &script[..start],
+            &[Instruction::Op(negated)],
+            &script[else_at + 1..end],
+            &script[else_at..=else_at],
+            &script[start + 1..else_at],
+            &script[end..],
+        ]
+        .concat();
+        let optimized = optimize_script(swapped.to_owned());
+        if weight(&encode(&optimized)) < weight(&encode(&best)) {
+            script = swapped;
+            best = optimized;
+        }
+    }
+    best
+}
+
+// Apply rules at the earliest position until none applies.
+// Rewrite may enable another one spanning the instructions before it, so scan steps back by the longest window.
+pub fn peephole(mut script: Vec<Instruction>) -> Vec<Instruction> {
+    let mut i = 0;
+    while i < script.len() {
+        match RULES.iter().find_map(|rule| rule(&script[i..])) {
+            Some((len, rewrite)) => {
+                script.splice(i..i + len, rewrite);
+                i = i.saturating_sub(2);
+            }
+            None => i += 1,
+        }
+    }
+    script
+}
+
+fn op(instruction: &Instruction) -> Option<Opcode> {
+    match instruction {
+        Instruction::Op(op) => Some(*op),
+        Instruction::Push(_) => None,
+    }
+}
+
+fn is_push(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::Push(_) => true,
+        Instruction::Op(op) => matches!(
+            op.classify(ClassifyContext::TapScript),
+            Class::PushNum(_) | Class::PushBytes(_)
+        ),
+    }
+}
+
+// Opcode leaving exactly 0(empty) or 1, as OP_IF and OP_NOTIF under MINIMALIF require.
+fn is_boolean(op: Opcode) -> bool {
+    matches!(
+        op,
+        OP_EQUAL
+            | OP_NUMEQUAL
+            | OP_NUMNOTEQUAL
+            | OP_LESSTHAN
+            | OP_GREATERTHAN
+            | OP_LESSTHANOREQUAL
+            | OP_GREATERTHANOREQUAL
+            | OP_BOOLAND
+            | OP_BOOLOR
+            | OP_NOT
+            | OP_0NOTEQUAL
+            | OP_WITHIN
+            | OP_CHECKSIG
+            | OP_CHECKMULTISIG
+    )
+}
+
+fn ops<const N: usize>(window: &[Instruction]) -> Option<[Opcode; N]> {
+    let ops: Vec<Opcode> = window.iter().take(N).map_while(op).collect();
+    ops.try_into().ok()
+}
+
+// OP_EQUAL OP_VERIFY => OP_EQUALVERIFY
+fn verify_fusion(window: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    let fused = match ops(window)? {
+        [OP_EQUAL, OP_VERIFY] => OP_EQUALVERIFY,
+        [OP_NUMEQUAL, OP_VERIFY] => OP_NUMEQUALVERIFY,
+        [OP_CHECKSIG, OP_VERIFY] => OP_CHECKSIGVERIFY,
+        [OP_CHECKMULTISIG, OP_VERIFY] => OP_CHECKMULTISIGVERIFY,
+        _ => return None,
+    };
+    Some((2, vec![Instruction::Op(fused)]))
+}
+
+// OP_SHA256 OP_RIPEMD160 => OP_HASH160
+fn double_hash(window: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    let hash = match ops(window)? {
+        [OP_SHA256, OP_RIPEMD160] => OP_HASH160,
+        [OP_SHA256, OP_SHA256] => OP_HASH256,
+        _ => return None,
+    };
+    Some((2, vec![Instruction::Op(hash)]))
+}
+
+// OP_NUMNOTEQUAL OP_NOT => OP_NUMEQUAL, which fuses with OP_VERIFY in turn.
+fn negated_numequal(window: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    let negated = match ops(window)? {
+        [OP_NUMEQUAL, OP_NOT] => OP_NUMNOTEQUAL,
+        [OP_NUMNOTEQUAL, OP_NOT] => OP_NUMEQUAL,
+        _ => return None,
+    };
+    Some((2, vec![Instruction::Op(negated)]))
+}
+
+// OP_EQUAL OP_NOT OP_IF => OP_EQUAL OP_NOTIF
+// OP_NOT takes any number, but OP_NOTIF only 0 or 1 under MINIMALIF, so the value must be boolean.
+fn notif(window: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    match ops(window)? {
+        [value, OP_NOT, OP_IF] if is_boolean(value) => {
+            Some((3, vec![Instruction::Op(value), Instruction::Op(OP_NOTIF)]))
+        }
+        _ => None,
+    }
+}
+
+// OP_DUP OP_DROP, <push> OP_DROP, OP_TOALTSTACK OP_FROMALTSTACK and OP_SWAP OP_SWAP leave the stack as it was.
+fn noop_pair(window: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    match window {
+        [first, Instruction::Op(OP_DROP), ..] if is_push(first) => Some((2, vec![])),
+        _ => match ops(window)? {
+            [OP_DUP, OP_DROP] | [OP_TOALTSTACK, OP_FROMALTSTACK] | [OP_SWAP, OP_SWAP] => {
+                Some((2, vec![]))
+            }
+            _ => None,
+        },
+    }
+}
+
+// OP_DROP OP_DROP => OP_2DROP
+fn double_drop(window: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    match ops(window)? {
+        [OP_DROP, OP_DROP] => Some((2, vec![Instruction::Op(OP_2DROP)])),
+        _ => None,
+    }
+}
+
+// OP_SWAP OP_EQUAL => OP_EQUAL, as the operands commute.
+fn commutative_swap(window: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    match ops(window)? {
+        [OP_SWAP, op @ (OP_EQUAL | OP_EQUALVERIFY | OP_NUMEQUAL | OP_NUMEQUALVERIFY | OP_NUMNOTEQUAL
+        | OP_BOOLAND | OP_BOOLOR | OP_ADD | OP_MIN | OP_MAX)] => {
+            Some((2, vec![Instruction::Op(op)]))
+        }
+        _ => None,
+    }
+}
+
+// Tapscript multisig of one key: OP_CHECKSIG 1 OP_NUMEQUAL => OP_CHECKSIG, which is 0 or 1 already.
+fn single_key_multisig(window: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    match ops(window)? {
+        [OP_CHECKSIG, OP_PUSHNUM_1, OP_NUMEQUAL | OP_EQUAL] => {
+            Some((3, vec![Instruction::Op(OP_CHECKSIG)]))
+        }
+        _ => None,
+    }
+}
+
+// OP_IF OP_ELSE => OP_NOTIF, and OP_ELSE OP_ENDIF => OP_ENDIF, as left by branch factoring.
+fn empty_block(window: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    match ops(window)? {
+        [OP_IF, OP_ELSE] => Some((2, vec![Instruction::Op(OP_NOTIF)])),
+        [OP_NOTIF, OP_ELSE] => Some((2, vec![Instruction::Op(OP_IF)])),
+        [OP_ELSE, OP_ENDIF] => Some((2, vec![Instruction::Op(OP_ENDIF)])),
+        _ => None,
+    }
+}
+
+// Branch with a single OP_ELSE: position of its OP_IF or OP_NOTIF, OP_ELSE and OP_ENDIF.
+// Inner one comes first, as it ends first.
+fn branches(script: &[Instruction]) -> Vec<(usize, usize, usize)> {
+    let mut open: Vec<(usize, Vec<usize>)> = vec![];
+    let mut found = vec![];
+    for (i, instruction) in script.iter().enumerate() {
+        match op(instruction) {
+            Some(OP_IF | OP_NOTIF) => open.push((i, vec![])),
+            Some(OP_ELSE) => {
+                if let Some((_start, elses)) = open.last_mut() {
+                    elses.push(i);
+                }
+            }
+            Some(OP_ENDIF) => {
+                if let Some((start, elses)) = open.pop() {
+                    if let [else_at] = elses[..] {
+                        found.push((start, else_at, i));
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    found
+}
+
+// Whether every block opened in the instructions is closed in them.
+fn is_balanced(instructions: &[Instruction]) -> bool {
+    let mut depth = 0;
+    for instruction in instructions {
+        match op(instruction) {
+            Some(OP_IF | OP_NOTIF) => depth += 1,
+            Some(OP_ELSE) if depth == 0 => return false,
+            Some(OP_ENDIF) if depth == 0 => return false,
+            Some(OP_ENDIF) => depth -= 1,
+            _ => (),
+        }
+    }
+    depth == 0
+}
+
+// Whether nothing runs after the instructions, but closing the blocks they're in.
+fn ends_script(rest: &[Instruction]) -> bool {
+    // Depth in the else block skipped, as the block before it ran.
+    let mut skipped = 0;
+    for instruction in rest {
+        match (skipped, op(instruction)) {
+            (0, Some(OP_ENDIF)) => (),
+            (0, Some(OP_ELSE)) => skipped = 1,
+            (0, _) => return false,
+            (1, Some(OP_ELSE)) => return false,
+            (_, Some(OP_IF | OP_NOTIF)) => skipped += 1,
+            (_, Some(OP_ENDIF)) => skipped -= 1,
+            _ => (),
+        }
+    }
+    true
+}
+
+// Rewrite of the first branch that can be shorter:
+// - Common suffix of both blocks runs after OP_ENDIF.
+// - Common pushes at the start of both blocks run before OP_IF, which takes its condition from under them.
+// - Common OP_DROP at the start of both blocks is OP_NIP before OP_IF.
+// - <x> OP_IF 1 OP_ELSE ... OP_ENDIF at the end of script leaves x itself as true, by OP_IFDUP OP_NOTIF.
+fn factor(script: &[Instruction]) -> Option<Vec<Instruction>> {
+    for (start, else_at, end) in branches(script) {
+        let first = &script[start + 1..else_at];
+        let second = &script[else_at + 1..end];
+        let rest = &script[end + 1..];
+
+        let common = first
+            .iter()
+            .rev()
+            .zip(second.iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if let Some(len) = (1..=common)
+            .rev()
+            .find(|len| is_balanced(&first[first.len() - len..]))
+        {
+            let (first, suffix) = first.split_at(first.len() - len);
+            return Some(
+                [
+                    &script[..=start],
+                    first,
+                    &script[else_at..=else_at],
+                    &second[..second.len() - len],
+                    &script[end..=end],
+                    suffix,
+                    rest,
+                ]
+                .concat(),
+            );
+        }
+
+        // OP_SWAP or OP_ROT brings the condition back on top.
+        let pushes = first
+            .iter()
+            .zip(second)
+            .take_while(|(a, b)| a == b && is_push(a))
+            .count()
+            .min(2);
+        if pushes > 0 && encode(&first[..pushes]).len() > 1 {
+            let roll = if pushes == 1 { OP_SWAP } else { OP_ROT };
+            return Some(
+                [
+                    &script[..start],
+                    &first[..pushes],
+                    &[Instruction::Op(roll)],
+                    &script[start..=start],
+                    &first[pushes..],
+                    &script[else_at..=else_at],
+                    &second[pushes..],
+                    &script[end..],
+                ]
+                .concat(),
+            );
+        }
+
+        // OP_NIP drops the same item from under the condition.
+        if first.first() == Some(&Instruction::Op(OP_DROP))
+            && second.first() == Some(&Instruction::Op(OP_DROP))
+        {
+            return Some(
+                [
+                    &script[..start],
+                    &[Instruction::Op(OP_NIP)],
+                    &script[start..=start],
+                    &first[1..],
+                    &script[else_at..=else_at],
+                    &second[1..],
+                    &script[end..],
+                ]
+                .concat(),
+            );
+        }
+
+        if script[start] == Instruction::Op(OP_IF)
+            && first == [Instruction::Op(OP_PUSHNUM_1)]
+            && ends_script(rest)
+        {
+            return Some(
+                [
+                    &script[..start],
+                    &[Instruction::Op(OP_IFDUP), Instruction::Op(OP_NOTIF)],
+                    second,
+                    &script[end..],
+                ]
+                .concat(),
+            );
+        }
+    }
+    None
+}
+
+// <bool> OP_NOT OP_NOTIF => <bool> OP_IF, and OP_NUMEQUAL OP_NOTIF => OP_NUMNOTEQUAL OP_IF, as left by swapping branch.
+fn negated_notif(window: &[Instruction]) -> Option<(usize, Vec<Instruction>)> {
+    match ops(window) {
+        Some([value, OP_NOT, OP_NOTIF]) if is_boolean(value) => {
+            return Some((3, vec![Instruction::Op(value), Instruction::Op(OP_IF)]));
+        }
+        _ => (),
+    }
+    let negated = match ops(window)? {
+        [OP_NUMEQUAL, OP_NOTIF] => OP_NUMNOTEQUAL,
+        [OP_NUMNOTEQUAL, OP_NOTIF] => OP_NUMEQUAL,
+        _ => return None,
+    };
+    Some((2, vec![Instruction::Op(negated), Instruction::Op(OP_IF)]))
+}
@@ -0,0 +1,609 @@
+#[cfg(test)]
+mod tests {
+    use bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+    use bitcoin::opcodes::all::*;
+    use bitcoin::opcodes::{Class, ClassifyContext};
+
+    use crate::peephole::*;
+
+    type Stack = Vec<Vec<u8>>;
+
+    fn script(ops: &[bitcoin::Opcode]) -> Vec<Instruction> {
+        ops.iter().map(|op| Instruction::Op(*op)).collect()
+    }
+
+    fn number(bytes: &[u8]) -> Result<i64, ()> {
+        bitcoin::script::read_scriptint(bytes).map_err(|_| ())
+    }
+
+    fn script_number(n: i64) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut abs = n.unsigned_abs();
+        while abs > 0 {
+            bytes.push((abs & 0xff) as u8);
+            abs >>= 8;
+        }
+        match bytes.last_mut() {
+            Some(last) if *last & 0x80 != 0 => bytes.push(if n < 0 { 0x80 } else { 0 }),
+            Some(last) if n < 0 => *last |= 0x80,
+            _ => (),
+        }
+        bytes
+    }
+
+    fn boolean(b: bool) -> Vec<u8> {
+        if b {
+            vec![1]
+        } else {
+            vec![]
+        }
+    }
+
+    fn truth(bytes: &[u8]) -> bool {
+        bytes
+            .iter()
+            .enumerate()
+            .any(|(i, b)| *b != 0 && !(i == bytes.len() - 1 && *b == 0x80))
+    }
+
+    // Reference interpreter of the opcodes codegen emits, with signature "ok" valid and "" failed.
+    // Returns the main and alt stack at the end, or none if the script fails.
+    fn run(script: &[Instruction], mut stack: Stack, minimal_if: bool) -> Option<(Stack, Stack)> {
+        let mut alt: Stack = vec![];
+        let mut executing: Vec<bool> = vec![];
+        for instruction in script {
+            let is_executed = executing.iter().all(|e| *e);
+            let op = match instruction {
+                Instruction::Push(data) => {
+                    if is_executed {
+                        stack.push(data.to_owned());
+                    }
+                    continue;
+                }
+                Instruction::Op(op) => *op,
+            };
+            match op {
+                OP_IF | OP_NOTIF => {
+                    if !is_executed {
+                        executing.push(false);
+                        continue;
+                    }
+                    let top = stack.pop()?;
+                    if minimal_if && !(top.is_empty() || top == [1]) {
+                        return None;
+                    }
+                    executing.push(truth(&top) == (op == OP_IF));
+                    continue;
+                }
+                OP_ELSE => {
+                    let last = executing.last_mut()?;
+                    *last = !*last;
+                    continue;
+                }
+                OP_ENDIF => {
+                    executing.pop()?;
+                    continue;
+                }
+                _ if !is_executed => continue,
+                _ => (),
+            }
+            if let Class::PushNum(n) = op.classify(ClassifyContext::Legacy) {
+                stack.push(script_number(n.into()));
+                continue;
+            }
+            let pops = match op {
+                OP_DUP | OP_IFDUP | OP_DROP | OP_TOALTSTACK | OP_VERIFY | OP_NOT | OP_0NOTEQUAL
+                | OP_SHA256 | OP_RIPEMD160 | OP_HASH160 | OP_HASH256 => 1,
+                OP_ROT => 3,
+                _ => 2,
+            };
+            if stack.len() < pops && op != OP_FROMALTSTACK {
+                return None;
+            }
+            let args = stack.split_off(stack.len() - if op == OP_FROMALTSTACK { 0 } else { pops });
+            match op {
+                OP_DUP => stack.extend([args[0].to_owned(), args[0].to_owned()]),
+                OP_DROP | OP_2DROP => (),
+                OP_SWAP => stack.extend([args[1].to_owned(), args[0].to_owned()]),
+                OP_NIP => stack.push(args[1].to_owned()),
+                OP_ROT => {
+                    stack.extend([args[1].to_owned(), args[2].to_owned(), args[0].to_owned()])
+                }
+                OP_IFDUP => {
+                    stack.push(args[0].to_owned());
+                    if truth(&args[0]) {
+                        stack.push(args[0].to_owned());
+                    }
+                }
+                OP_TOALTSTACK => alt.push(args[0].to_owned()),
+                OP_FROMALTSTACK => stack.push(alt.pop()?),
+                OP_VERIFY => {
+                    if !truth(&args[0]) {
+                        return None;
+                    }
+                }
+                OP_NOT => stack.push(boolean(number(&args[0]).ok()? == 0)),
+                OP_0NOTEQUAL => stack.push(boolean(number(&args[0]).ok()? != 0)),
+                OP_SHA256 => stack.push(sha256::Hash::hash(&args[0]).to_byte_array().to_vec()),
+                OP_RIPEMD160 => {
+                    stack.push(ripemd160::Hash::hash(&args[0]).to_byte_array().to_vec())
+                }
+                OP_HASH160 => stack.push(hash160::Hash::hash(&args[0]).to_byte_array().to_vec()),
+                OP_HASH256 => stack.push(sha256d::Hash::hash(&args[0]).to_byte_array().to_vec()),
+                OP_EQUAL | OP_EQUALVERIFY => stack.push(boolean(args[0] == args[1])),
+                OP_CHECKSIG | OP_CHECKSIGVERIFY => match args[0].as_slice() {
+                    b"ok" => stack.push(boolean(true)),
+                    b"" => stack.push(boolean(false)),
+                    _ => return None,
+                },
+                _ => {
+                    let (a, b) = (number(&args[0]).ok()?, number(&args[1]).ok()?);
+                    stack.push(match op {
+                        OP_NUMEQUAL | OP_NUMEQUALVERIFY => boolean(a == b),
+                        OP_NUMNOTEQUAL => boolean(a != b),
+                        OP_LESSTHAN => boolean(a < b),
+                        OP_BOOLAND => boolean(a != 0 && b != 0),
+                        OP_BOOLOR => boolean(a != 0 || b != 0),
+                        OP_ADD => script_number(a + b),
+                        OP_SUB => script_number(a - b),
+                        OP_MIN => script_number(a.min(b)),
+                        OP_MAX => script_number(a.max(b)),
+                        _ => panic!("{} is not supported by the reference interpreter.", op),
+                    });
+                }
+            }
+            if matches!(op, OP_EQUALVERIFY | OP_NUMEQUALVERIFY | OP_CHECKSIGVERIFY)
+                && !truth(&stack.pop()?)
+            {
+                return None;
+            }
+        }
+        Some((stack, alt))
+    }
+
+    // Every stack of 3 items from numbers, bytes too long for number, and signatures.
+    fn stacks() -> Vec<Stack> {
+        let items: Stack = vec![
+            vec![],
+            vec![1],
+            vec![2],
+            vec![0x81],
+            vec![0, 0, 0, 0, 1],
+            b"ok".to_vec(),
+            b"no".to_vec(),
+        ];
+        let mut stacks = vec![];
+        for a in &items {
+            for b in &items {
+                for c in &items {
+                    stacks.push(vec![a.to_owned(), b.to_owned(), c.to_owned()]);
+                }
+            }
+        }
+        stacks
+    }
+
+    fn assert_equivalent(before: &[Instruction], after: &[Instruction]) {
+        for stack in stacks() {
+            for minimal_if in [false, true] {
+                assert_eq!(
+                    run(before, stack.to_owned(), minimal_if),
+                    run(after, stack.to_owned(), minimal_if),
+                    "{:?} => {:?} on {:?}",
+                    before,
+                    after,
+                    stack
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_peephole_rules_preserve_behaviour() {
+        let push = |data: &[u8]| Instruction::Push(data.to_vec());
+        let rewritten = vec![
+            script(&[OP_EQUAL, OP_VERIFY]),
+            script(&[OP_NUMEQUAL, OP_VERIFY]),
+            script(&[OP_CHECKSIG, OP_VERIFY]),
+            script(&[OP_SHA256, OP_RIPEMD160]),
+            script(&[OP_SHA256, OP_SHA256]),
+            script(&[OP_NUMEQUAL, OP_NOT]),
+            script(&[OP_NUMNOTEQUAL, OP_NOT, OP_VERIFY]),
+            script(&[
+                OP_EQUAL,
+                OP_NOT,
+                OP_IF,
+                OP_PUSHNUM_2,
+                OP_ELSE,
+                OP_PUSHNUM_3,
+                OP_ENDIF,
+            ]),
+            script(&[OP_CHECKSIG, OP_NOT, OP_IF, OP_DROP, OP_ENDIF]),
+            script(&[OP_LESSTHAN, OP_NOT, OP_IF, OP_DUP, OP_ENDIF]),
+            script(&[OP_DUP, OP_DROP]),
+            script(&[OP_PUSHNUM_5, OP_DROP]),
+            vec![push(&[1, 2, 3]), Instruction::Op(OP_DROP)],
+            script(&[OP_TOALTSTACK, OP_FROMALTSTACK]),
+            script(&[OP_SWAP, OP_SWAP]),
+            script(&[OP_DROP, OP_DROP]),
+            script(&[OP_SWAP, OP_EQUAL]),
+            script(&[OP_SWAP, OP_NUMEQUAL, OP_VERIFY]),
+            script(&[OP_SWAP, OP_ADD]),
+            script(&[OP_SWAP, OP_MIN]),
+            script(&[OP_SWAP, OP_BOOLAND]),
+            script(&[OP_CHECKSIG, OP_PUSHNUM_1, OP_NUMEQUAL]),
+            script(&[OP_CHECKSIG, OP_PUSHNUM_1, OP_EQUAL]),
+            script(&[OP_IF, OP_ELSE, OP_DUP, OP_ENDIF]),
+            script(&[OP_NOTIF, OP_ELSE, OP_DUP, OP_ENDIF]),
+            script(&[OP_IF, OP_DUP, OP_ELSE, OP_ENDIF]),
+        ];
+        for before in rewritten {
+            let after = peephole(before.to_owned());
+            assert!(
+                encode(&after).len() < encode(&before).len(),
+                "{:?} is not shortened.",
+                before
+            );
+            assert_equivalent(&before, &after);
+        }
+
+        // Left as is: operands don't commute, or the value isn't 0 or 1 for OP_NOTIF.
+        for before in [
+            script(&[OP_SWAP, OP_SUB]),
+            script(&[OP_SWAP, OP_LESSTHAN]),
+            script(&[OP_ADD, OP_NOT, OP_IF, OP_DUP, OP_ENDIF]),
+            script(&[OP_ADD, OP_PUSHNUM_1, OP_NUMEQUAL]),
+        ] {
+            assert_eq!(peephole(before.to_owned()), before);
+        }
+        // OP_NOTIF would fail under MINIMALIF where OP_NOT OP_IF runs.
+        let stack = vec![vec![1], vec![2]];
+        assert!(run(
+            &script(&[OP_ADD, OP_NOT, OP_IF, OP_DUP, OP_ENDIF]),
+            stack.to_owned(),
+            true
+        )
+        .is_some());
+        assert!(run(&script(&[OP_ADD, OP_NOTIF, OP_DUP, OP_ENDIF]), stack, true).is_none());
+    }
+
+    #[test]
+    fn test_peephole_large_push() {
+        // PUSHDATA length is unsigned little-endian, so 200 bytes isn't a negative script number.
+        let before = vec![
+            Instruction::Push(vec![0xab; 200]),
+            Instruction::Push(vec![0xcd; 300]),
+            Instruction::Op(OP_EQUAL),
+            Instruction::Op(OP_VERIFY),
+        ];
+        let bytes = encode(&peephole(before));
+        assert_eq!(bytes[..2], [OP_PUSHDATA1.to_u8(), 200]);
+        assert_eq!(bytes[202..205], [OP_PUSHDATA2.to_u8(), 0x2c, 0x01]);
+        assert_eq!(bytes[505..], [OP_EQUALVERIFY.to_u8()]);
+
+        let mut decoded = vec![];
+        emit(
+            &mut decoded,
+            bitcoin::script::Builder::from(bytes.to_owned()),
+        );
+        assert_eq!(encode(&decoded), bytes);
+        assert_eq!(decoded.len(), 3);
+    }
+
+    #[test]
+    fn test_peephole_codegen() {
+        let key = "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+        let program = |target: &str, stacks: &str, body: &str| {
+            format!(
+                "pragma bithoven version 0.0.1;\npragma bithoven target {};\n{}\n{{\n{}\n}}",
+                target, stacks, body
+            )
+        };
+
+        // Multisig of one key is checksig, without the dummy item on legacy and segwit.
+        for (target, pubkey) in [("segwit", key), ("taproot", &key[2..])] {
+            let source = program(
+                target,
+                "(sig: signature)",
+                &format!("return checksig [1, (sig, \"{}\")];", pubkey),
+            );
+            let output = crate::compile_program(source).unwrap();
+            assert_eq!(
+                output.asm(),
+                format!("OP_PUSHBYTES_{} {} OP_CHECKSIG", pubkey.len() / 2, pubkey)
+            );
+        }
+
+        // Negated condition branches by OP_NOTIF.
+        let source = program(
+            "segwit",
+            "(a: number, sig: signature)\n(a: number, sig: signature)",
+            &format!(
+                "if !(a == 3) {{\nreturn checksig (sig, \"{}\");\n}} else {{\nreturn checksig (sig, \"{}\");\n}}",
+                key, key
+            ),
+        );
+        let output = crate::compile_program(source).unwrap();
+        // Same blocks are factored out whole, so the empty branch only consumes its condition.
+        assert_eq!(
+            output.asm(),
+            format!(
+                "OP_PUSHNUM_3 OP_EQUAL OP_IF OP_ENDIF OP_PUSHBYTES_33 {} OP_CHECKSIG",
+                key
+            )
+        );
+    }
+
+    #[test]
+    fn test_peephole_codegen_unfactored() {
+        // Blocks differing by key keep OP_NOTIF, but share the checksig after it.
+        let key = "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+        let source = format!(
+            "pragma bithoven version 0.0.1;\npragma bithoven target segwit;\n(a: number, sig: signature)\n(a: number, sig: signature)\n{{\nif !(a == 3) {{\nreturn checksig (sig, \"{}\");\n}} else {{\nreturn checksig (sig, \"03{}\");\n}}\n}}",
+            key,
+            &key[2..]
+        );
+        let output = crate::compile_program(source).unwrap();
+        assert_eq!(
+            output.asm(),
+            format!(
+                "OP_PUSHNUM_3 OP_EQUAL OP_NOTIF OP_PUSHBYTES_33 {} OP_ELSE OP_PUSHBYTES_33 03{} OP_ENDIF OP_CHECKSIG",
+                key,
+                &key[2..]
+            )
+        );
+    }
+
+    #[test]
+    fn test_branch_factoring() {
+        let push = |data: &[u8]| Instruction::Push(data.to_vec());
+        let op = Instruction::Op;
+        let factored = [
+            // Common suffix runs after OP_ENDIF.
+            (
+                script(&[
+                    OP_IF,
+                    OP_PUSHNUM_1,
+                    OP_PUSHNUM_2,
+                    OP_ADD,
+                    OP_ELSE,
+                    OP_PUSHNUM_3,
+                    OP_PUSHNUM_2,
+                    OP_ADD,
+                    OP_ENDIF,
+                ]),
+                script(&[
+                    OP_IF,
+                    OP_PUSHNUM_1,
+                    OP_ELSE,
+                    OP_PUSHNUM_3,
+                    OP_ENDIF,
+                    OP_PUSHNUM_2,
+                    OP_ADD,
+                ]),
+            ),
+            // Suffix taking a whole block leaves the other one alone.
+            (
+                script(&[OP_IF, OP_DUP, OP_SHA256, OP_ELSE, OP_SHA256, OP_ENDIF]),
+                script(&[OP_IF, OP_DUP, OP_ENDIF, OP_SHA256]),
+            ),
+            // Common pushes run before OP_IF, which takes its condition from under them.
+            (
+                vec![
+                    op(OP_IF),
+                    push(&[1, 2]),
+                    op(OP_ADD),
+                    op(OP_ELSE),
+                    push(&[1, 2]),
+                    op(OP_SUB),
+                    op(OP_ENDIF),
+                ],
+                vec![
+                    push(&[1, 2]),
+                    op(OP_SWAP),
+                    op(OP_IF),
+                    op(OP_ADD),
+                    op(OP_ELSE),
+                    op(OP_SUB),
+                    op(OP_ENDIF),
+                ],
+            ),
+            (
+                script(&[
+                    OP_IF,
+                    OP_PUSHNUM_7,
+                    OP_PUSHNUM_8,
+                    OP_ADD,
+                    OP_ELSE,
+                    OP_PUSHNUM_7,
+                    OP_PUSHNUM_8,
+                    OP_SUB,
+                    OP_ENDIF,
+                ]),
+                script(&[
+                    OP_PUSHNUM_7,
+                    OP_PUSHNUM_8,
+                    OP_ROT,
+                    OP_IF,
+                    OP_ADD,
+                    OP_ELSE,
+                    OP_SUB,
+                    OP_ENDIF,
+                ]),
+            ),
+            // Common drop of the item under the condition.
+            (
+                script(&[
+                    OP_IF,
+                    OP_DROP,
+                    OP_DUP,
+                    OP_ELSE,
+                    OP_DROP,
+                    OP_PUSHNUM_1,
+                    OP_ENDIF,
+                ]),
+                script(&[OP_NIP, OP_IF, OP_DUP, OP_ELSE, OP_PUSHNUM_1, OP_ENDIF]),
+            ),
+        ];
+        for (before, expected) in factored {
+            let after = optimize_script(before.to_owned());
+            assert_eq!(after, expected);
+            assert_equivalent(&before, &after);
+        }
+
+        // Suffix closing a block opened before it isn't moved.
+        let before = script(&[
+            OP_IF,
+            OP_DUP,
+            OP_IF,
+            OP_PUSHNUM_5,
+            OP_ENDIF,
+            OP_ELSE,
+            OP_DUP,
+            OP_NOTIF,
+            OP_PUSHNUM_5,
+            OP_ENDIF,
+            OP_ENDIF,
+        ]);
+        assert_eq!(optimize_script(before.to_owned()), before);
+
+        // `return true` of the first block at the end leaves the condition itself as true.
+        let before = script(&[OP_IF, OP_PUSHNUM_1, OP_ELSE, OP_DUP, OP_ADD, OP_ENDIF]);
+        let after = optimize_script(before.to_owned());
+        assert_eq!(
+            after,
+            script(&[OP_IFDUP, OP_NOTIF, OP_DUP, OP_ADD, OP_ENDIF])
+        );
+        // Script succeeds alike, with the same depth of stack, but true item may be other than 1.
+        let outcome = |script: &[Instruction], stack: Stack, minimal_if: bool| {
+            run(script, stack, minimal_if)
+                .map(|(stack, _alt)| (stack.len(), stack.last().is_some_and(|top| truth(top))))
+        };
+        for stack in stacks() {
+            for minimal_if in [false, true] {
+                assert_eq!(
+                    outcome(&before, stack.to_owned(), minimal_if),
+                    outcome(&after, stack.to_owned(), minimal_if),
+                    "{:?}",
+                    stack
+                );
+            }
+        }
+        // Not at the end, as the item left would be read by the code after it.
+        let before = script(&[OP_IF, OP_PUSHNUM_1, OP_ELSE, OP_DUP, OP_ENDIF, OP_ADD]);
+        assert_eq!(optimize_script(before.to_owned()), before);
+    }
+
+    #[test]
+    fn test_minimize() {
+        // Negated condition of OP_NOTIF branches by the condition itself, of the same length.
+        for before in [
+            script(&[OP_CHECKSIG, OP_NOT, OP_NOTIF, OP_DUP, OP_ENDIF]),
+            script(&[OP_NUMEQUAL, OP_NOTIF, OP_DUP, OP_ENDIF]),
+            script(&[OP_NUMNOTEQUAL, OP_NOTIF, OP_DUP, OP_ENDIF]),
+        ] {
+            let after = peephole(before.to_owned());
+            assert!(encode(&after).len() <= encode(&before).len());
+            assert!(!after.contains(&Instruction::Op(OP_NOTIF)));
+            assert_equivalent(&before, &after);
+        }
+
+        // Blocks swapped put `return true` first, so it ends as OP_IFDUP OP_NOTIF.
+        let before = script(&[
+            OP_CHECKSIG,
+            OP_NOT,
+            OP_IF,
+            OP_DUP,
+            OP_ADD,
+            OP_ELSE,
+            OP_PUSHNUM_1,
+            OP_ENDIF,
+        ]);
+        let standard = optimize_script(before.to_owned());
+        let after = minimize(before.to_owned(), |script| script.len());
+        assert_eq!(
+            after,
+            script(&[OP_CHECKSIG, OP_IFDUP, OP_NOTIF, OP_DUP, OP_ADD, OP_ENDIF])
+        );
+        assert!(encode(&after).len() < encode(&standard).len());
+        let outcome = |script: &[Instruction], stack: Stack| {
+            run(script, stack, true)
+                .map(|(stack, _alt)| (stack.len(), stack.last().is_some_and(|top| truth(top))))
+        };
+        for stack in stacks() {
+            assert_eq!(
+                outcome(&before, stack.to_owned()),
+                outcome(&after, stack.to_owned()),
+                "{:?}",
+                stack
+            );
+        }
+
+        // Swap not shortening the script is left out.
+        let before = script(&[OP_IF, OP_DUP, OP_ELSE, OP_DROP, OP_ENDIF]);
+        assert_eq!(
+            minimize(before.to_owned(), |script| script.len()),
+            optimize_script(before)
+        );
+    }
+
+    #[test]
+    fn test_optimize_level() {
+        let key = "0245a6b3f8eeab8e88501a9a25391318dce9bf35e24c377ee82799543606bf5212";
+        let program = |level: &str, stack: &str, body: &str| {
+            format!(
+                "pragma bithoven version 0.0.1;\npragma bithoven target segwit;\n{}\n{}\n{{\n{}\n}}",
+                level, stack, body
+            )
+        };
+
+        // O0 generates one-to-one, with operands in written order, and without folding or peephole rules.
+        let stack = "(a: number, sig_a: signature)";
+        let body = format!("verify a == 1 + 2;\nreturn checksig (sig_a, \"{}\");", key);
+        let output =
+            crate::compile_program(program("pragma bithoven optimize O0;", stack, &body)).unwrap();
+        assert!(
+            output.asm().starts_with(
+                "OP_TOALTSTACK OP_PUSHNUM_1 OP_TOALTSTACK OP_PUSHNUM_2 OP_FROMALTSTACK OP_SWAP OP_ADD OP_FROMALTSTACK OP_SWAP OP_EQUAL OP_VERIFY"
+            ),
+            "{}",
+            output.asm()
+        );
+        assert!(output.optimizations().is_empty());
+        let output = crate::compile_program(program("", stack, &body)).unwrap();
+        assert!(output.asm().starts_with("OP_PUSHNUM_3 OP_EQUALVERIFY"));
+        assert!(!output.optimizations().is_empty());
+
+        // Range form is kept as written, comparing a copy of the variable.
+        let body = format!(
+            "verify a >= 10 && a < 20;\nreturn checksig (sig_a, \"{}\");",
+            key
+        );
+        let output =
+            crate::compile_program(program("pragma bithoven optimize O0;", stack, &body)).unwrap();
+        assert!(output.asm().starts_with("OP_DUP"), "{}", output.asm());
+        assert!(!output.asm().contains("OP_WITHIN"));
+        let output = crate::compile_program(program("", stack, &body)).unwrap();
+        assert!(output.asm().contains("OP_WITHIN"));
+
+        // 1-of-3 multisig on segwit leaves one of the unsigned slots after OP_CHECKMULTISIG.
+        let stack = "(a: number, sig_a: signature, sig_b: signature, sig_c: signature)";
+        let body = format!(
+            "verify a == 3;\nreturn checksig [1, (sig_a, \"{}\"), (sig_b, \"03{}\"), (sig_c, \"0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798\")];",
+            key,
+            &key[2..]
+        );
+        let err = crate::compile_program(program("pragma bithoven optimize O1;", stack, &body))
+            .unwrap_err();
+        assert!(matches!(err.kind, crate::ast::ErrorKind::CleanStack(_)));
+        // Os takes the chain of OP_CHECKSIG instead.
+        let output =
+            crate::compile_program(program("pragma bithoven optimize Os;", stack, &body)).unwrap();
+        assert!(output
+            .asm()
+            .ends_with("OP_CHECKSIG OP_ADD OP_PUSHNUM_1 OP_NUMEQUAL"));
+        assert!(output.optimizations().iter().any(|o| o
+            .message
+            .starts_with("Encoded multisig by OP_CHECKSIG of each key")));
+    }
+}
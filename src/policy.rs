@@ -0,0 +1,457 @@
+use crate::ast::*;
+
+// Node of Bitcoin policy language, e.g. `or(9@pk(A), and(pk(B), older(1000)))`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PolicyNode {
+    Key(String),
+    Hash(UnaryCryptoOp, String),
+    Older(u32),
+    After(u32),
+    And(Vec<PolicyNode>),
+    /// Branches with their probability weight, 1 by default.
+    Or(Vec<(u32, PolicyNode)>),
+    Thresh(usize, Vec<PolicyNode>),
+}
+
+// Requirement of a spending path, which is one statement of its block.
+#[derive(Clone, Debug, PartialEq)]
+enum Atom {
+    Older(u32),
+    After(u32),
+    Hash(UnaryCryptoOp, String),
+    Sig(String),
+    Multi(usize, Vec<String>),
+}
+
+/// Parse the policy, and generate Bithoven source with an if/else branch for each spending path.
+/// Branch of higher probability comes first, and named keys and digests are left as constants.
+pub fn policy_source(policy: &str, target: &Target) -> Result<String, CompileError> {
+    let mut parser = PolicyParser {
+        source: policy,
+        pos: 0,
+    };
+    let node = parser.parse_node()?;
+    parser.skip_whitespace();
+    if parser.pos != policy.len() {
+        return Err(parser.error("Unexpected token after policy."));
+    }
+    Ok(generate(policy, &node, target))
+}
+
+struct PolicyParser<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl PolicyParser<'_> {
+    fn error(&self, message: &str) -> CompileError {
+        CompileError {
+            loc: Location {
+                start: self.pos,
+                end: self.pos,
+                line: 1,
+                column: self.pos + 1,
+                file: String::new(),
+            },
+            kind: ErrorKind::ParseError(format!(
+                "Invalid Policy: {} at {:?}",
+                message,
+                &self.source[self.pos..]
+            )),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.source[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn expect(&mut self, token: char) -> Result<(), CompileError> {
+        self.skip_whitespace();
+        if self.source[self.pos..].starts_with(token) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected {:?}.", token)))
+        }
+    }
+
+    fn word(&mut self) -> Result<&str, CompileError> {
+        self.skip_whitespace();
+        let rest = &self.source[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("Expected name or number."));
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, CompileError> {
+        let start = self.pos;
+        let word = self.word()?.to_string();
+        word.parse().map_err(|_| {
+            self.pos = start;
+            self.error(&format!("Expected number but: {:?}.", word))
+        })
+    }
+
+    fn parse_node(&mut self) -> Result<PolicyNode, CompileError> {
+        let start = self.pos;
+        let name = self.word()?.to_string();
+        self.expect('(')?;
+        let node = match name.as_str() {
+            "pk" => PolicyNode::Key(self.word()?.to_string()),
+            "older" => {
+                let sequence: u32 = self.number()?;
+                // BIP 68 encodes 16 bits of blocks, or of 512 seconds with the type flag.
+                if sequence & !(0xffff | 1 << 22) != 0 {
+                    self.pos = start;
+                    return Err(self.error(
+                        "Relative locktime must be at most 65535 blocks, or 65535 units of 512 seconds with the type flag (1 << 22).",
+                    ));
+                }
+                PolicyNode::Older(sequence)
+            }
+            "after" => PolicyNode::After(self.number()?),
+            "sha256" | "hash256" | "ripemd160" | "hash160" => {
+                let op = match name.as_str() {
+                    "sha256" => UnaryCryptoOp::Sha256,
+                    "hash256" => UnaryCryptoOp::Hash256,
+                    "ripemd160" => UnaryCryptoOp::Ripemd160,
+                    _ => UnaryCryptoOp::Hash160,
+                };
+                PolicyNode::Hash(op, self.word()?.to_string())
+            }
+            "and" => PolicyNode::And(self.parse_list(|parser| parser.parse_node())?),
+            "or" => PolicyNode::Or(self.parse_list(|parser| parser.parse_weighted())?),
+            "thresh" => {
+                let k: usize = self.number()?;
+                self.expect(',')?;
+                let subs = self.parse_list(|parser| parser.parse_node())?;
+                if k == 0 || k > subs.len() {
+                    self.pos = start;
+                    return Err(self.error("Threshold must be from 1 to the number of policies."));
+                }
+                PolicyNode::Thresh(k, subs)
+            }
+            _ => {
+                self.pos = start;
+                return Err(self.error(&format!("Unknown policy {:?}.", name)));
+            }
+        };
+        self.expect(')')?;
+        Ok(node)
+    }
+
+    // Optional weight of or branch, e.g. `9@pk(A)`.
+    fn parse_weighted(&mut self) -> Result<(u32, PolicyNode), CompileError> {
+        self.skip_whitespace();
+        let rest = &self.source[self.pos..];
+        match rest.find('@') {
+            Some(at) if rest[..at].trim().chars().all(|c| c.is_ascii_digit()) => {
+                let start = self.pos;
+                let weight = self.number()?;
+                if weight == 0 {
+                    self.pos = start;
+                    return Err(self.error("Weight of branch must be positive."));
+                }
+                self.expect('@')?;
+                Ok((weight, self.parse_node()?))
+            }
+            _ => Ok((1, self.parse_node()?)),
+        }
+    }
+
+    // Comma separated, until the closing parenthesis.
+    fn parse_list<T>(
+        &mut self,
+        mut parse: impl FnMut(&mut Self) -> Result<T, CompileError>,
+    ) -> Result<Vec<T>, CompileError> {
+        let mut items = vec![parse(self)?];
+        loop {
+            self.skip_whitespace();
+            if !self.source[self.pos..].starts_with(',') {
+                return Ok(items);
+            }
+            self.pos += 1;
+            items.push(parse(self)?);
+        }
+    }
+}
+
+// Every spending path with its probability, each as the requirements all to be met.
+fn alternatives(node: &PolicyNode, target: &Target) -> Vec<(f64, Vec<Atom>)> {
+    match node {
+        PolicyNode::Key(key) => vec![(1.0, vec![Atom::Sig(key.to_owned())])],
+        PolicyNode::Hash(op, digest) => {
+            vec![(1.0, vec![Atom::Hash(op.to_owned(), digest.to_owned())])]
+        }
+        PolicyNode::Older(n) => vec![(1.0, vec![Atom::Older(*n)])],
+        PolicyNode::After(n) => vec![(1.0, vec![Atom::After(*n)])],
+        PolicyNode::And(subs) => subs.iter().fold(vec![(1.0, vec![])], |acc, sub| {
+            let sub_alternatives = alternatives(sub, target);
+            acc.iter()
+                .flat_map(|(p, atoms)| {
+                    sub_alternatives.iter().map(move |(q, sub_atoms)| {
+                        let mut joined = atoms.clone();
+                        for atom in sub_atoms {
+                            if !joined.contains(atom) {
+                                joined.push(atom.to_owned());
+                            }
+                        }
+                        (p * q, joined)
+                    })
+                })
+                .collect()
+        }),
+        PolicyNode::Or(branches) => {
+            let total: u64 = branches.iter().map(|(weight, _)| *weight as u64).sum();
+            branches
+                .iter()
+                .flat_map(|(weight, sub)| {
+                    alternatives(sub, target)
+                        .into_iter()
+                        .map(move |(p, atoms)| (p * *weight as f64 / total as f64, atoms))
+                })
+                .collect()
+        }
+        // Threshold of keys is a multisig, otherwise any k of the policies.
+        // OP_CHECKMULTISIG takes only k signatures, so k of n keys is a path for each k keys out of tapscript.
+        PolicyNode::Thresh(k, subs) => {
+            let keys: Option<Vec<String>> = subs
+                .iter()
+                .map(|sub| match sub {
+                    PolicyNode::Key(key) => Some(key.to_owned()),
+                    _ => None,
+                })
+                .collect();
+            match keys {
+                Some(keys) if keys.len() == 1 => vec![(1.0, vec![Atom::Sig(keys[0].to_owned())])],
+                Some(keys) if *k == keys.len() || *target == Target::Taproot => {
+                    vec![(1.0, vec![Atom::Multi(*k, keys)])]
+                }
+                _ => {
+                    let subsets = combinations(subs.len(), *k);
+                    let branches = subsets
+                        .iter()
+                        .map(|subset| {
+                            let subs: Vec<PolicyNode> =
+                                subset.iter().map(|i| subs[*i].to_owned()).collect();
+                            match subs.iter().all(|sub| matches!(sub, PolicyNode::Key(_))) {
+                                true => (1, PolicyNode::Thresh(*k, subs)),
+                                false => (1, PolicyNode::And(subs)),
+                            }
+                        })
+                        .collect();
+                    alternatives(&PolicyNode::Or(branches), target)
+                }
+            }
+        }
+    }
+}
+
+fn combinations(n: usize, k: usize) -> Vec<Vec<usize>> {
+    if k == 0 {
+        return vec![vec![]];
+    }
+    (k - 1..n)
+        .flat_map(|last| {
+            combinations(last, k - 1)
+                .into_iter()
+                .map(move |mut subset| {
+                    subset.push(last);
+                    subset
+                })
+        })
+        .collect()
+}
+
+fn is_hex(s: &str) -> bool {
+    !s.is_empty() && s.len().is_multiple_of(2) && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Literal as is, or constant named after the policy to be filled in.
+fn literal(value: &str) -> String {
+    if is_hex(value) {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn generate(policy: &str, node: &PolicyNode, target: &Target) -> String {
+    let mut paths = alternatives(node, target);
+
+    // Witness items are named in the order of the policy.
+    let mut keys: Vec<String> = vec![];
+    let mut digests: Vec<String> = vec![];
+    let mut digest_types: Vec<String> = vec![];
+    for (_p, atoms) in &paths {
+        for atom in atoms {
+            match atom {
+                Atom::Sig(key) if !keys.contains(key) => keys.push(key.to_owned()),
+                Atom::Multi(_, multi) => {
+                    for key in multi {
+                        if !keys.contains(key) {
+                            keys.push(key.to_owned());
+                        }
+                    }
+                }
+                Atom::Hash(op, digest) if !digests.contains(digest) => {
+                    digests.push(digest.to_owned());
+                    digest_types.push(match op.result_type() {
+                        Type::Bytes(n) => format!("bytes{}", n),
+                        _ => "string".to_string(),
+                    });
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // Stable, so that paths of the same probability keep the order of policy.
+    paths.sort_by(|(p, _), (q, _)| q.total_cmp(p));
+
+    let sig_name = |key: &String| match is_hex(key) {
+        true => format!(
+            "sig_{}",
+            keys.iter().position(|k| k == key).unwrap_or_default()
+        ),
+        false => format!("sig_{}", key.to_lowercase()),
+    };
+    let preimage_name = |digest: &String| {
+        format!(
+            "preimage_{}",
+            digests.iter().position(|d| d == digest).unwrap_or_default()
+        )
+    };
+
+    let target_name = match target {
+        Target::Legacy => "legacy",
+        Target::Segwit => "segwit",
+        Target::Taproot => "taproot",
+    };
+    let mut source = format!(
+        "pragma bithoven version 0.0.1;\npragma bithoven target {};\n\n// Generated from policy: {}\n",
+        target_name, policy
+    );
+
+    // Named keys and digests are left for the engineer to fill in.
+    let constants: Vec<String> = keys
+        .iter()
+        .map(|key| (key, "pubkey"))
+        .chain(digests.iter().zip(digest_types.iter().map(String::as_str)))
+        .filter(|(value, _ty)| !is_hex(value))
+        .map(|(name, ty)| format!("const {}: {} = \"<{}>\";\n", name, ty, name))
+        .collect();
+    if !constants.is_empty() {
+        source += "\n";
+        source += &constants.concat();
+    }
+
+    // Each path but the last is selected by a bool item, as in else if chain.
+    let selectors = paths.len() - 1;
+    source += "\n";
+    for (i, (_p, atoms)) in paths.iter().enumerate() {
+        let mut items: Vec<String> = (0..=i.min(selectors.saturating_sub(1)))
+            .take(selectors)
+            .map(|j| format!("branch{}: bool", j))
+            .collect();
+        for atom in ordered(atoms) {
+            match atom {
+                Atom::Hash(_, digest) => items.push(format!("{}: bytes32", preimage_name(digest))),
+                Atom::Sig(key) => items.push(format!("{}: signature", sig_name(key))),
+                Atom::Multi(_, multi) => items.extend(
+                    multi
+                        .iter()
+                        .map(|key| format!("{}: signature", sig_name(key))),
+                ),
+                Atom::Older(_) | Atom::After(_) => (),
+            }
+        }
+        source += &format!("({})\n", items.join(", "));
+    }
+
+    source += "{\n";
+    for (i, (p, atoms)) in paths.iter().enumerate() {
+        let indent = if selectors > 0 { "        " } else { "    " };
+        let header = match (i, i == selectors) {
+            (_, true) if selectors == 0 => String::new(),
+            (0, _) => "    if branch0 {\n".to_string(),
+            (_, true) => "    } else {\n".to_string(),
+            _ => format!("    }} else if branch{} {{\n", i),
+        };
+        source += &header;
+        if selectors > 0 {
+            source += &format!("{}// Probability {:.2}\n", indent, p);
+        }
+        let atoms = ordered(atoms);
+        for (k, atom) in atoms.iter().enumerate() {
+            let is_last = k == atoms.len() - 1;
+            let keyword = if is_last { "return" } else { "verify" };
+            let line = match atom {
+                Atom::Older(n) => older_source(*n),
+                Atom::After(n) => format!("after {};", n),
+                Atom::Hash(op, digest) => format!(
+                    "{} {} {} == {};",
+                    keyword,
+                    crate::source::crypto_source(op),
+                    preimage_name(digest),
+                    literal(digest)
+                ),
+                Atom::Sig(key) => format!(
+                    "{} checksig ({}, {});",
+                    keyword,
+                    sig_name(key),
+                    literal(key)
+                ),
+                Atom::Multi(m, multi) => format!(
+                    "{} checksig [{}, {}];",
+                    keyword,
+                    m,
+                    multi
+                        .iter()
+                        .map(|key| format!("({}, {})", sig_name(key), literal(key)))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            };
+            source += &format!("{}{}\n", indent, line);
+        }
+        // Path of timelocks only has nothing to return.
+        if matches!(atoms.last(), Some(Atom::Older(_) | Atom::After(_))) {
+            source += &format!("{}return true;\n", indent);
+        }
+    }
+    if selectors > 0 {
+        source += "    }\n";
+    }
+    source += "}\n";
+    source
+}
+
+// Timelocks first, then hashlocks and signatures, in the order witness items are consumed.
+fn ordered(atoms: &[Atom]) -> Vec<&Atom> {
+    let rank = |atom: &Atom| match atom {
+        Atom::Older(_) | Atom::After(_) => 0,
+        Atom::Hash(..) => 1,
+        Atom::Sig(_) | Atom::Multi(..) => 2,
+    };
+    let mut ordered: Vec<&Atom> = atoms.iter().collect();
+    ordered.sort_by_key(|atom| rank(atom));
+    ordered
+}
+
+// nSequence with the type flag of BIP 68 is in 512 seconds.
+fn older_source(sequence: u32) -> String {
+    let is_time = sequence & (1 << 22) != 0;
+    let value = sequence & 0xffff;
+    if is_time {
+        format!("older {} seconds;", value * 512)
+    } else {
+        format!("older {};", value)
+    }
+}
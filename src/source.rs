@@ -20,6 +20,7 @@ impl Locatable for Statement {
             // For struct variants with a named `loc` field
             Statement::IfStatement { loc, .. } => loc,
             Statement::LocktimeStatement { loc, .. } => loc,
+            Statement::MatchStatement { loc, .. } => loc,
 
             // For tuple-struct variants, access by index
             Statement::VerifyStatement(loc, ..) => loc,
@@ -31,6 +32,7 @@ impl Locatable for Statement {
             // For struct variants with a named `loc` field
             Statement::IfStatement { loc, .. } => loc,
             Statement::LocktimeStatement { loc, .. } => loc,
+            Statement::MatchStatement { loc, .. } => loc,
 
             // For tuple-struct variants, access by index
             Statement::VerifyStatement(loc, ..) => loc,
@@ -53,6 +55,9 @@ impl Locatable for Expression {
             Expression::UnaryCryptoExpression { loc, .. } => loc,
             Expression::CheckSigExpression { loc, .. } => loc,
             Expression::ByteExpression { loc, .. } => loc,
+            Expression::WithinExpression { loc, .. } => loc,
+            Expression::CallExpression { loc, .. } => loc,
+            Expression::InlineExpression { loc, .. } => loc,
         }
     }
     fn loc(self) -> Location {
@@ -68,10 +73,40 @@ impl Locatable for Expression {
             Expression::UnaryCryptoExpression { loc, .. } => loc,
             Expression::CheckSigExpression { loc, .. } => loc,
             Expression::ByteExpression { loc, .. } => loc,
+            Expression::WithinExpression { loc, .. } => loc,
+            Expression::CallExpression { loc, .. } => loc,
+            Expression::InlineExpression { loc, .. } => loc,
         }
     }
 }
 
+impl Locatable for Function {
+    fn loc_mut(&mut self) -> &mut Location {
+        &mut self.loc
+    }
+    fn loc(self) -> Location {
+        self.loc
+    }
+}
+
+impl Locatable for Constant {
+    fn loc_mut(&mut self) -> &mut Location {
+        &mut self.loc
+    }
+    fn loc(self) -> Location {
+        self.loc
+    }
+}
+
+impl Locatable for Import {
+    fn loc_mut(&mut self) -> &mut Location {
+        &mut self.loc
+    }
+    fn loc(self) -> Location {
+        self.loc
+    }
+}
+
 impl Locatable for Factor {
     fn loc_mut(&mut self) -> &mut Location {
         match self {
@@ -108,42 +143,84 @@ pub fn get_line_and_column(line_index: &[usize], byte_offset: usize) -> (usize,
     (line, column)
 }
 
-pub fn set_stack_location(stack_vec: &mut Vec<Vec<StackParam>>, line_index: &[usize]) {
+/// Source file being located, with its name and line index.
+pub struct SourceFile {
+    pub name: String,
+    pub line_index: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: &str, source: &str) -> Self {
+        SourceFile {
+            name: name.to_string(),
+            line_index: build_line_index(source),
+        }
+    }
+
+    /// Populates the file, line and column of location.
+    pub fn locate(&self, loc: &mut Location) {
+        let (line, column) = get_line_and_column(&self.line_index, loc.start);
+        loc.line = line;
+        loc.column = column;
+        loc.file = self.name.to_owned();
+    }
+}
+
+pub fn set_stack_location(stack_vec: &mut Vec<Vec<StackParam>>, source_file: &SourceFile) {
     for stack in stack_vec {
         for item in stack {
             // Get a mutable reference to the location using our new trait method.
             let loc = item.loc_mut();
 
             // Perform the update logic once.
-            let (line, column) = get_line_and_column(line_index, loc.start);
-            loc.line = line;
-            loc.column = column;
+            source_file.locate(loc);
         }
     }
 }
 
-pub fn set_ast_location(ast: &mut Vec<Statement>, line_index: &[usize]) {
+pub fn set_function_location(functions: &mut Vec<Function>, source_file: &SourceFile) {
+    for function in functions {
+        source_file.locate(function.loc_mut());
+        for param in function.params.iter_mut() {
+            source_file.locate(param.loc_mut());
+        }
+        set_expr_location(&mut function.body, source_file);
+    }
+}
+
+pub fn set_constant_location(constants: &mut Vec<Constant>, source_file: &SourceFile) {
+    for constant in constants {
+        source_file.locate(constant.loc_mut());
+        set_expr_location(&mut constant.value, source_file);
+    }
+}
+
+pub fn set_import_location(imports: &mut Vec<Import>, source_file: &SourceFile) {
+    for import in imports {
+        source_file.locate(import.loc_mut());
+    }
+}
+
+pub fn set_ast_location(ast: &mut Vec<Statement>, source_file: &SourceFile) {
     for stmt in ast {
-        set_stmt_location(stmt, line_index);
+        set_stmt_location(stmt, source_file);
     }
 }
 
 /// Recursively walks the AST and populates the line and column numbers.
-pub fn set_stmt_location(stmt: &mut Statement, line_index: &[usize]) {
+pub fn set_stmt_location(stmt: &mut Statement, source_file: &SourceFile) {
     // Get a mutable reference to the location using our new trait method.
     let loc = stmt.loc_mut();
 
     // Perform the update logic once.
-    let (line, column) = get_line_and_column(line_index, loc.start);
-    loc.line = line;
-    loc.column = column;
+    source_file.locate(loc);
 
     match stmt {
         Statement::VerifyStatement(_loc, expr) => {
-            set_expr_location(expr, line_index);
+            set_expr_location(expr, source_file);
         }
         Statement::ExpressionStatement(_loc, expr) => {
-            set_expr_location(expr, line_index);
+            set_expr_location(expr, source_file);
         }
         Statement::IfStatement {
             loc: _,
@@ -151,14 +228,34 @@ pub fn set_stmt_location(stmt: &mut Statement, line_index: &[usize]) {
             if_block,
             else_block,
         } => {
-            set_expr_location(expr, line_index);
+            set_expr_location(expr, source_file);
             // recursive
             for if_stmt in if_block {
-                set_stmt_location(if_stmt, line_index);
+                set_stmt_location(if_stmt, source_file);
             }
             if let Some(else_b) = else_block.as_mut() {
                 for else_stmt in else_b {
-                    set_stmt_location(else_stmt, line_index);
+                    set_stmt_location(else_stmt, source_file);
+                }
+            }
+        }
+        Statement::MatchStatement {
+            loc: _,
+            selector,
+            arms,
+            default,
+        } => {
+            set_expr_location(selector, source_file);
+            // recursive
+            for arm in arms {
+                source_file.locate(&mut arm.loc);
+                for arm_stmt in &mut arm.block {
+                    set_stmt_location(arm_stmt, source_file);
+                }
+            }
+            if let Some(default_b) = default.as_mut() {
+                for default_stmt in default_b {
+                    set_stmt_location(default_stmt, source_file);
                 }
             }
         }
@@ -167,14 +264,12 @@ pub fn set_stmt_location(stmt: &mut Statement, line_index: &[usize]) {
 }
 
 /// Recursively walks the AST and populates the line and column numbers.
-pub fn set_expr_location(expr: &mut Expression, line_index: &[usize]) {
+pub fn set_expr_location(expr: &mut Expression, source_file: &SourceFile) {
     // Get a mutable reference to the location using our new trait method.
     let loc = expr.loc_mut();
 
     // Perform the update logic once.
-    let (line, column) = get_line_and_column(line_index, loc.start);
-    loc.line = line;
-    loc.column = column;
+    source_file.locate(loc);
 
     // Set factor for expression which does have factor as child node
     match expr {
@@ -183,21 +278,21 @@ pub fn set_expr_location(expr: &mut Expression, line_index: &[usize]) {
             operand,
             op: _,
         } => {
-            set_factor_location(&mut *operand, line_index);
+            set_factor_location(&mut *operand, source_file);
         }
         Expression::UnaryCryptoExpression {
             loc: _,
             operand,
             op,
-        } => set_expr_location(operand, line_index),
+        } => set_expr_location(operand, source_file),
         Expression::LogicalExpression {
             loc: _,
             lhs,
             op,
             rhs,
         } => {
-            set_expr_location(lhs, line_index);
-            set_expr_location(rhs, line_index);
+            set_expr_location(lhs, source_file);
+            set_expr_location(rhs, source_file);
         }
         Expression::CompareExpression {
             loc: _,
@@ -205,49 +300,62 @@ pub fn set_expr_location(expr: &mut Expression, line_index: &[usize]) {
             op,
             rhs,
         } => {
-            set_expr_location(lhs, line_index);
-            set_expr_location(rhs, line_index);
+            set_expr_location(lhs, source_file);
+            set_expr_location(rhs, source_file);
         }
         Expression::UnaryMathExpression {
             loc: _,
             operand,
             op,
-        } => set_expr_location(operand, line_index),
+        } => set_expr_location(operand, source_file),
         Expression::BinaryMathExpression {
             loc: _,
             lhs,
             op,
             rhs,
         } => {
-            set_expr_location(lhs, line_index);
-            set_expr_location(rhs, line_index);
+            set_expr_location(lhs, source_file);
+            set_expr_location(rhs, source_file);
         }
         Expression::ByteExpression {
             loc: _,
             operand,
             op: _,
-        } => set_expr_location(operand, line_index),
+        } => set_expr_location(operand, source_file),
+        Expression::WithinExpression {
+            loc: _,
+            operand,
+            min,
+            max,
+        } => {
+            set_expr_location(operand, source_file);
+            set_expr_location(min, source_file);
+            set_expr_location(max, source_file);
+        }
+        Expression::CallExpression { args, .. } => {
+            for arg in args {
+                set_expr_location(arg, source_file);
+            }
+        }
         _ => (),
     }
 }
 
 /// Recursively walks the AST and populates the line and column numbers.
-pub fn set_factor_location(factor: &mut Factor, line_index: &[usize]) {
+pub fn set_factor_location(factor: &mut Factor, source_file: &SourceFile) {
     // Get a mutable reference to the location using our new trait method.
     let loc = factor.loc_mut();
 
     // Perform the update logic once.
-    let (line, column) = get_line_and_column(line_index, loc.start);
-    loc.line = line;
-    loc.column = column;
+    source_file.locate(loc);
     match factor {
         Factor::SingleSigFactor {
             loc: _,
             sig,
             pubkey,
         } => {
-            set_expr_location(sig, line_index);
-            set_expr_location(pubkey, line_index);
+            set_expr_location(sig, source_file);
+            set_expr_location(pubkey, source_file);
         }
         Factor::MultiSigFactor { loc: _, m: _, n } => {
             for factor in n {
@@ -257,8 +365,8 @@ pub fn set_factor_location(factor: &mut Factor, line_index: &[usize]) {
                         sig,
                         pubkey,
                     } => {
-                        set_expr_location(sig, line_index);
-                        set_expr_location(pubkey, line_index);
+                        set_expr_location(sig, source_file);
+                        set_expr_location(pubkey, source_file);
                     }
                     _ => continue,
                 }
@@ -280,3 +388,148 @@ pub fn strip_comments(source: &str) -> String {
     }
     COMMENT_REGEX.replace_all(source, "").to_string()
 }
+
+// 2. Write expression back in Bithoven syntax, for reports.
+
+/// Simple statement as written in source, without block of if and match.
+pub fn statement_source(stmt: &Statement) -> String {
+    match stmt {
+        Statement::IfStatement { condition_expr, .. } => {
+            format!("if {}", expression_source(condition_expr))
+        }
+        Statement::MatchStatement { selector, .. } => {
+            format!("match {}", expression_source(selector))
+        }
+        Statement::LocktimeStatement {
+            operand, op, unit, ..
+        } => match (op, unit) {
+            (LocktimeOp::Csv, LocktimeUnit::Second) => format!("older {} seconds;", operand),
+            (LocktimeOp::Csv, _) => format!("older {};", operand),
+            (LocktimeOp::Cltv, LocktimeUnit::Block) => format!("after height {};", operand),
+            (LocktimeOp::Cltv, _) => format!("after {};", operand),
+        },
+        Statement::VerifyStatement(_loc, expr) => format!("verify {};", expression_source(expr)),
+        Statement::ExpressionStatement(_loc, expr) => {
+            format!("return {};", expression_source(expr))
+        }
+    }
+}
+
+/// Expression as written in source, with compound operands parenthesized.
+pub fn expression_source(expr: &Expression) -> String {
+    match expr {
+        Expression::Variable(_, id) => id.0.to_owned(),
+        Expression::NumberLiteral(_, n) => n.to_string(),
+        Expression::BooleanLiteral(_, b) => b.to_string(),
+        Expression::StringLiteral(_, s) => format!("{:?}", s),
+        Expression::LogicalExpression { lhs, op, rhs, .. } => {
+            let op = match op {
+                BinaryLogicalOp::BoolOr => "||",
+                BinaryLogicalOp::BoolAnd => "&&",
+            };
+            format!("{} {} {}", operand_source(lhs), op, operand_source(rhs))
+        }
+        Expression::CompareExpression { lhs, op, rhs, .. } => {
+            let op = match op {
+                BinaryCompareOp::Equal | BinaryCompareOp::NumEqual => "==",
+                BinaryCompareOp::NotEqual | BinaryCompareOp::NumNotEqual => "!=",
+                BinaryCompareOp::Greater => ">",
+                BinaryCompareOp::GreaterOrEqual => ">=",
+                BinaryCompareOp::Less => "<",
+                BinaryCompareOp::LessOrEqual => "<=",
+            };
+            format!("{} {} {}", operand_source(lhs), op, operand_source(rhs))
+        }
+        Expression::UnaryMathExpression { operand, op, .. } => {
+            let op = match op {
+                UnaryMathOp::Add => "++ ",
+                UnaryMathOp::Sub => "-- ",
+                UnaryMathOp::Negate => "negate ",
+                UnaryMathOp::Abs => "abs ",
+                UnaryMathOp::Not => "!",
+            };
+            format!("{}{}", op, operand_source(operand))
+        }
+        Expression::BinaryMathExpression { lhs, op, rhs, .. } => match op {
+            BinaryMathOp::Add => format!("{} + {}", operand_source(lhs), operand_source(rhs)),
+            BinaryMathOp::Sub => format!("{} - {}", operand_source(lhs), operand_source(rhs)),
+            BinaryMathOp::Max => format!(
+                "max({}, {})",
+                expression_source(lhs),
+                expression_source(rhs)
+            ),
+            BinaryMathOp::Min => format!(
+                "min({}, {})",
+                expression_source(lhs),
+                expression_source(rhs)
+            ),
+        },
+        Expression::UnaryCryptoExpression { operand, op, .. } => {
+            format!("{} {}", crypto_source(op), operand_source(operand))
+        }
+        Expression::CheckSigExpression { operand, .. } => {
+            format!("checksig{}", factor_source(operand))
+        }
+        Expression::ByteExpression { operand, .. } => format!("len {}", operand_source(operand)),
+        Expression::WithinExpression {
+            operand, min, max, ..
+        } => format!(
+            "{} in {}..{}",
+            operand_source(operand),
+            operand_source(min),
+            operand_source(max)
+        ),
+        Expression::CallExpression {
+            identifier, args, ..
+        }
+        | Expression::InlineExpression {
+            identifier, args, ..
+        } => format!(
+            "{}({})",
+            identifier.0,
+            args.iter()
+                .map(expression_source)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+pub fn crypto_source(op: &UnaryCryptoOp) -> &'static str {
+    match op {
+        UnaryCryptoOp::Sha256 => "sha256",
+        UnaryCryptoOp::Ripemd160 => "ripemd160",
+        UnaryCryptoOp::Hash160 => "hash160",
+        UnaryCryptoOp::Hash256 => "hash256",
+        UnaryCryptoOp::Sha1 => "sha1",
+    }
+}
+
+fn operand_source(expr: &Expression) -> String {
+    match expr {
+        Expression::LogicalExpression { .. }
+        | Expression::CompareExpression { .. }
+        | Expression::UnaryMathExpression { .. }
+        | Expression::WithinExpression { .. }
+        | Expression::BinaryMathExpression {
+            op: BinaryMathOp::Add | BinaryMathOp::Sub,
+            ..
+        } => format!("({})", expression_source(expr)),
+        _ => expression_source(expr),
+    }
+}
+
+fn factor_source(factor: &Factor) -> String {
+    match factor {
+        Factor::SingleSigFactor { sig, pubkey, .. } => format!(
+            "({}, {})",
+            expression_source(sig),
+            expression_source(pubkey)
+        ),
+        Factor::MultiSigFactor { m, n, .. } => format!(
+            "[{}, {}]",
+            m,
+            n.iter().map(factor_source).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
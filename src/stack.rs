@@ -0,0 +1,314 @@
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::{Class, ClassifyContext};
+use bitcoin::script::Instruction;
+
+use crate::analyze::*;
+use crate::ast::*;
+use crate::cfg::witness_size;
+use crate::compile::Encoding;
+use crate::path::*;
+use crate::source::*;
+
+// Stack of each path is simulated through the compiled script, by its depth.
+// Number pushed by the script is kept, as OP_CHECKMULTISIG pops by the counts on the stack.
+// Segwit and tapscript require exactly one element left on the stack(CLEANSTACK).
+pub fn check_clean_stack(
+    script: &[u8],
+    paths: &[Path],
+    input: &[Vec<StackParam>],
+    names: &[String],
+    target: &Target,
+    encoding: &Encoding,
+) -> Result<(), CompileError> {
+    for (branch, (path, stack)) in paths.iter().zip(input).enumerate() {
+        let label = path_label(names, branch);
+        let witness = stack.len() + multisig_dummies(path, target, encoding).len();
+        let depth = simulate(script, witness, &path.conditions()).map_err(|msg| {
+            bind_error(
+                CompileError {
+                    loc: path.end(),
+                    kind: ErrorKind::StackMismatch(msg),
+                },
+                &label,
+            )
+        })?;
+        let is_clean = match target {
+            Target::Legacy => depth > 0,
+            _ => depth == 1,
+        };
+        if !is_clean {
+            return Err(bind_error(
+                CompileError {
+                    loc: path.end(),
+                    kind: ErrorKind::CleanStack(format!(
+                        "Spending path must leave exactly one element on the stack but: {} left.{}",
+                        depth,
+                        multisig_hint(path, target, encoding)
+                    )),
+                },
+                &label,
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Multisig checked by OP_CHECKMULTISIG on the path, in order.
+pub fn checkmultisigs<'a>(path: &'a Path, target: &Target, encoding: &Encoding) -> Vec<&'a Factor> {
+    if *target == Target::Taproot {
+        return vec![];
+    }
+    path_multisigs(path)
+        .into_iter()
+        .filter(|factor| !encoding.is_checksig_chain(factor, target))
+        .collect()
+}
+
+// OP_CHECKMULTISIG pops one more item than its signatures, which the witness supplies empty.
+// Signature declared for an unsigned key of m-of-n is the empty item, but n-of-n has none left.
+// Input stack doesn't declare it then, so it's counted for each such multisig, by its location.
+pub fn multisig_dummies(path: &Path, target: &Target, encoding: &Encoding) -> Vec<Location> {
+    checkmultisigs(path, target, encoding)
+        .into_iter()
+        .filter(|factor| matches!(factor, Factor::MultiSigFactor { m, n, .. } if *m as usize == n.len()))
+        .map(|factor| factor.to_owned().loc())
+        .collect()
+}
+
+// Signature is declared for each key of multisig, but OP_CHECKMULTISIG takes m of them and a dummy.
+fn multisig_hint(path: &Path, target: &Target, encoding: &Encoding) -> String {
+    checkmultisigs(path, target, encoding)
+        .into_iter()
+        .find_map(|factor| match factor {
+            Factor::MultiSigFactor { loc, m, n } if (*m as usize) + 1 < n.len() => Some(format!(
+                " OP_CHECKMULTISIG of {}-of-{} multisig at {} takes {} signatures plus a dummy item, {} of the {} declared for its keys: compile with -Os to check each key by OP_CHECKSIG instead.",
+                m,
+                n.len(),
+                loc,
+                m,
+                m + 1,
+                n.len()
+            )),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+// Weight of the script and the largest witness of its paths, as spent.
+// Only m of the n keys of multisig sign, so the other signature slots are empty pushes.
+// Legacy script and its witness are in scriptSig, which weighs 4 units a byte.
+pub fn witness_weight(
+    script: &[u8],
+    paths: &[Path],
+    input: &[Vec<StackParam>],
+    target: &Target,
+    encoding: &Encoding,
+) -> usize {
+    let signature = if *target == Target::Taproot { 64 } else { 72 };
+    let witness = paths
+        .iter()
+        .zip(input)
+        .map(|(path, stack)| {
+            let unsigned: usize = path_multisigs(path)
+                .into_iter()
+                .map(|factor| match factor {
+                    Factor::MultiSigFactor { m, n, .. } => n.len().saturating_sub(*m as usize),
+                    Factor::SingleSigFactor { .. } => 0,
+                })
+                .sum();
+            witness_size(stack, &path.witness(stack), target) - unsigned * signature
+                + multisig_dummies(path, target, encoding).len()
+        })
+        .max()
+        .unwrap_or(0);
+    let scale = if *target == Target::Legacy { 4 } else { 1 };
+    (script.len() + witness) * scale
+}
+
+// Multisig checked on the path, in order.
+fn path_multisigs<'a>(path: &'a Path) -> Vec<&'a Factor> {
+    let mut found = vec![];
+    for step in &path.steps {
+        match step {
+            Step::Branch { condition, .. } => find_multisig(condition, &mut found),
+            Step::Statement(
+                Statement::VerifyStatement(_loc, expr) | Statement::ExpressionStatement(_loc, expr),
+            ) => find_multisig(expr, &mut found),
+            _ => (),
+        }
+    }
+    found
+}
+
+fn find_multisig<'a>(expression: &'a Expression, found: &mut Vec<&'a Factor>) {
+    if let Expression::CheckSigExpression { operand, .. } = expression {
+        if let Factor::MultiSigFactor { .. } = &**operand {
+            found.push(operand);
+        }
+    }
+    for sub in expression.sub_expressions() {
+        find_multisig(sub, found);
+    }
+}
+
+// Run the script from the input stack of `depth` items, taking OP_IF by `conditions` in order.
+// Condition is whether the block right after OP_IF or OP_NOTIF runs, as the source branch is taken.
+// Returns the depth of the stack at the end.
+pub fn simulate(script: &[u8], depth: usize, conditions: &[bool]) -> Result<usize, String> {
+    let mut stack: Vec<Option<i64>> = vec![None; depth];
+    let mut alt_stack: Vec<Option<i64>> = vec![];
+    // Whether each OP_IF block open is executed.
+    let mut executing: Vec<bool> = vec![];
+    let mut conditions = conditions.iter();
+
+    for instruction in bitcoin::Script::from_bytes(script).instructions() {
+        let instruction = instruction.map_err(|e| format!("Script can't be decoded: {}.", e))?;
+        let is_executed = executing.iter().all(|e| *e);
+        let op = match instruction {
+            Instruction::PushBytes(bytes) => {
+                if is_executed {
+                    stack.push(bitcoin::script::read_scriptint(bytes.as_bytes()).ok());
+                }
+                continue;
+            }
+            Instruction::Op(op) => op,
+        };
+
+        // Control flow is followed even in the block not executed.
+        match op {
+            OP_IF | OP_NOTIF => {
+                if is_executed {
+                    pop(&mut stack, 1, op)?;
+                    let taken = *conditions
+                        .next()
+                        .ok_or(format!("Path has no branch left for {}.", op))?;
+                    executing.push(taken);
+                } else {
+                    executing.push(false);
+                }
+                continue;
+            }
+            OP_ELSE => {
+                let last = executing
+                    .last_mut()
+                    .ok_or("OP_ELSE without OP_IF.".to_string())?;
+                *last = !*last;
+                continue;
+            }
+            OP_ENDIF => {
+                executing
+                    .pop()
+                    .ok_or("OP_ENDIF without OP_IF.".to_string())?;
+                continue;
+            }
+            _ if !is_executed => continue,
+            _ => (),
+        }
+
+        if let Class::PushNum(n) = op.classify(ClassifyContext::Legacy) {
+            stack.push(Some(n.into()));
+            continue;
+        }
+        match op {
+            OP_TOALTSTACK => {
+                let item = pop(&mut stack, 1, op)?;
+                alt_stack.extend(item);
+            }
+            OP_FROMALTSTACK => {
+                let item = pop(&mut alt_stack, 1, op)?;
+                stack.extend(item);
+            }
+            OP_DUP => {
+                let top = pop(&mut stack, 1, op)?;
+                stack.extend(top.iter().chain(top.iter()));
+            }
+            OP_SWAP => {
+                let items = pop(&mut stack, 2, op)?;
+                stack.extend([items[1], items[0]]);
+            }
+            OP_TUCK => {
+                let items = pop(&mut stack, 2, op)?;
+                stack.extend([items[1], items[0], items[1]]);
+            }
+            OP_ROT => {
+                let items = pop(&mut stack, 3, op)?;
+                stack.extend([items[1], items[2], items[0]]);
+            }
+            OP_SIZE => {
+                let top = pop(&mut stack, 1, op)?;
+                stack.extend(top);
+                stack.push(None);
+            }
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                let n = count(&mut stack, op)?;
+                pop(&mut stack, n, op)?;
+                let m = count(&mut stack, op)?;
+                // Signatures, and the dummy element popped by the off-by-one bug.
+                pop(&mut stack, m + 1, op)?;
+                if op == OP_CHECKMULTISIG {
+                    stack.push(None);
+                }
+            }
+            _ => {
+                let (pops, pushes) = stack_effect(op)
+                    .ok_or(format!("{} is not supported by stack simulation.", op))?;
+                pop(&mut stack, pops, op)?;
+                stack.extend(vec![None; pushes]);
+            }
+        }
+    }
+    Ok(stack.len())
+}
+
+// Items popped and pushed by the opcode, which leaves unknown value.
+fn stack_effect(op: bitcoin::Opcode) -> Option<(usize, usize)> {
+    match op {
+        OP_VERIFY | OP_DROP => Some((1, 0)),
+        OP_2DROP => Some((2, 0)),
+        OP_CLTV | OP_CSV => Some((1, 1)),
+        OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => Some((1, 1)),
+        OP_SHA256 | OP_RIPEMD160 | OP_HASH160 | OP_HASH256 | OP_SHA1 => Some((1, 1)),
+        OP_EQUAL
+        | OP_NUMEQUAL
+        | OP_NUMNOTEQUAL
+        | OP_LESSTHAN
+        | OP_GREATERTHAN
+        | OP_LESSTHANOREQUAL
+        | OP_GREATERTHANOREQUAL
+        | OP_BOOLAND
+        | OP_BOOLOR
+        | OP_ADD
+        | OP_SUB
+        | OP_MIN
+        | OP_MAX
+        | OP_CHECKSIG => Some((2, 1)),
+        OP_EQUALVERIFY | OP_NUMEQUALVERIFY | OP_CHECKSIGVERIFY => Some((2, 0)),
+        OP_WITHIN | OP_CHECKSIGADD => Some((3, 1)),
+        _ => None,
+    }
+}
+
+// Pop `n` items, the top at last.
+fn pop(
+    stack: &mut Vec<Option<i64>>,
+    n: usize,
+    op: bitcoin::Opcode,
+) -> Result<Vec<Option<i64>>, String> {
+    if stack.len() < n {
+        return Err(format!(
+            "Input stack has too few items for {}, which needs {} but: {} left.",
+            op,
+            n,
+            stack.len()
+        ));
+    }
+    Ok(stack.split_off(stack.len() - n))
+}
+
+// Count of keys or signatures pushed by the script.
+fn count(stack: &mut Vec<Option<i64>>, op: bitcoin::Opcode) -> Result<usize, String> {
+    match pop(stack, 1, op)?[0] {
+        Some(n) if n >= 0 => Ok(n as usize),
+        _ => Err(format!("Count of {} is not known at compile time.", op)),
+    }
+}